@@ -1,7 +1,12 @@
+use crate::leb;
 use crate::traits::ElementSize;
-use crate::vlu16n::Vlu16N;
+use crate::vlun::{Vlu16N, Vlu32N, Vlu64N};
+use crate::zigzag;
 use crate::Error::OutOfBoundsRev;
+#[cfg(feature = "error-context")]
+use crate::ErrorContext;
 use crate::{DeserializeShrinkWrap, Error};
+use core::cell::Cell;
 
 /// Buffer reader that treats input as a stream of nibbles.
 #[derive(Copy, Clone)]
@@ -14,6 +19,51 @@ pub struct BufReader<'i> {
     byte_idx: usize,
     // Next bit to read from
     bit_idx: u8,
+    // Expect 32 bit lengths and sizes instead of 16 bit ones.
+    large_payload: bool,
+    limits: Option<&'i ReadLimits>,
+    // Number of nested read() calls
+    depth: u16,
+    #[cfg(feature = "error-context")]
+    error_context: Option<&'i ErrorContext>,
+    // Position of buf in the outermost buffer, so that split readers report absolute positions
+    #[cfg(feature = "error-context")]
+    offset: usize,
+}
+
+/// Limits for reading untrusted input, shared between a reader and all the readers split from it.
+/// Exceeding any of them results in [Error::LimitExceeded].
+#[derive(Debug)]
+pub struct ReadLimits {
+    /// Maximum nesting depth of items read with [BufReader::read] or through the serde bridge.
+    pub max_depth: u16,
+    /// Maximum total number of vector elements, nested vectors are counted when their elements are iterated.
+    /// Vectors iterated several times are counted again each time.
+    pub max_elements: u32,
+    /// Maximum length of a string or a byte blob in bytes.
+    pub max_str_len: u32,
+    elements: Cell<u32>,
+}
+
+impl ReadLimits {
+    pub const fn new(max_depth: u16, max_elements: u32, max_str_len: u32) -> Self {
+        ReadLimits {
+            max_depth,
+            max_elements,
+            max_str_len,
+            elements: Cell::new(0),
+        }
+    }
+
+    /// Total number of vector elements read so far.
+    pub fn elements(&self) -> u32 {
+        self.elements.get()
+    }
+
+    /// Reset element counter, so that limits can be reused for the next message.
+    pub fn reset(&self) {
+        self.elements.set(0);
+    }
 }
 
 impl<'i> BufReader<'i> {
@@ -25,11 +75,59 @@ impl<'i> BufReader<'i> {
             is_at_bit7_rev: false,
             byte_idx: 0,
             bit_idx: 7,
+            large_payload: false,
+            limits: None,
+            depth: 0,
+            #[cfg(feature = "error-context")]
+            error_context: None,
+            #[cfg(feature = "error-context")]
+            offset: 0,
+        }
+    }
+
+    /// Create a reader that enforces provided limits, use for untrusted input.
+    pub fn new_with_limits(buf: &'i [u8], limits: &'i ReadLimits) -> Self {
+        BufReader {
+            limits: Some(limits),
+            ..Self::new(buf)
+        }
+    }
+
+    /// Record position and path of a failed item into provided context, shared with all the readers split from this one.
+    #[cfg(feature = "error-context")]
+    pub fn set_error_context(&mut self, error_context: &'i ErrorContext) {
+        self.error_context = Some(error_context);
+    }
+
+    /// Push name of an item that failed to deserialize into the error context, if one is set, and return the error back.
+    /// Used by generated code on the error path, does nothing without the `error-context` feature.
+    #[cfg_attr(not(feature = "error-context"), allow(unused_variables))]
+    #[inline]
+    pub fn push_error_path(&self, err: Error, name: &'static str) -> Error {
+        #[cfg(feature = "error-context")]
+        if let Some(error_context) = self.error_context {
+            error_context.push(self.offset + self.byte_idx, self.bit_idx, name);
         }
+        err
+    }
+
+    /// Account for `count` vector elements about to be read, returns an error if maximum element count is exceeded.
+    pub fn count_elements(&mut self, count: u32) -> Result<(), Error> {
+        let Some(limits) = self.limits else {
+            return Ok(());
+        };
+        let elements = limits
+            .elements
+            .get()
+            .checked_add(count)
+            .filter(|e| *e <= limits.max_elements)
+            .ok_or(Error::LimitExceeded)?;
+        limits.elements.set(elements);
+        Ok(())
     }
 
     pub fn read_bool(&mut self) -> Result<bool, Error> {
-        if (self.bytes_left() == 0) && self.bit_idx == 7 {
+        if !self.is_bit_available() {
             return Err(Error::OutOfBounds);
         }
         let val = (self.buf[self.byte_idx] & (1 << self.bit_idx)) != 0;
@@ -42,9 +140,29 @@ impl<'i> BufReader<'i> {
         Ok(val)
     }
 
+    /// Read `bits` (at most 64) wide number written with [BufWriter::write_un](crate::BufWriter::write_un).
+    pub fn read_un(&mut self, bits: u8) -> Result<u64, Error> {
+        debug_assert!(bits <= 64);
+        let mut val = 0;
+        for _ in 0..bits {
+            val = (val << 1) | self.read_bool()? as u64;
+        }
+        Ok(val)
+    }
+
+    /// Read `bits` wide two's complement number written with [BufWriter::write_in](crate::BufWriter::write_in).
+    pub fn read_in(&mut self, bits: u8) -> Result<i64, Error> {
+        let val = self.read_un(bits)?;
+        if bits == 0 {
+            return Ok(0);
+        }
+        let shift = 64 - bits;
+        Ok(((val << shift) as i64) >> shift)
+    }
+
     pub fn read_u4(&mut self) -> Result<u8, Error> {
         self.align_nibble();
-        if (self.bytes_left() == 0) && self.bit_idx == 7 {
+        if !self.is_bit_available() {
             return Err(Error::OutOfBounds);
         }
         if self.bit_idx == 7 {
@@ -84,6 +202,56 @@ impl<'i> BufReader<'i> {
         Ok(Vlu16N::read_reversed(self)?.0)
     }
 
+    /// Read length or size written with [BufWriter::write_size_rev](crate::BufWriter::write_size_rev).
+    pub fn read_size_rev(&mut self) -> Result<usize, Error> {
+        if self.large_payload {
+            Ok(self.read_vlu32n_rev()? as usize)
+        } else {
+            Ok(self.read_vlu16n_rev()? as usize)
+        }
+    }
+
+    /// Expect 32 bit lengths and sizes for the duration of `f`, used by types marked with `#[large_payload]`.
+    /// Previous mode is restored afterwards, so that nested types can use different mode.
+    pub fn with_large_payload<T>(
+        &mut self,
+        large_payload: bool,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let prev = core::mem::replace(&mut self.large_payload, large_payload);
+        let result = f(self);
+        self.large_payload = prev;
+        result
+    }
+
+    pub fn read_vlu32n(&mut self) -> Result<u32, Error> {
+        Ok(Vlu32N::read_forward(self)?.0)
+    }
+
+    pub fn read_vlu32n_rev(&mut self) -> Result<u32, Error> {
+        Ok(Vlu32N::read_reversed(self)?.0)
+    }
+
+    pub fn read_vlu64n(&mut self) -> Result<u64, Error> {
+        Ok(Vlu64N::read_forward(self)?.0)
+    }
+
+    pub fn read_vlu64n_rev(&mut self) -> Result<u64, Error> {
+        Ok(Vlu64N::read_reversed(self)?.0)
+    }
+
+    pub fn read_vli16n(&mut self) -> Result<i16, Error> {
+        Ok(zigzag::decode(Vlu16N::read_forward(self)?.0 as u128) as i16)
+    }
+
+    pub fn read_vli32n(&mut self) -> Result<i32, Error> {
+        Ok(zigzag::decode(Vlu32N::read_forward(self)?.0 as u128) as i32)
+    }
+
+    pub fn read_vli64n(&mut self) -> Result<i64, Error> {
+        Ok(zigzag::decode(Vlu64N::read_forward(self)?.0 as u128) as i64)
+    }
+
     pub fn read_u32(&mut self) -> Result<u32, Error> {
         let u32_bytes: [u8; 4] = self
             .read_slice(4)?
@@ -160,6 +328,38 @@ impl<'i> BufReader<'i> {
         Ok(f64::from_le_bytes(f64_bytes))
     }
 
+    pub fn read_leb_u16(&mut self) -> Result<u16, Error> {
+        Ok(leb::read_unsigned(self, 16)? as u16)
+    }
+
+    pub fn read_leb_u32(&mut self) -> Result<u32, Error> {
+        Ok(leb::read_unsigned(self, 32)? as u32)
+    }
+
+    pub fn read_leb_u64(&mut self) -> Result<u64, Error> {
+        Ok(leb::read_unsigned(self, 64)? as u64)
+    }
+
+    pub fn read_leb_u128(&mut self) -> Result<u128, Error> {
+        leb::read_unsigned(self, 128)
+    }
+
+    pub fn read_leb_i16(&mut self) -> Result<i16, Error> {
+        Ok(zigzag::decode(leb::read_unsigned(self, 16)?) as i16)
+    }
+
+    pub fn read_leb_i32(&mut self) -> Result<i32, Error> {
+        Ok(zigzag::decode(leb::read_unsigned(self, 32)?) as i32)
+    }
+
+    pub fn read_leb_i64(&mut self) -> Result<i64, Error> {
+        Ok(zigzag::decode(leb::read_unsigned(self, 64)?) as i64)
+    }
+
+    pub fn read_leb_i128(&mut self) -> Result<i128, Error> {
+        Ok(zigzag::decode(leb::read_unsigned(self, 128)?))
+    }
+
     pub fn read_slice(&mut self, len: usize) -> Result<&'i [u8], Error> {
         self.align_byte();
         if self.bytes_left() < len {
@@ -171,22 +371,66 @@ impl<'i> BufReader<'i> {
     }
 
     pub fn read_str(&mut self) -> Result<&'i str, Error> {
-        let len_bytes = self.read_vlu16n_rev()? as usize;
+        let len_bytes = self.read_len_limited()?;
         let str_bytes = self.read_slice(len_bytes)?;
         core::str::from_utf8(str_bytes).map_err(|_| Error::MalformedUtf8)
     }
 
+    /// Read bytes written with [BufWriter::write_bytes](crate::BufWriter::write_bytes) without copying.
+    pub fn read_bytes(&mut self) -> Result<&'i [u8], Error> {
+        let len = self.read_len_limited()?;
+        self.read_slice(len)
+    }
+
+    /// Read bytes, returning [Error::VecTooLong] if there are more than `max_len` of them.
+    pub fn read_bytes_max(&mut self, max_len: usize) -> Result<&'i [u8], Error> {
+        let len = self.read_len_limited()?;
+        if len > max_len {
+            return Err(Error::VecTooLong);
+        }
+        self.read_slice(len)
+    }
+
+    /// Read length of a string or bytes, checking it against [ReadLimits::max_str_len] if limits are set.
+    fn read_len_limited(&mut self) -> Result<usize, Error> {
+        let len = self.read_size_rev()?;
+        if let Some(limits) = self.limits {
+            if len > limits.max_str_len as usize {
+                return Err(Error::LimitExceeded);
+            }
+        }
+        Ok(len)
+    }
+
     pub fn read<T: DeserializeShrinkWrap<'i>>(
         &mut self,
         element_size: ElementSize,
     ) -> Result<T, Error> {
-        T::des_shrink_wrap(self, element_size)
+        self.enter()?;
+        let result = T::des_shrink_wrap(self, element_size);
+        self.leave();
+        result
     }
 
-    pub fn split(&mut self, len: usize) -> Result<Self, Error> {
-        if len > 0 {
-            self.align_byte();
+    /// Account for one more level of nesting, returns [Error::LimitExceeded] if [ReadLimits::max_depth] is reached.
+    /// Must be followed by [leave](Self::leave) once the nested item is read.
+    pub(crate) fn enter(&mut self) -> Result<(), Error> {
+        if let Some(limits) = self.limits {
+            if self.depth >= limits.max_depth {
+                return Err(Error::LimitExceeded);
+            }
         }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    pub fn split(&mut self, len: usize) -> Result<Self, Error> {
+        // BufWriter aligns to byte boundary before writing an unsized item, even if it ends up empty
+        self.align_byte();
         if self.bytes_left() < len {
             return Err(Error::OutOfBounds);
         }
@@ -198,6 +442,13 @@ impl<'i> BufReader<'i> {
             is_at_bit7_rev: false,
             byte_idx: 0,
             bit_idx: 7,
+            large_payload: self.large_payload,
+            limits: self.limits,
+            depth: self.depth,
+            #[cfg(feature = "error-context")]
+            error_context: self.error_context,
+            #[cfg(feature = "error-context")]
+            offset: self.offset + prev_byte_idx,
         })
     }
 
@@ -215,6 +466,17 @@ impl<'i> BufReader<'i> {
         }
     }
 
+    /// Last byte might be shared between forward data in the high nibble and reversed data in the low nibble.
+    fn is_bit_available(&self) -> bool {
+        if self.byte_idx >= self.len_bytes {
+            return false;
+        }
+        if self.is_at_bit7_rev && self.byte_idx == self.len_bytes - 1 {
+            return self.bit_idx >= 4;
+        }
+        true
+    }
+
     fn align_nibble(&mut self) {
         if self.bit_idx == 7 || self.bit_idx == 3 {
             return;
@@ -235,6 +497,20 @@ impl<'i> BufReader<'i> {
         self.byte_idx += 1;
     }
 
+    /// Next byte and bit (7 is MSB) to read from.
+    pub fn pos(&self) -> (usize, u8) {
+        (self.byte_idx, self.bit_idx)
+    }
+
+    /// Byte and bit position right after the reversed data that was not read yet.
+    pub fn rev_pos(&self) -> (usize, u8) {
+        if self.is_at_bit7_rev {
+            (self.len_bytes - 1, 3)
+        } else {
+            (self.len_bytes, 7)
+        }
+    }
+
     pub fn bytes_left(&mut self) -> usize {
         if self.byte_idx >= self.len_bytes {
             return 0;
@@ -257,7 +533,7 @@ impl<'i> BufReader<'i> {
 
 #[cfg(test)]
 mod tests {
-    use crate::BufReader;
+    use crate::{BufReader, BufWriter, ElementSize, ElementSizeOf, Error, ReadLimits, RefVec};
 
     #[test]
     fn bytes() {
@@ -271,6 +547,22 @@ mod tests {
         assert_eq!(rd.bytes_left(), 0);
     }
 
+    #[test]
+    fn positions() {
+        let buf = [0b1010_0000, 0xAA, 0x21];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.rev_pos(), (3, 7));
+        assert_eq!(rd.read_vlu16n_rev(), Ok(1));
+        assert_eq!(rd.rev_pos(), (2, 3));
+        assert_eq!(rd.read_vlu16n_rev(), Ok(2));
+        assert_eq!(rd.rev_pos(), (2, 7));
+        rd.read_bool().unwrap();
+        rd.read_bool().unwrap();
+        assert_eq!(rd.pos(), (0, 5));
+        rd.read_u8().unwrap();
+        assert_eq!(rd.pos(), (2, 7));
+    }
+
     #[test]
     fn float() {
         let buf = [0, 0, 0x80, 0x3E];
@@ -289,4 +581,102 @@ mod tests {
         let _ = rd.read_vlu16n_rev().unwrap();
         assert_eq!(rd.bytes_left(), 0);
     }
+
+    #[test]
+    fn limits_str_len() {
+        let mut buf = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_str("abcd").unwrap();
+        let buf = wr.finish().unwrap();
+
+        let limits = ReadLimits::new(8, 8, 3);
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        assert_eq!(rd.read_str(), Err(Error::LimitExceeded));
+        let limits = ReadLimits::new(8, 8, 4);
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        assert_eq!(rd.read_str(), Ok("abcd"));
+    }
+
+    #[test]
+    fn limits_bytes_len() {
+        let mut buf = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_bytes(&[1, 2, 3, 4]).unwrap();
+        wr.write_bytes(&[5, 6, 7, 8]).unwrap();
+        let buf = wr.finish().unwrap();
+        let limits = ReadLimits::new(8, 8, 3);
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        assert_eq!(rd.read_bytes(), Err(Error::LimitExceeded));
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        assert_eq!(rd.read_bytes_max(16), Err(Error::LimitExceeded));
+        let limits = ReadLimits::new(8, 8, 4);
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        assert_eq!(rd.read_bytes(), Ok(&[1, 2, 3, 4][..]));
+        assert_eq!(rd.read_bytes_max(4), Ok(&[5, 6, 7, 8][..]));
+    }
+
+    #[test]
+    fn limits_elements() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&RefVec::from(&[1u8, 2, 3][..])).unwrap();
+        wr.write(&RefVec::from(&[4u8, 5][..])).unwrap();
+        let buf = wr.finish().unwrap();
+
+        let limits = ReadLimits::new(8, 4, 8);
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        let _a: RefVec<'_, u8> = rd.read(u8::ELEMENT_SIZE).unwrap();
+        assert_eq!(limits.elements(), 3);
+        let b: Result<RefVec<'_, u8>, _> = rd.read(u8::ELEMENT_SIZE);
+        assert!(matches!(b, Err(Error::LimitExceeded)));
+
+        limits.reset();
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        let _a: RefVec<'_, u8> = rd.read(u8::ELEMENT_SIZE).unwrap();
+        assert_eq!(limits.elements(), 3);
+    }
+
+    #[test]
+    fn limits_depth() {
+        let inner = [RefVec::from(&[1u8][..])];
+        let outer = [RefVec::from(&inner[..])];
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&RefVec::from(&outer[..])).unwrap();
+        let buf = wr.finish().unwrap();
+
+        type Nested<'i> = RefVec<'i, RefVec<'i, RefVec<'i, u8>>>;
+        // unsized elements are skipped over by their sizes, nested vectors are only read when iterated
+        fn innermost(nested: Nested<'_>) -> Result<RefVec<'_, u8>, Error> {
+            let middle = nested.des_iter().unwrap().next().unwrap()?;
+            middle.des_iter().unwrap().next().unwrap()
+        }
+        let limits = ReadLimits::new(2, 100, 8);
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        let nested: Nested = rd.read(ElementSize::Implied).unwrap();
+        assert!(matches!(innermost(nested), Err(Error::LimitExceeded)));
+
+        let limits = ReadLimits::new(3, 100, 8);
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        let nested: Nested = rd.read(ElementSize::Implied).unwrap();
+        assert_eq!(innermost(nested).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn limits_nested_elements_counted_once() {
+        let inner = [RefVec::from(&[1u8, 2][..]), RefVec::from(&[3u8, 4][..])];
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&RefVec::from(&inner[..])).unwrap();
+        let buf = wr.finish().unwrap();
+
+        let limits = ReadLimits::new(8, 6, 8);
+        let mut rd = BufReader::new_with_limits(buf, &limits);
+        let outer: RefVec<'_, RefVec<'_, u8>> = rd.read(ElementSize::Implied).unwrap();
+        assert_eq!(limits.elements(), 2);
+        for inner in outer.des_iter().unwrap() {
+            assert_eq!(inner.unwrap().len(), 2);
+        }
+        assert_eq!(limits.elements(), 6);
+    }
 }
@@ -1,5 +1,12 @@
-use crate::vlu16n::Vlu16N;
+use crate::fragment::{Fragment, FragmentSink};
+use crate::leb;
+use crate::vlun::{Vlu16N, Vlu32N, Vlu64N};
+use crate::zigzag;
 use crate::{Error, SerializeShrinkWrap};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// no_std buffer writer that supports 1 bit, 4 bit, variable length integer and other operations.
 /// No alignment requirements are imposed on the byte buffer provided.
@@ -11,35 +18,123 @@ use crate::{Error, SerializeShrinkWrap};
 /// let mut wr = shrink_wrap::BufWriter::new(&mut buf);
 /// wr.write_bool(true).unwrap();
 /// wr.write_u8(0xaa).unwrap();
-/// assert_eq!(wr.finish(), &[0x80, 0xaa]);
+/// assert_eq!(wr.finish().unwrap(), &[0x80, 0xaa]);
 /// ```
 pub struct BufWriter<'i> {
-    buf: &'i mut [u8],
+    buf: Buf<'i>,
     // Next byte to write to
     byte_idx: usize,
     // Next bit to write to
     bit_idx: u8,
     // Buffer length from the front, shrinks when write_u16_rev() is used.
     len_bytes: usize,
+    // Use 32 bit lengths and sizes instead of 16 bit ones.
+    large_payload: bool,
+    // Drop trailing fields with default values that do not fit instead of failing.
+    truncate_evolvable: bool,
+    // At least one field was dropped.
+    truncated: bool,
+    // Unique id, identifies slots and checkpoints made by this writer
+    id: usize,
+    // Forward slots discarded by rollback()
+    slots: SlotGuard,
+    // Reversed slots discarded by rollback() and encode_vlu16n_rev()
+    rev_slots: SlotGuard,
+    // Position of the last reserved u16 slot that was not patched yet, or a position above all of them
+    rev_pending_top: usize,
+    // Bytes already sent out by a fragmented writer, byte_idx is relative to them
+    flushed: usize,
 }
 
+/// Values that do not fit into u16 take three u16 slots in the reversed area: this marker, low and high half.
+const U16_REV_ESCAPE: u16 = u16::MAX;
+
+/// Virtual buffer length of a size counter, large enough to never run out, small enough to never overflow.
+const COUNTER_LEN: usize = usize::MAX >> 2;
+
+static NEXT_WRITER_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Unique writer id, so that slots cannot be patched by another writer, even if it uses the same buffer.
+fn next_writer_id() -> usize {
+    #[cfg(target_has_atomic = "ptr")]
+    {
+        NEXT_WRITER_ID.fetch_add(1, Ordering::Relaxed)
+    }
+    // no compare-and-swap on e.g. thumbv6m, writer can be created from an interrupt in between load and store
+    #[cfg(all(not(target_has_atomic = "ptr"), feature = "critical-section"))]
+    critical_section::with(|_| {
+        let id = NEXT_WRITER_ID.load(Ordering::Relaxed);
+        NEXT_WRITER_ID.store(id.wrapping_add(1), Ordering::Relaxed);
+        id
+    })
+}
+
+#[cfg(all(not(target_has_atomic = "ptr"), not(feature = "critical-section")))]
+compile_error!("targets without atomic compare-and-swap need the critical-section feature for unique writer ids");
+
 impl<'i> BufWriter<'i> {
     pub fn new(buf: &'i mut [u8]) -> Self {
         let len_bytes = buf.len();
+        Self::with_buf(Buf::Slice(buf), len_bytes)
+    }
+
+    fn with_buf(buf: Buf<'i>, len_bytes: usize) -> Self {
         Self {
             buf,
             len_bytes,
             byte_idx: 0,
             bit_idx: 7,
+            large_payload: false,
+            truncate_evolvable: false,
+            truncated: false,
+            id: next_writer_id(),
+            slots: SlotGuard::new(),
+            rev_slots: SlotGuard::new(),
+            rev_pending_top: 0,
+            flushed: 0,
         }
     }
 
+    /// Create a writer that does not store anything, but tracks positions exactly as a normal writer would,
+    /// use [finish_size](Self::finish_size) to get the resulting message length.
+    /// See also [SerializeShrinkWrap::encoded_size].
+    pub fn new_size_counter() -> BufWriter<'static> {
+        BufWriter::with_buf(Buf::Counter { rev_nibbles: 0 }, COUNTER_LEN)
+    }
+
+    /// Create a writer that sends the message out in fragments of at most `mtu` bytes while it is being written,
+    /// so that `scratch` only has to hold one fragment and the reversed area, instead of the whole message.
+    /// Nested items are compacted as soon as they are written, only the sizes of the top level items
+    /// stay in the reversed area until [finish_size](Self::finish_size) sends them out with the last fragments.
+    ///
+    /// Fragments are the same as the ones produced by [Fragmenter](crate::fragment::Fragmenter) from a finished message
+    /// and are reassembled with [Reassembler](crate::fragment::Reassembler).
+    /// Bytes that were already sent out cannot be patched or rolled back anymore.
+    /// Writer must not be used after `send` returns an error.
+    pub fn new_fragmented(
+        scratch: &'i mut [u8],
+        mtu: usize,
+        send: &'i mut dyn FnMut(Fragment<'_>) -> Result<(), Error>,
+    ) -> Result<Self, Error> {
+        let sink = FragmentSink::new(mtu, send)?;
+        let len_bytes = scratch.len();
+        Ok(Self::with_buf(
+            Buf::Stream { buf: scratch, sink },
+            len_bytes,
+        ))
+    }
+
     pub fn write_bool(&mut self, val: bool) -> Result<(), Error> {
+        if self.bit_idx == 7 {
+            self.reserve(1)?;
+        }
         if (self.bytes_left() == 0) && self.bit_idx == 7 {
             return Err(Error::OutOfBounds);
         }
-        self.buf[self.byte_idx] &= !(1 << self.bit_idx);
-        self.buf[self.byte_idx] |= (val as u8) << self.bit_idx;
+        if let Some(byte) = self.buf.get_mut(self.byte_idx) {
+            *byte &= !(1 << self.bit_idx);
+            *byte |= (val as u8) << self.bit_idx;
+        }
         if self.bit_idx == 0 {
             self.bit_idx = 7;
             self.byte_idx += 1;
@@ -49,18 +144,59 @@ impl<'i> BufWriter<'i> {
         Ok(())
     }
 
+    /// Write lowest `bits` (at most 64) of `val`, most significant first, packed together with bools without alignment.
+    /// Returns [Error::OutOfRange] if `val` does not fit into `bits`.
+    pub fn write_un(&mut self, val: u64, bits: u8) -> Result<(), Error> {
+        debug_assert!(bits <= 64);
+        if bits < 64 && val >> bits != 0 {
+            return Err(Error::OutOfRange);
+        }
+        for i in (0..bits).rev() {
+            self.write_bool(val & (1 << i) != 0)?;
+        }
+        Ok(())
+    }
+
+    /// Write `val` as a `bits` wide two's complement number, see [write_un](Self::write_un).
+    pub fn write_in(&mut self, val: i64, bits: u8) -> Result<(), Error> {
+        debug_assert!(bits <= 64);
+        if bits == 0 {
+            return if val == 0 {
+                Ok(())
+            } else {
+                Err(Error::OutOfRange)
+            };
+        }
+        if bits < 64 {
+            let half = 1i64 << (bits - 1);
+            if val < -half || val >= half {
+                return Err(Error::OutOfRange);
+            }
+        }
+        let mask = u64::MAX >> (64 - bits);
+        self.write_un(val as u64 & mask, bits)
+    }
+
     pub fn write_u4(&mut self, val: u8) -> Result<(), Error> {
         self.align_nibble();
+        if self.bit_idx == 7 {
+            self.reserve(1)?;
+        }
         if (self.bytes_left() == 0) && self.bit_idx == 7 {
             return Err(Error::OutOfBounds);
         }
+        let byte = self.buf.get_mut(self.byte_idx);
         if self.bit_idx == 7 {
-            self.buf[self.byte_idx] &= 0b0000_1111;
-            self.buf[self.byte_idx] |= val << 4;
+            if let Some(byte) = byte {
+                *byte &= 0b0000_1111;
+                *byte |= val << 4;
+            }
             self.bit_idx = 3;
         } else {
-            self.buf[self.byte_idx] &= 0b1111_0000;
-            self.buf[self.byte_idx] |= val & 0b0000_1111;
+            if let Some(byte) = byte {
+                *byte &= 0b1111_0000;
+                *byte |= val & 0b0000_1111;
+            }
             self.bit_idx = 7;
             self.byte_idx += 1;
         }
@@ -69,10 +205,13 @@ impl<'i> BufWriter<'i> {
 
     pub fn write_u8(&mut self, val: u8) -> Result<(), Error> {
         self.align_byte();
+        self.reserve(1)?;
         if self.bytes_left() == 0 {
             return Err(Error::OutOfBounds);
         }
-        self.buf[self.byte_idx] = val;
+        if let Some(byte) = self.buf.get_mut(self.byte_idx) {
+            *byte = val;
+        }
         self.byte_idx += 1;
         Ok(())
     }
@@ -83,6 +222,55 @@ impl<'i> BufWriter<'i> {
     }
 
     pub fn write_u16_rev(&mut self, val: u16) -> Result<(), Error> {
+        self.write_u32_rev(val as u32)
+    }
+
+    pub fn write_u32_rev(&mut self, val: u32) -> Result<(), Error> {
+        if let Buf::Counter { rev_nibbles } = &mut self.buf {
+            // only the encoded length matters, values themselves are not needed
+            *rev_nibbles += Vlu32N(val).len_nibbles();
+            self.len_bytes -= if val < U16_REV_ESCAPE as u32 { 2 } else { 6 };
+            return Ok(());
+        }
+        if val < U16_REV_ESCAPE as u32 {
+            return self.push_u16_rev(val as u16);
+        }
+        self.reserve(6)?;
+        if self.bytes_left() < 6 {
+            return Err(Error::OutOfBoundsRev);
+        }
+        self.push_u16_rev((val >> 16) as u16)?;
+        self.push_u16_rev(val as u16)?;
+        self.push_u16_rev(U16_REV_ESCAPE)
+    }
+
+    /// Write length or size to the reversed area, using 16 or 32 bits depending on large payload mode.
+    /// `too_long` is returned if the value does not fit.
+    pub fn write_size_rev(&mut self, size: usize, too_long: Error) -> Result<(), Error> {
+        if self.large_payload {
+            let size = u32::try_from(size).map_err(|_| too_long)?;
+            self.write_u32_rev(size)
+        } else {
+            let size = u16::try_from(size).map_err(|_| too_long)?;
+            self.write_u16_rev(size)
+        }
+    }
+
+    /// Switch to 32 bit lengths and sizes for the duration of `f`, used by types marked with `#[large_payload]`.
+    /// Previous mode is restored afterwards, so that nested types can use different mode.
+    pub fn with_large_payload<T>(
+        &mut self,
+        large_payload: bool,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let prev = core::mem::replace(&mut self.large_payload, large_payload);
+        let result = f(self);
+        self.large_payload = prev;
+        result
+    }
+
+    fn push_u16_rev(&mut self, val: u16) -> Result<(), Error> {
+        self.reserve(2)?;
         if self.bytes_left() < 2 {
             return Err(Error::OutOfBoundsRev);
         }
@@ -95,18 +283,160 @@ impl<'i> BufWriter<'i> {
     }
 
     pub fn u16_rev_pos(&self) -> U16RevPos {
-        U16RevPos(self.len_bytes)
+        let rev_nibbles = match self.buf {
+            Buf::Counter { rev_nibbles } => rev_nibbles,
+            _ => 0,
+        };
+        U16RevPos(self.buf_len() - self.len_bytes, rev_nibbles)
+    }
+
+    /// Instead of failing with [Error::OutOfBounds], drop trailing struct fields that have a default value
+    /// together with all the fields after them, reader will use defaults in their place.
+    pub fn set_truncate_evolvable(&mut self, enabled: bool) {
+        self.truncate_evolvable = enabled;
+    }
+
+    /// Whether any fields were dropped due to [set_truncate_evolvable](Self::set_truncate_evolvable).
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Write trailing field that has a default value, used by generated code.
+    /// Returns false if the field did not fit and was dropped, remaining fields must not be written then.
+    pub fn write_evolvable(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<bool, Error> {
+        if !self.truncate_evolvable {
+            f(self)?;
+            return Ok(true);
+        }
+        let checkpoint = self.checkpoint();
+        match f(self) {
+            Ok(()) => Ok(true),
+            Err(Error::OutOfBounds | Error::OutOfBoundsRev | Error::OutOfBoundsRevCompact) => {
+                self.rollback(checkpoint)?;
+                self.truncated = true;
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remember current position, so that everything written afterwards can be discarded with [rollback](Self::rollback).
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            writer_id: self.id,
+            byte_idx: self.flushed + self.byte_idx,
+            bit_idx: self.bit_idx,
+            rev_pos: self.u16_rev_pos(),
+        }
     }
 
-    // pub fn update_u16_rev(&mut self, pos: U16RevPos, val: u16) -> Result<(), Error> {
-    //     if pos.0 + 1 >= self.buf.len() {
-    //         return Err(Error::OutOfBoundsRev);
-    //     }
-    //     let val_be = val.to_le_bytes();
-    //     self.buf[pos.0] = val_be[0];
-    //     self.buf[pos.0 + 1] = val_be[1];
-    //     Ok(())
-    // }
+    /// Restore forward position and reversed area to the state at `checkpoint`, for example to drop a partially written
+    /// optional field after [Error::OutOfBounds]. Slots reserved after the checkpoint can no longer be patched.
+    /// Returns [Error::InvalidCheckpoint] if the checkpoint was made by another writer, is ahead of the current position
+    /// or bytes after it were already sent out by a [fragmented](Self::new_fragmented) writer.
+    /// Reversed values written before the checkpoint must not be encoded in between.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) -> Result<(), Error> {
+        let byte_idx = self.flushed + self.byte_idx;
+        let is_ahead = (checkpoint.byte_idx, 7 - checkpoint.bit_idx) > (byte_idx, 7 - self.bit_idx);
+        if checkpoint.writer_id != self.id || is_ahead || checkpoint.byte_idx < self.flushed {
+            return Err(Error::InvalidCheckpoint);
+        }
+        if checkpoint.byte_idx < byte_idx {
+            self.slots.discard_above(checkpoint.byte_idx);
+        }
+        self.discard_rev_slots(checkpoint.rev_pos.0);
+        self.byte_idx = checkpoint.byte_idx - self.flushed;
+        self.bit_idx = checkpoint.bit_idx;
+        self.len_bytes = self.buf_len() - checkpoint.rev_pos.0;
+        if let Buf::Counter { rev_nibbles } = &mut self.buf {
+            *rev_nibbles = checkpoint.rev_pos.1;
+        }
+        Ok(())
+    }
+
+    /// Reserve `len` zeroed bytes at the next byte boundary to be filled in later with [patch_slot](Self::patch_slot),
+    /// for example with a checksum or a number of items written in a loop.
+    pub fn reserve_slot(&mut self, len: usize) -> Result<Slot, Error> {
+        self.align_byte();
+        let byte_idx = self.flushed + self.byte_idx;
+        for _ in 0..len {
+            self.write_u8(0)?;
+        }
+        Ok(Slot {
+            writer_id: self.id,
+            generation: self.slots.generation,
+            byte_idx,
+            len,
+        })
+    }
+
+    /// Fill in previously reserved slot, `val` must be exactly as long as the slot.
+    /// Returns [Error::InvalidSlot] if the slot was reserved by another writer, discarded by [rollback](Self::rollback)
+    /// or already sent out by a [fragmented](Self::new_fragmented) writer.
+    pub fn patch_slot(&mut self, slot: Slot, val: &[u8]) -> Result<(), Error> {
+        let end = slot.byte_idx + slot.len;
+        if slot.writer_id != self.id
+            || slot.byte_idx < self.flushed
+            || end > self.flushed + self.byte_idx
+            || !self.slots.is_valid(slot.generation, end)
+        {
+            return Err(Error::InvalidSlot);
+        }
+        if val.len() != slot.len {
+            return Err(Error::OutOfRange);
+        }
+        let idx = slot.byte_idx - self.flushed;
+        if let Some(bytes) = self.buf.get_mut(idx..idx + slot.len) {
+            bytes.copy_from_slice(val);
+        }
+        Ok(())
+    }
+
+    /// Reserve u16 slot in the reversed area to be filled in later with [patch_u16_rev](Self::patch_u16_rev),
+    /// for example with a length known only at the end.
+    /// Slot must be patched before the reversed area containing it is encoded with [encode_vlu16n_rev](Self::encode_vlu16n_rev).
+    pub fn reserve_u16_rev(&mut self) -> Result<U16RevSlot, Error> {
+        self.write_u16_rev(0)?;
+        let pos = self.u16_rev_pos().0;
+        let below = core::mem::replace(&mut self.rev_pending_top, pos);
+        Ok(U16RevSlot {
+            writer_id: self.id,
+            generation: self.rev_slots.generation,
+            pos,
+            below,
+        })
+    }
+
+    /// Fill in previously reserved reversed slot, `val` must be less than `u16::MAX`.
+    /// Returns [Error::InvalidSlot] if the slot was reserved by another writer, was already encoded
+    /// or discarded by [rollback](Self::rollback).
+    ///
+    /// Size counter accounts for the patched value in all the enclosing items,
+    /// so the slot must not be patched from inside an item started after reserving it.
+    pub fn patch_u16_rev(&mut self, slot: U16RevSlot, val: u16) -> Result<(), Error> {
+        if slot.writer_id != self.id
+            || slot.pos > self.u16_rev_pos().0
+            || !self.rev_slots.is_valid(slot.generation, slot.pos)
+        {
+            return Err(Error::InvalidSlot);
+        }
+        if val == U16_REV_ESCAPE {
+            return Err(Error::OutOfRange);
+        }
+        if slot.pos == self.rev_pending_top {
+            self.rev_pending_top = slot.below;
+        }
+        if let Buf::Counter { rev_nibbles } = &mut self.buf {
+            *rev_nibbles += Vlu32N(val as u32).len_nibbles() - Vlu32N(0).len_nibbles();
+            return Ok(());
+        }
+        let idx = self.buf.len() - slot.pos;
+        self.buf[idx..idx + 2].copy_from_slice(&val.to_le_bytes());
+        Ok(())
+    }
 
     pub fn write_u32(&mut self, val: u32) -> Result<(), Error> {
         self.write_slice(&val.to_le_bytes())?;
@@ -161,42 +491,130 @@ impl<'i> BufWriter<'i> {
         Vlu16N(val).write_forward(self)
     }
 
-    pub fn write_slice(&mut self, val: &[u8]) -> Result<(), Error> {
+    pub fn write_vlu32n(&mut self, val: u32) -> Result<(), Error> {
+        Vlu32N(val).write_forward(self)
+    }
+
+    pub fn write_vlu64n(&mut self, val: u64) -> Result<(), Error> {
+        Vlu64N(val).write_forward(self)
+    }
+
+    pub fn write_vli16n(&mut self, val: i16) -> Result<(), Error> {
+        Vlu16N(zigzag::encode(val as i128, 16) as u16).write_forward(self)
+    }
+
+    pub fn write_vli32n(&mut self, val: i32) -> Result<(), Error> {
+        Vlu32N(zigzag::encode(val as i128, 32) as u32).write_forward(self)
+    }
+
+    pub fn write_vli64n(&mut self, val: i64) -> Result<(), Error> {
+        Vlu64N(zigzag::encode(val as i128, 64) as u64).write_forward(self)
+    }
+
+    pub fn write_leb_u16(&mut self, val: u16) -> Result<(), Error> {
+        leb::write_unsigned(self, val as u128)
+    }
+
+    pub fn write_leb_u32(&mut self, val: u32) -> Result<(), Error> {
+        leb::write_unsigned(self, val as u128)
+    }
+
+    pub fn write_leb_u64(&mut self, val: u64) -> Result<(), Error> {
+        leb::write_unsigned(self, val as u128)
+    }
+
+    pub fn write_leb_u128(&mut self, val: u128) -> Result<(), Error> {
+        leb::write_unsigned(self, val)
+    }
+
+    pub fn write_leb_i16(&mut self, val: i16) -> Result<(), Error> {
+        leb::write_unsigned(self, zigzag::encode(val as i128, 16))
+    }
+
+    pub fn write_leb_i32(&mut self, val: i32) -> Result<(), Error> {
+        leb::write_unsigned(self, zigzag::encode(val as i128, 32))
+    }
+
+    pub fn write_leb_i64(&mut self, val: i64) -> Result<(), Error> {
+        leb::write_unsigned(self, zigzag::encode(val as i128, 64))
+    }
+
+    pub fn write_leb_i128(&mut self, val: i128) -> Result<(), Error> {
+        leb::write_unsigned(self, zigzag::encode(val, 128))
+    }
+
+    pub fn write_slice(&mut self, mut val: &[u8]) -> Result<(), Error> {
         self.align_byte();
+        self.reserve(val.len())?;
+        // fragmented writer sends out what was written, so slices longer than the buffer are written in parts
+        while matches!(self.buf, Buf::Stream { .. }) && self.bytes_left() < val.len() {
+            let (head, tail) = val.split_at(self.bytes_left());
+            if head.is_empty() {
+                break;
+            }
+            self.copy_slice(head);
+            val = tail;
+            self.reserve(val.len())?;
+        }
         if self.bytes_left() < val.len() {
             return Err(Error::OutOfBoundsRev);
         }
-        self.buf[self.byte_idx..self.byte_idx + val.len()].copy_from_slice(val);
-        self.byte_idx += val.len();
+        self.copy_slice(val);
         Ok(())
     }
 
+    fn copy_slice(&mut self, val: &[u8]) {
+        if let Some(bytes) = self.buf.get_mut(self.byte_idx..self.byte_idx + val.len()) {
+            bytes.copy_from_slice(val);
+        }
+        self.byte_idx += val.len();
+    }
+
     pub fn write_str(&mut self, val: &str) -> Result<(), Error> {
-        let len = u16::try_from(val.len()).map_err(|_| Error::StrTooLong)?;
-        self.write_u16_rev(len)?;
+        self.write_size_rev(val.len(), Error::StrTooLong)?;
         self.write_slice(val.as_bytes())
     }
 
+    /// Write bytes as is, with length in the reversed area, same wire format as `RefVec<'_, u8>`.
+    pub fn write_bytes(&mut self, val: &[u8]) -> Result<(), Error> {
+        self.write_size_rev(val.len(), Error::VecTooLong)?;
+        self.write_slice(val)
+    }
+
+    /// Write bytes, returning [Error::VecTooLong] if there are more than `max_len` of them.
+    pub fn write_bytes_max(&mut self, val: &[u8], max_len: usize) -> Result<(), Error> {
+        if val.len() > max_len {
+            return Err(Error::VecTooLong);
+        }
+        self.write_bytes(val)
+    }
+
     pub fn write<T: SerializeShrinkWrap>(&mut self, val: &T) -> Result<(), Error> {
         val.ser_shrink_wrap(self)
     }
 
+    /// Encode reversed values written between `from` and `to` positions as reversed Vlu16N's.
+    /// Values larger than u16 are encoded as Vlu32N's, which is the same format, just longer.
+    /// Positions can be provided in any order, one of them must be the current position.
     pub fn encode_vlu16n_rev(&mut self, from: U16RevPos, to: U16RevPos) -> Result<(), Error> {
-        if to.0 < from.0 {
-            return Ok(());
-        }
-        let reverse_u16_written = (to.0 - from.0) / 2;
-        // dbg!(reverse_u16_written);
-        if reverse_u16_written == 0 {
+        debug_assert_eq!(from.0.max(to.0), self.u16_rev_pos().0);
+        let rev_bytes = from.0.abs_diff(to.0);
+        if rev_bytes == 0 {
             return Ok(());
         }
+        self.discard_rev_slots(from.0.min(to.0));
         let mut total_nibbles = 0;
-        let mut idx = from.0;
-        for _ in 0..reverse_u16_written {
-            let val = u16::from_le_bytes([self.buf[idx], self.buf[idx + 1]]);
-            total_nibbles += Vlu16N(val).len_nibbles();
-            idx += 2;
+        if let Buf::Counter { rev_nibbles } = self.buf {
+            total_nibbles = rev_nibbles - from.1.min(to.1);
+        } else {
+            let mut idx = self.len_bytes;
+            while idx < self.len_bytes + rev_bytes {
+                let (val, len) = self.u32_rev_at(idx);
+                total_nibbles += Vlu32N(val).len_nibbles();
+                idx += len;
+            }
         }
+        let value_nibbles = total_nibbles;
         self.align_nibble();
         let not_at_byte_boundary = self.bit_idx != 7;
         if not_at_byte_boundary {
@@ -207,26 +625,128 @@ impl<'i> BufWriter<'i> {
             self.write_u4(0).map_err(|_| Error::OutOfBoundsRevCompact)?;
         }
 
-        let mut idx = self.len_bytes;
-        for _ in 0..reverse_u16_written {
-            let val = u16::from_le_bytes([self.buf[idx], self.buf[idx + 1]]);
-            self.len_bytes += 2;
-            Vlu16N(val).write_reversed(self)?;
-            idx += 2;
+        if let Buf::Counter { rev_nibbles } = &mut self.buf {
+            *rev_nibbles -= value_nibbles;
+            self.len_bytes += rev_bytes;
+            for _ in 0..value_nibbles {
+                self.write_u4(0)?;
+            }
+            return Ok(());
+        }
+        let mut encoded_bytes = 0;
+        while encoded_bytes < rev_bytes {
+            // read from len_bytes on each iteration, owned buffer might grow and move reversed area
+            let (val, len) = self.u32_rev_at(self.len_bytes);
+            self.len_bytes += len;
+            encoded_bytes += len;
+            Vlu32N(val).write_reversed(self)?;
         }
         debug_assert!(self.bit_idx == 7);
         Ok(())
     }
 
-    pub fn finish(mut self) -> Result<&'i [u8], Error> {
-        let reverse_u16_written = (self.buf.len() - self.len_bytes) / 2;
-        self.encode_vlu16n_rev(U16RevPos(self.len_bytes), U16RevPos(self.buf.len()))?;
-        if reverse_u16_written != 0 {
-            Ok(&self.buf[0..self.byte_idx])
+    /// Reversed area is about to shrink to `pos`, invalidate pending slots above it.
+    /// Slots that were already patched cannot be used again, so they are not tracked.
+    fn discard_rev_slots(&mut self, pos: usize) {
+        if pos < self.rev_pending_top {
+            self.rev_slots.discard_above(pos);
+            self.rev_pending_top = pos;
+        }
+    }
+
+    /// Value written with write_u32_rev() starting at `idx` and the number of bytes it occupies.
+    fn u32_rev_at(&self, idx: usize) -> (u32, usize) {
+        let slot = |idx: usize| u16::from_le_bytes([self.buf[idx], self.buf[idx + 1]]);
+        let val = slot(idx);
+        if val == U16_REV_ESCAPE {
+            let val = (slot(idx + 4) as u32) << 16 | slot(idx + 2) as u32;
+            (val, 6)
         } else {
+            (val as u32, 2)
+        }
+    }
+
+    /// Encode the reversed area and return the resulting message.
+    /// Returns [Error::WrongWriterKind] for a [fragmented](Self::new_fragmented) writer, use [finish_size](Self::finish_size) for it.
+    pub fn finish(mut self) -> Result<&'i [u8], Error> {
+        if let Buf::Stream { .. } = self.buf {
+            return Err(Error::WrongWriterKind);
+        }
+        let len = self.compact()?;
+        match self.buf {
+            Buf::Slice(buf) => Ok(&buf[0..len]),
+            Buf::Counter { .. } => Ok(&[]),
+            Buf::Stream { .. } => Err(Error::WrongWriterKind),
+            #[cfg(feature = "alloc")]
+            Buf::Vec(_) => Err(Error::WrongWriterKind),
+        }
+    }
+
+    /// Same as [finish](Self::finish), but only returns the resulting message length,
+    /// the only way to get the result out of a [size counter](Self::new_size_counter).
+    /// [Fragmented](Self::new_fragmented) writer sends out the rest of the message as the last fragments.
+    pub fn finish_size(mut self) -> Result<usize, Error> {
+        self.compact()
+    }
+
+    /// Encode all the remaining reversed values and return resulting message length.
+    fn compact(&mut self) -> Result<usize, Error> {
+        let rev_bytes = self.buf_len() - self.len_bytes;
+        self.encode_vlu16n_rev(self.u16_rev_pos(), U16RevPos(0, 0))?;
+        if rev_bytes == 0 {
             self.align_byte();
-            Ok(&self.buf[0..self.byte_idx])
         }
+        self.send_fragments(true)?;
+        Ok(self.flushed + self.byte_idx)
+    }
+
+    /// Make room if less than `bytes` are left: grow owned buffer, moving reversed area to the new end,
+    /// or send out written bytes of a fragmented writer. Does nothing for borrowed buffers.
+    #[inline]
+    fn reserve(&mut self, bytes: usize) -> Result<(), Error> {
+        let bytes_left = self.bytes_left();
+        if bytes_left >= bytes {
+            return Ok(());
+        }
+        match &mut self.buf {
+            #[cfg(feature = "alloc")]
+            Buf::Vec(vec) => {
+                let old_len = vec.len();
+                let rev_len = old_len - self.len_bytes;
+                let new_len = (old_len * 2).max(old_len + bytes - bytes_left);
+                vec.resize(new_len, 0);
+                vec.copy_within(self.len_bytes..old_len, new_len - rev_len);
+                self.len_bytes = new_len - rev_len;
+                Ok(())
+            }
+            Buf::Stream { .. } => self.send_fragments(false),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send out written bytes of a fragmented writer as full fragments, and the rest of them as well if `is_last`.
+    /// Bytes that are not sent yet, including the one currently being written to, are moved to the start of the buffer.
+    /// A full fragment is only sent once more bytes follow it, so that the last fragment is never empty,
+    /// same as with [Fragmenter](crate::fragment::Fragmenter).
+    fn send_fragments(&mut self, is_last: bool) -> Result<(), Error> {
+        let Buf::Stream { buf, sink } = &mut self.buf else {
+            return Ok(());
+        };
+        let payload_len = sink.payload_len();
+        let mut sent = 0;
+        while self.byte_idx - sent > payload_len {
+            sink.send(&buf[sent..sent + payload_len], false)?;
+            sent += payload_len;
+        }
+        if is_last {
+            sink.send(&buf[sent..self.byte_idx], true)?;
+            sent = self.byte_idx;
+        }
+        let keep_end = self.byte_idx + (self.bit_idx != 7) as usize;
+        buf.copy_within(sent..keep_end, 0);
+        self.byte_idx -= sent;
+        self.flushed += sent;
+        Ok(())
     }
 
     fn align_nibble(&mut self) {
@@ -234,7 +754,9 @@ impl<'i> BufWriter<'i> {
             return;
         }
         if self.bit_idx > 3 {
-            self.buf[self.byte_idx] &= !(0xFF >> (7 - self.bit_idx));
+            if let Some(byte) = self.buf.get_mut(self.byte_idx) {
+                *byte &= !(0xFF >> (7 - self.bit_idx));
+            }
             self.bit_idx = 3;
         } else {
             self.bit_idx = 7;
@@ -246,7 +768,9 @@ impl<'i> BufWriter<'i> {
         if self.bit_idx == 7 {
             return;
         }
-        self.buf[self.byte_idx] &= !(0xFF >> (7 - self.bit_idx));
+        if let Some(byte) = self.buf.get_mut(self.byte_idx) {
+            *byte &= !(0xFF >> (7 - self.bit_idx));
+        }
         self.bit_idx = 7;
         self.byte_idx += 1;
     }
@@ -262,17 +786,190 @@ impl<'i> BufWriter<'i> {
         }
     }
 
+    /// Position from the start of the message, including bytes already sent out by a [fragmented](Self::new_fragmented) writer.
     pub fn pos(&self) -> (usize, u8) {
-        (self.byte_idx, self.bit_idx)
+        (self.flushed + self.byte_idx, self.bit_idx)
+    }
+
+    fn buf_len(&self) -> usize {
+        match self.buf {
+            Buf::Counter { .. } => COUNTER_LEN,
+            _ => self.buf.len(),
+        }
     }
 }
 
+/// Position in the reversed u16 area, stored as an offset from the end of the buffer,
+/// so that it stays valid when owned buffer grows.
+/// Size counter also stores the number of nibbles all the reversed values up to this position will take.
+#[derive(Debug, Copy, Clone)]
+pub struct U16RevPos(usize, usize);
+
+/// Writer position saved with [BufWriter::checkpoint].
 #[derive(Debug, Copy, Clone)]
-pub struct U16RevPos(usize);
+pub struct Checkpoint {
+    writer_id: usize,
+    byte_idx: usize,
+    bit_idx: u8,
+    rev_pos: U16RevPos,
+}
+
+/// Bytes reserved with [BufWriter::reserve_slot], can only be patched once and only by the same writer.
+#[derive(Debug)]
+pub struct Slot {
+    writer_id: usize,
+    generation: usize,
+    byte_idx: usize,
+    len: usize,
+}
+
+/// u16 slot reserved with [BufWriter::reserve_u16_rev], can only be patched once and only by the same writer.
+/// Stored as an offset from the end of the buffer, same as [U16RevPos].
+#[derive(Debug)]
+pub struct U16RevSlot {
+    writer_id: usize,
+    generation: usize,
+    pos: usize,
+    // Pending slot position at the time of reservation, restored when this one is patched
+    below: usize,
+}
+
+/// Tracks which slots were discarded without storing them: each discard starts a new generation.
+/// Slots from older generations are only accepted if they end below every discarded position,
+/// which is stricter than necessary, but never lets a discarded slot through.
+#[derive(Copy, Clone)]
+struct SlotGuard {
+    generation: usize,
+    floor: usize,
+}
+
+impl SlotGuard {
+    const fn new() -> Self {
+        SlotGuard {
+            generation: 0,
+            floor: usize::MAX,
+        }
+    }
+
+    /// Slots ending after `pos` were discarded.
+    fn discard_above(&mut self, pos: usize) {
+        self.generation = self.generation.wrapping_add(1);
+        self.floor = self.floor.min(pos);
+    }
+
+    fn is_valid(&self, generation: usize, end: usize) -> bool {
+        generation == self.generation || end <= self.floor
+    }
+}
+
+enum Buf<'i> {
+    Slice(&'i mut [u8]),
+    /// Nothing is stored, only the number of nibbles reversed values will take when encoded is tracked.
+    Counter {
+        rev_nibbles: usize,
+    },
+    /// Holds only the part of the message that was not sent out yet and the reversed area.
+    Stream {
+        buf: &'i mut [u8],
+        sink: FragmentSink<'i>,
+    },
+    #[cfg(feature = "alloc")]
+    Vec(Vec<u8>),
+}
+
+impl<'i> Deref for Buf<'i> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Buf::Slice(buf) => buf,
+            Buf::Counter { .. } => &[],
+            Buf::Stream { buf, .. } => buf,
+            #[cfg(feature = "alloc")]
+            Buf::Vec(vec) => vec.as_slice(),
+        }
+    }
+}
+
+impl<'i> DerefMut for Buf<'i> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Buf::Slice(buf) => buf,
+            Buf::Counter { .. } => &mut [],
+            Buf::Stream { buf, .. } => buf,
+            #[cfg(feature = "alloc")]
+            Buf::Vec(vec) => vec.as_mut_slice(),
+        }
+    }
+}
+
+/// Buffer writer backed by a Vec<u8> that grows on demand, for std and alloc targets.
+/// Derefs to BufWriter, so all the same write operations are available.
+///
+/// # Example
+/// ```
+/// let mut wr = shrink_wrap::BufWriterOwned::new();
+/// wr.write_bool(true).unwrap();
+/// wr.write_u8(0xaa).unwrap();
+/// assert_eq!(wr.finish().unwrap(), vec![0x80, 0xaa]);
+/// ```
+#[cfg(feature = "alloc")]
+pub struct BufWriterOwned {
+    wr: BufWriter<'static>,
+}
+
+#[cfg(feature = "alloc")]
+impl BufWriterOwned {
+    pub fn new() -> Self {
+        Self::with_capacity(64)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let vec = alloc::vec![0; capacity.max(1)];
+        let len_bytes = vec.len();
+        BufWriterOwned {
+            wr: BufWriter::with_buf(Buf::Vec(vec), len_bytes),
+        }
+    }
+
+    /// Same as BufWriter::finish(), but returns the buffer truncated to the message length.
+    /// Returns [Error::WrongWriterKind] if the inner writer was replaced through `DerefMut`.
+    pub fn finish(mut self) -> Result<Vec<u8>, Error> {
+        let len = self.wr.compact()?;
+        let Buf::Vec(mut vec) = self.wr.buf else {
+            return Err(Error::WrongWriterKind);
+        };
+        vec.truncate(len);
+        Ok(vec)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Default for BufWriterOwned {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Deref for BufWriterOwned {
+    type Target = BufWriter<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.wr
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl DerefMut for BufWriterOwned {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.wr
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::BufWriter;
+    use crate::{BufReader, BufWriter, Error};
 
     #[test]
     fn finish_zeroes_reserved_bits() {
@@ -314,6 +1011,64 @@ mod tests {
         assert_eq!(wr.finish().unwrap(), &[0b10101100]);
     }
 
+    #[test]
+    fn arbitrary_width() {
+        let mut buf = [0xFF; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_bool(true).unwrap();
+        wr.write_un(0xABC, 12).unwrap();
+        wr.write_in(-3, 3).unwrap();
+        assert_eq!(wr.write_un(8, 3), Err(Error::OutOfRange));
+        assert_eq!(wr.write_in(4, 3), Err(Error::OutOfRange));
+        assert_eq!(wr.write_in(-5, 3), Err(Error::OutOfRange));
+        wr.write_un(u64::MAX, 64).unwrap();
+        wr.write_in(i64::MIN, 64).unwrap();
+        let buf = wr.finish().unwrap();
+        assert_eq!(&buf[..2], &[0b1101_0101, 0b1110_0101]);
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read_bool(), Ok(true));
+        assert_eq!(rd.read_un(12), Ok(0xABC));
+        assert_eq!(rd.read_in(3), Ok(-3));
+        assert_eq!(rd.read_un(64), Ok(u64::MAX));
+        assert_eq!(rd.read_in(64), Ok(i64::MIN));
+    }
+
+    #[test]
+    fn size_counter_matches() {
+        extern crate std;
+        use std::string::String;
+        let long = String::from_iter(core::iter::repeat_n('a', 70_000));
+        // sizes and lengths take 1 to 3 u16 slots in large payload mode
+        let write_all = |wr: &mut BufWriter, strings: &[&str]| {
+            wr.with_large_payload(true, |wr| {
+                for (i, s) in strings.iter().enumerate() {
+                    wr.write_bool(i % 2 == 0)?;
+                    wr.write_u4(i as u8)?;
+                    let rev_from = wr.u16_rev_pos();
+                    let start = wr.pos().0;
+                    wr.write_un(5, 3)?;
+                    wr.write_str(s)?;
+                    wr.encode_vlu16n_rev(rev_from, wr.u16_rev_pos())?;
+                    wr.write_size_rev(wr.pos().0 - start, Error::ItemTooLong)?;
+                }
+                wr.write_vlu32n(1 << 20)
+            })
+            .unwrap();
+        };
+        let cases: [&[&str]; 4] = [&[], &["a"], &["abc", "", "de"], &[&long, "x"]];
+        for strings in cases {
+            let mut buf = [0u8; 80_000];
+            let mut wr = BufWriter::new(&mut buf);
+            write_all(&mut wr, strings);
+            let len = wr.finish().unwrap().len();
+
+            let mut wr = BufWriter::new_size_counter();
+            write_all(&mut wr, strings);
+            assert_eq!(wr.finish_size(), Ok(len));
+        }
+    }
+
     #[test]
     fn rev_u16_aligned() {
         let mut buf = [0; 6];
@@ -323,7 +1078,7 @@ mod tests {
         wr.write_u16_rev(3).unwrap();
         wr.write_u16_rev(5).unwrap();
         assert_eq!(wr.bytes_left(), 0);
-        assert_eq!(&wr.buf, &[0xAA, 0xCC, 5, 0, 3, 0]);
+        assert_eq!(&wr.buf[..], &[0xAA, 0xCC, 5, 0, 3, 0]);
         assert_eq!(wr.finish().unwrap(), &[0xAA, 0xCC, 0b0101_0011]);
     }
 
@@ -337,7 +1092,7 @@ mod tests {
         wr.write_u16_rev(5).unwrap();
         wr.write_u16_rev(7).unwrap();
         assert_eq!(wr.bytes_left(), 1);
-        assert_eq!(&wr.buf, &[0xAA, 0xCC, 0, 7, 0, 5, 0, 3, 0]);
+        assert_eq!(&wr.buf[..], &[0xAA, 0xCC, 0, 7, 0, 5, 0, 3, 0]);
         assert_eq!(
             wr.finish().unwrap(),
             &[0xAA, 0xCC, 0b0000_0111, 0b0101_0011]
@@ -352,4 +1107,297 @@ mod tests {
         wr.write_u16_rev(5).unwrap();
         assert_eq!(wr.finish().unwrap(), &[0x25]);
     }
+
+    #[test]
+    fn rev_u32_escaped() {
+        let mut buf = [0; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_u8(0xAA).unwrap();
+        wr.write_u32_rev(0x1_2345).unwrap();
+        wr.write_u16_rev(0xFFFF).unwrap();
+        wr.write_u16_rev(3).unwrap();
+        assert_eq!(wr.bytes_left(), 1);
+        let buf = wr.finish().unwrap();
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read_u8(), Ok(0xAA));
+        assert_eq!(rd.read_vlu32n_rev(), Ok(0x1_2345));
+        assert_eq!(rd.read_vlu16n_rev(), Ok(0xFFFF));
+        assert_eq!(rd.read_vlu16n_rev(), Ok(3));
+    }
+
+    #[test]
+    fn rev_u32_matches_u16() {
+        let mut buf = [0; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_u32_rev(700).unwrap();
+        wr.write_u32_rev(5).unwrap();
+        let mut buf_u16 = [0; 16];
+        let mut wr_u16 = BufWriter::new(&mut buf_u16);
+        wr_u16.write_u16_rev(700).unwrap();
+        wr_u16.write_u16_rev(5).unwrap();
+        assert_eq!(wr.finish(), wr_u16.finish());
+    }
+
+    #[test]
+    fn large_str() {
+        let s = [b'a'; 70_000];
+        let s = core::str::from_utf8(&s).unwrap();
+        let mut buf = [0; 70_016];
+        let mut wr = BufWriter::new(&mut buf);
+        assert_eq!(wr.write_str(s), Err(Error::StrTooLong));
+        wr.with_large_payload(true, |wr| wr.write_str(s)).unwrap();
+        assert_eq!(
+            wr.write_size_rev(70_000, Error::ItemTooLong),
+            Err(Error::ItemTooLong)
+        );
+        let buf = wr.finish().unwrap();
+        assert_eq!(buf.len(), 70_003);
+
+        let mut rd = BufReader::new(buf);
+        let s_read = rd.with_large_payload(true, |rd| rd.read_str()).unwrap();
+        assert!(s_read == s);
+        assert_eq!(rd.read_size_rev(), Err(Error::OutOfBoundsRev));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn owned_grows() {
+        let mut wr = crate::BufWriterOwned::with_capacity(1);
+        for i in 0..100 {
+            wr.write_u8(i).unwrap();
+        }
+        let buf = wr.finish().unwrap();
+        assert_eq!(buf.len(), 100);
+        assert!(buf.iter().enumerate().all(|(i, b)| i == *b as usize));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn owned_swapped_out() {
+        let mut owned = crate::BufWriterOwned::new();
+        let inner = core::mem::replace(&mut *owned, BufWriter::new_size_counter());
+        assert_eq!(inner.finish(), Err(Error::WrongWriterKind));
+        assert_eq!(owned.finish(), Err(Error::WrongWriterKind));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn owned_rev_u16_matches_slice() {
+        fn write(wr: &mut BufWriter) {
+            wr.write_bool(true).unwrap();
+            wr.write_u16_rev(3).unwrap();
+            wr.write_u4(0xA).unwrap();
+            wr.write_u16_rev(700).unwrap();
+            for i in 0..50 {
+                wr.write_u8(i).unwrap();
+            }
+            wr.write_u16_rev(5).unwrap();
+        }
+        let mut buf = [0u8; 128];
+        let mut wr = BufWriter::new(&mut buf);
+        write(&mut wr);
+        let expected = wr.finish().unwrap();
+
+        let mut wr = crate::BufWriterOwned::with_capacity(2);
+        write(&mut wr);
+        assert_eq!(wr.finish().unwrap(), expected);
+    }
+
+    #[test]
+    fn patch_slots() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_bool(true).unwrap();
+        let count = wr.reserve_slot(2).unwrap();
+        let len = wr.reserve_u16_rev().unwrap();
+        for i in 0..3 {
+            wr.write_u8(i).unwrap();
+        }
+        wr.patch_slot(count, &3u16.to_le_bytes()).unwrap();
+        wr.patch_u16_rev(len, 700).unwrap();
+        let patched = wr.finish().unwrap();
+
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_bool(true).unwrap();
+        wr.write_u16(3).unwrap();
+        wr.write_u16_rev(700).unwrap();
+        for i in 0..3 {
+            wr.write_u8(i).unwrap();
+        }
+        assert_eq!(patched, wr.finish().unwrap());
+    }
+
+    #[test]
+    fn patch_slots_misuse() {
+        let mut buf_a = [0u8; 64];
+        let mut buf_b = [0u8; 64];
+        let mut wr_a = BufWriter::new(&mut buf_a);
+        let mut wr_b = BufWriter::new(&mut buf_b);
+        let slot = wr_a.reserve_slot(1).unwrap();
+        assert_eq!(wr_b.patch_slot(slot, &[1]), Err(Error::InvalidSlot));
+        let slot = wr_a.reserve_slot(2).unwrap();
+        assert_eq!(wr_a.patch_slot(slot, &[1]), Err(Error::OutOfRange));
+
+        let from = wr_a.u16_rev_pos();
+        let slot = wr_a.reserve_u16_rev().unwrap();
+        let to = wr_a.u16_rev_pos();
+        wr_a.encode_vlu16n_rev(from, to).unwrap();
+        assert_eq!(wr_a.patch_u16_rev(slot, 1), Err(Error::InvalidSlot));
+    }
+
+    #[test]
+    fn patch_slots_stale() {
+        let mut buf = [0u8; 8];
+        let mut wr = BufWriter::new(&mut buf);
+        let stale = wr.reserve_slot(2).unwrap();
+        wr.finish().unwrap();
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_u32(0xAABBCCDD).unwrap();
+        assert_eq!(wr.patch_slot(stale, &[0x11, 0x22]), Err(Error::InvalidSlot));
+        assert_eq!(wr.finish().unwrap(), &[0xDD, 0xCC, 0xBB, 0xAA]);
+
+        let mut buf = [0u8; 8];
+        let mut wr = BufWriter::new(&mut buf);
+        let from = wr.u16_rev_pos();
+        let stale = wr.reserve_u16_rev().unwrap();
+        wr.encode_vlu16n_rev(from, wr.u16_rev_pos()).unwrap();
+        wr.write_str("abc").unwrap();
+        assert_eq!(wr.patch_u16_rev(stale, 7), Err(Error::InvalidSlot));
+        let mut rd = BufReader::new(wr.finish().unwrap());
+        assert_eq!(rd.read_u4(), Ok(0));
+        assert_eq!(rd.read_str(), Ok("abc"));
+
+        let mut wr = BufWriter::new_size_counter();
+        let checkpoint = wr.checkpoint();
+        let stale = wr.reserve_u16_rev().unwrap();
+        wr.rollback(checkpoint).unwrap();
+        wr.write_u16_rev(1).unwrap();
+        assert_eq!(wr.patch_u16_rev(stale, 1000), Err(Error::InvalidSlot));
+
+        let mut wr = BufWriter::new(&mut buf);
+        let checkpoint = wr.checkpoint();
+        let stale = wr.reserve_slot(1).unwrap();
+        wr.rollback(checkpoint).unwrap();
+        wr.write_u8(0xAA).unwrap();
+        assert_eq!(wr.patch_slot(stale, &[0]), Err(Error::InvalidSlot));
+    }
+
+    #[test]
+    fn patch_slots_around_nested() {
+        let mut buf = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        let count = wr.reserve_slot(1).unwrap();
+        let len = wr.reserve_u16_rev().unwrap();
+        // nested item encodes its own reversed area, including a slot of its own
+        let from = wr.u16_rev_pos();
+        let inner = wr.reserve_u16_rev().unwrap();
+        wr.write_u8(0xAA).unwrap();
+        wr.patch_u16_rev(inner, 2).unwrap();
+        wr.encode_vlu16n_rev(from, wr.u16_rev_pos()).unwrap();
+        // optional field rolled back together with a slot it reserved
+        let checkpoint = wr.checkpoint();
+        let _dropped = wr.reserve_u16_rev().unwrap();
+        let _dropped_fwd = wr.reserve_slot(1).unwrap();
+        wr.rollback(checkpoint).unwrap();
+        wr.patch_slot(count, &[1]).unwrap();
+        wr.patch_u16_rev(len, 3).unwrap();
+        let patched = wr.finish().unwrap();
+
+        let mut buf = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_u8(1).unwrap();
+        wr.write_u16_rev(3).unwrap();
+        let from = wr.u16_rev_pos();
+        wr.write_u16_rev(2).unwrap();
+        wr.write_u8(0xAA).unwrap();
+        wr.encode_vlu16n_rev(from, wr.u16_rev_pos()).unwrap();
+        assert_eq!(patched, wr.finish().unwrap());
+    }
+
+    #[test]
+    fn patch_slots_size_counter() {
+        fn write(wr: &mut BufWriter) -> Result<(), Error> {
+            let len = wr.reserve_u16_rev()?;
+            wr.write_u8(0xAA)?;
+            wr.patch_u16_rev(len, 1000)
+        }
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        write(&mut wr).unwrap();
+        let expected = wr.finish().unwrap().len();
+
+        let mut wr = BufWriter::new_size_counter();
+        write(&mut wr).unwrap();
+        assert_eq!(wr.finish_size(), Ok(expected));
+
+        #[cfg(feature = "alloc")]
+        {
+            let mut wr = crate::BufWriterOwned::with_capacity(0);
+            write(&mut wr).unwrap();
+            assert_eq!(wr.finish().unwrap().len(), expected);
+        }
+    }
+
+    #[test]
+    fn rollback_optional_tail() {
+        fn write_head(wr: &mut BufWriter) {
+            wr.write_bool(true).unwrap();
+            wr.write_u16_rev(3).unwrap();
+            wr.write_u8(0xAA).unwrap();
+        }
+        let mut buf = [0u8; 8];
+        let mut wr = BufWriter::new(&mut buf);
+        write_head(&mut wr);
+        let checkpoint = wr.checkpoint();
+        wr.write_bool(true).unwrap();
+        wr.write_u16_rev(5).unwrap();
+        assert_eq!(wr.write_str("too long"), Err(Error::OutOfBoundsRev));
+        wr.rollback(checkpoint).unwrap();
+        let rolled_back = wr.finish().unwrap();
+
+        let mut buf = [0u8; 8];
+        let mut wr = BufWriter::new(&mut buf);
+        write_head(&mut wr);
+        assert_eq!(rolled_back, wr.finish().unwrap());
+    }
+
+    #[test]
+    fn rollback_misuse() {
+        let mut buf = [0u8; 8];
+        let mut wr = BufWriter::new(&mut buf);
+        let start = wr.checkpoint();
+        wr.write_u8(1).unwrap();
+        let checkpoint = wr.checkpoint();
+        let slot = wr.reserve_slot(1).unwrap();
+        wr.rollback(start).unwrap();
+        assert_eq!(wr.rollback(checkpoint), Err(Error::InvalidCheckpoint));
+        assert_eq!(wr.patch_slot(slot, &[0]), Err(Error::InvalidSlot));
+
+        let mut counter = BufWriter::new_size_counter();
+        counter.write_u8(1).unwrap();
+        let checkpoint = counter.checkpoint();
+        counter.write_u16_rev(1000).unwrap();
+        counter.rollback(checkpoint).unwrap();
+        assert_eq!(counter.finish_size(), Ok(1));
+    }
+
+    #[test]
+    fn truncate_evolvable() {
+        let mut buf = [0u8; 4];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_u16(0xAABB).unwrap();
+        assert!(wr.write_evolvable(|wr| wr.write_u32(0)).is_err());
+
+        let mut buf = [0u8; 4];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.set_truncate_evolvable(true);
+        wr.write_u16(0xAABB).unwrap();
+        assert_eq!(wr.write_evolvable(|wr| wr.write_u8(1)), Ok(true));
+        assert!(!wr.is_truncated());
+        assert_eq!(wr.write_evolvable(|wr| wr.write_u32(0)), Ok(false));
+        assert!(wr.is_truncated());
+        assert_eq!(wr.finish().unwrap(), &[0xBB, 0xAA, 1]);
+    }
 }
@@ -0,0 +1,134 @@
+use core::cell::Cell;
+use core::fmt::{Display, Formatter};
+
+/// Position and path of the item that failed to deserialize, shared between a reader and all the readers split from it.
+///
+/// Generated code pushes struct, variant and field names on the error path only, innermost first.
+/// Position is recorded when the first name is pushed and is relative to the start of the outermost buffer.
+/// Call [reset](ErrorContext::reset) before reusing it for the next message.
+#[derive(Debug)]
+pub struct ErrorContext {
+    byte_idx: Cell<usize>,
+    bit_idx: Cell<u8>,
+    path: Cell<[&'static str; ErrorContext::MAX_PATH]>,
+    // Number of pushed names, can be larger than MAX_PATH
+    pushed: Cell<usize>,
+}
+
+impl ErrorContext {
+    /// Number of innermost names that are kept, outer ones are dropped.
+    pub const MAX_PATH: usize = 8;
+
+    pub const fn new() -> Self {
+        ErrorContext {
+            byte_idx: Cell::new(0),
+            bit_idx: Cell::new(7),
+            path: Cell::new([""; ErrorContext::MAX_PATH]),
+            pushed: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn push(&self, byte_idx: usize, bit_idx: u8, name: &'static str) {
+        let pushed = self.pushed.get();
+        if pushed == 0 {
+            self.byte_idx.set(byte_idx);
+            self.bit_idx.set(bit_idx);
+        }
+        if pushed < Self::MAX_PATH {
+            let mut path = self.path.get();
+            path[pushed] = name;
+            self.path.set(path);
+        }
+        self.pushed.set(pushed.saturating_add(1));
+    }
+
+    /// Byte and bit (7 is MSB) position of the innermost failed item, if an error was recorded.
+    pub fn position(&self) -> Option<(usize, u8)> {
+        if self.pushed.get() == 0 {
+            None
+        } else {
+            Some((self.byte_idx.get(), self.bit_idx.get()))
+        }
+    }
+
+    /// Names of items that were being read, outermost first.
+    pub fn path(&self) -> impl DoubleEndedIterator<Item = &'static str> {
+        let len = self.pushed.get().min(Self::MAX_PATH);
+        self.path.get().into_iter().take(len).rev()
+    }
+
+    /// Whether outermost names were dropped due to nesting deeper than [MAX_PATH](ErrorContext::MAX_PATH).
+    pub fn is_truncated(&self) -> bool {
+        self.pushed.get() > Self::MAX_PATH
+    }
+
+    pub fn reset(&self) {
+        self.pushed.set(0);
+    }
+}
+
+impl Default for ErrorContext {
+    fn default() -> Self {
+        ErrorContext::new()
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let Some((byte_idx, bit_idx)) = self.position() else {
+            return write!(f, "no error context");
+        };
+        if self.is_truncated() {
+            write!(f, "..")?;
+        }
+        for (i, name) in self.path().enumerate() {
+            if i != 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{name}")?;
+        }
+        write!(f, " at byte {byte_idx} bit {bit_idx}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::{BufReader, Error, ErrorContext};
+    use std::format;
+
+    #[test]
+    fn path_and_position() {
+        let ctx = ErrorContext::new();
+        assert_eq!(ctx.position(), None);
+        let buf = [0xAB, 0xCD];
+        let mut rd = BufReader::new(&buf);
+        rd.set_error_context(&ctx);
+        rd.read_u8().unwrap();
+        let mut rd_split = rd.split(1).unwrap();
+        rd_split.read_u4().unwrap();
+        let e = rd_split.read_u8().unwrap_err();
+        let e = rd_split.push_error_path(e, "x");
+        let e = rd.push_error_path(e, "Inner");
+        let e = rd.push_error_path(e, "inner");
+        let e = rd.push_error_path(e, "Outer");
+        assert_eq!(e, Error::OutOfBounds);
+        // u8 is byte aligned, so the failed read is at the start of the next byte
+        assert_eq!(ctx.position(), Some((2, 7)));
+        assert_eq!(format!("{ctx}"), "Outer.inner.Inner.x at byte 2 bit 7");
+
+        ctx.reset();
+        assert_eq!(ctx.position(), None);
+        assert_eq!(ctx.path().count(), 0);
+    }
+
+    #[test]
+    fn truncated() {
+        let ctx = ErrorContext::new();
+        for name in ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"] {
+            ctx.push(0, 7, name);
+        }
+        assert!(ctx.is_truncated());
+        assert_eq!(format!("{ctx}"), "..h.g.f.e.d.c.b.a at byte 0 bit 7");
+    }
+}
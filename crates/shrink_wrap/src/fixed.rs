@@ -0,0 +1,135 @@
+use crate::traits::ElementSize;
+use crate::{
+    BufReader, BufWriter, DeserializeShrinkWrap, ElementSizeOf, Error, SerializeShrinkWrap,
+};
+use core::fmt::{Debug, Display, Formatter};
+
+/// Q-format fixed-point number stored as `T` with `FRAC` fractional bits, e.g. `Fixed<i16, 15>` for q15.
+/// Value is `self.0 / 2^FRAC`, arithmetic is left to the integer itself, so that no FPU is needed on a device.
+/// Serialized as the underlying integer.
+///
+/// `FRAC` must not exceed the number of bits in `T`, conversions fail to compile otherwise:
+/// ```compile_fail
+/// let _ = shrink_wrap::Fixed::<i16, 40>::from_f32(1.0);
+/// ```
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed<T, const FRAC: u8>(pub T);
+
+impl<T, const FRAC: u8> Fixed<T, FRAC> {
+    pub const fn from_bits(bits: T) -> Self {
+        Fixed(bits)
+    }
+
+    pub fn to_bits(self) -> T {
+        self.0
+    }
+}
+
+macro_rules! impl_fixed {
+    ($($ty:ty),*) => {
+        $(
+        impl<const FRAC: u8> Fixed<$ty, FRAC> {
+            // Evaluated when a conversion is used, same check as for fixed types in a .ww file.
+            const SCALE: f64 = {
+                assert!(FRAC as u32 <= <$ty>::BITS, "FRAC must not exceed the number of bits");
+                (1u128 << FRAC) as f64
+            };
+            pub const MIN: Self = Fixed(<$ty>::MIN);
+            pub const MAX: Self = Fixed(<$ty>::MAX);
+
+            /// Nearest representable value, rounded half away from zero.
+            /// Out of range values saturate to MIN or MAX, NaN is converted to 0.
+            pub fn from_f64(val: f64) -> Self {
+                let scaled = val * Self::SCALE;
+                let rounded = if scaled < 0.0 { scaled - 0.5 } else { scaled + 0.5 };
+                Fixed(rounded as $ty)
+            }
+
+            pub fn to_f64(self) -> f64 {
+                self.0 as f64 / Self::SCALE
+            }
+
+            pub fn from_f32(val: f32) -> Self {
+                Self::from_f64(val as f64)
+            }
+
+            pub fn to_f32(self) -> f32 {
+                self.to_f64() as f32
+            }
+        }
+
+        impl<const FRAC: u8> Debug for Fixed<$ty, FRAC> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+                f.debug_tuple("Fixed").field(&self.to_f64()).finish()
+            }
+        }
+
+        impl<const FRAC: u8> Display for Fixed<$ty, FRAC> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+                write!(f, "{}", self.to_f64())
+            }
+        }
+        )*
+    };
+}
+
+impl_fixed!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl<T: ElementSizeOf, const FRAC: u8> ElementSizeOf for Fixed<T, FRAC> {
+    const ELEMENT_SIZE: ElementSize = T::ELEMENT_SIZE;
+}
+
+impl<T: SerializeShrinkWrap, const FRAC: u8> SerializeShrinkWrap for Fixed<T, FRAC> {
+    fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
+        self.0.ser_shrink_wrap(wr)
+    }
+}
+
+impl<'i, T: DeserializeShrinkWrap<'i>, const FRAC: u8> DeserializeShrinkWrap<'i>
+    for Fixed<T, FRAC>
+{
+    fn des_shrink_wrap<'di>(
+        rd: &'di mut BufReader<'i>,
+        element_size: ElementSize,
+    ) -> Result<Self, Error> {
+        Ok(Fixed(T::des_shrink_wrap(rd, element_size)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Q15 = Fixed<i16, 15>;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Q15::from_f32(0.5), Fixed(0x4000));
+        assert_eq!(Q15::from_f32(-1.0), Q15::MIN);
+        assert_eq!(Q15::from_f32(1.0), Q15::MAX);
+        assert_eq!(Q15::from_f32(f32::NAN), Fixed(0));
+        assert_eq!(Q15::from_f32(-0.25).to_f32(), -0.25);
+        // 1.5 LSB rounds away from zero
+        assert_eq!(Q15::from_f64(1.5 / 32768.0), Fixed(2));
+        assert_eq!(Q15::from_f64(-1.5 / 32768.0), Fixed(-2));
+
+        let x = Fixed::<u32, 16>::from_f32(123.456);
+        assert_eq!(x.to_bits() >> 16, 123);
+        assert!((x.to_f32() - 123.456).abs() <= 0.5 / 65536.0);
+        assert_eq!(Fixed::<u32, 16>::from_f32(-1.0), Fixed(0));
+        assert_eq!(Fixed::<i64, 63>::from_f64(-1.0), Fixed::<i64, 63>::MIN);
+    }
+
+    #[test]
+    fn same_as_underlying_integer() {
+        let mut buf = [0u8; 8];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&Q15::from_f32(0.5)).unwrap();
+        let bytes = wr.finish().unwrap();
+        assert_eq!(bytes, &[0x00, 0x40]);
+        let mut rd = BufReader::new(bytes);
+        let x: Q15 = rd.read(ElementSize::Implied).unwrap();
+        assert_eq!(x.to_f32(), 0.5);
+        assert_eq!(Q15::ELEMENT_SIZE, i16::ELEMENT_SIZE);
+    }
+}
@@ -0,0 +1,363 @@
+//! Splitting of serialized messages into MTU sized frames and reassembly on the receiving side.
+//!
+//! Sizes and lengths are written at the back of a message and compacted in [BufWriter::finish](crate::BufWriter::finish),
+//! so a finished message can be sent out in fragments with [Fragmenter].
+//! Alternatively, [BufWriter::new_fragmented](crate::BufWriter::new_fragmented) sends fragments while the message is being written,
+//! so that the sender does not need a buffer for the whole message either.
+//! Each fragment is prefixed with a one byte header: bit 7 marks the first fragment, bit 6 the last one
+//! and bits 5..0 carry a sequence number that wraps around, so that lost fragments are detected.
+
+use crate::Error;
+
+const FIRST: u8 = 0b1000_0000;
+const LAST: u8 = 0b0100_0000;
+const SEQ_MASK: u8 = 0b0011_1111;
+
+/// Fragment header length in bytes.
+pub const HEADER_LEN: usize = 1;
+
+/// Iterator over fragments of a serialized message, each at most `mtu` bytes long including the header.
+pub struct Fragmenter<'i> {
+    message: &'i [u8],
+    payload_len: usize,
+    headers: Headers,
+    done: bool,
+}
+
+/// Sends out fragments of a message written by [BufWriter::new_fragmented](crate::BufWriter::new_fragmented).
+pub(crate) struct FragmentSink<'i> {
+    send: &'i mut dyn FnMut(Fragment<'_>) -> Result<(), Error>,
+    payload_len: usize,
+    headers: Headers,
+}
+
+/// Sequence numbers and first fragment flag of an outgoing message.
+struct Headers {
+    seq: u8,
+    is_first: bool,
+}
+
+/// One fragment of a message, payload is borrowed from the message without copying.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Fragment<'i> {
+    pub header: u8,
+    pub payload: &'i [u8],
+}
+
+/// Collects fragments into a caller provided scratch buffer until the whole message is received.
+pub struct Reassembler<'i> {
+    buf: &'i mut [u8],
+    len: usize,
+    // Sequence number of the next fragment, None if waiting for the first one
+    next_seq: Option<u8>,
+}
+
+impl<'i> Fragmenter<'i> {
+    pub fn new(message: &'i [u8], mtu: usize) -> Result<Self, Error> {
+        Ok(Fragmenter {
+            message,
+            payload_len: payload_len(mtu)?,
+            headers: Headers::new(),
+            done: false,
+        })
+    }
+}
+
+fn payload_len(mtu: usize) -> Result<usize, Error> {
+    if mtu <= HEADER_LEN {
+        return Err(Error::MtuTooSmall);
+    }
+    Ok(mtu - HEADER_LEN)
+}
+
+impl Headers {
+    fn new() -> Self {
+        Headers {
+            seq: 0,
+            is_first: true,
+        }
+    }
+
+    fn next(&mut self, is_last: bool) -> u8 {
+        let mut header = self.seq;
+        if self.is_first {
+            header |= FIRST;
+            self.is_first = false;
+        }
+        if is_last {
+            header |= LAST;
+        }
+        self.seq = (self.seq + 1) & SEQ_MASK;
+        header
+    }
+}
+
+impl<'i> FragmentSink<'i> {
+    pub(crate) fn new(
+        mtu: usize,
+        send: &'i mut dyn FnMut(Fragment<'_>) -> Result<(), Error>,
+    ) -> Result<Self, Error> {
+        Ok(FragmentSink {
+            send,
+            payload_len: payload_len(mtu)?,
+            headers: Headers::new(),
+        })
+    }
+
+    pub(crate) fn payload_len(&self) -> usize {
+        self.payload_len
+    }
+
+    pub(crate) fn send(&mut self, payload: &[u8], is_last: bool) -> Result<(), Error> {
+        let header = self.headers.next(is_last);
+        (self.send)(Fragment { header, payload })
+    }
+}
+
+impl<'i> Iterator for Fragmenter<'i> {
+    type Item = Fragment<'i>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let len = self.message.len().min(self.payload_len);
+        let (payload, rest) = self.message.split_at(len);
+        self.done = rest.is_empty();
+        self.message = rest;
+        let header = self.headers.next(self.done);
+        Some(Fragment { header, payload })
+    }
+}
+
+impl<'i> Fragment<'i> {
+    pub fn is_first(&self) -> bool {
+        self.header & FIRST != 0
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.header & LAST != 0
+    }
+
+    /// Copy header and payload into `frame`, returning the used part of it.
+    pub fn write_to<'f>(&self, frame: &'f mut [u8]) -> Result<&'f [u8], Error> {
+        let len = HEADER_LEN + self.payload.len();
+        let frame = frame.get_mut(..len).ok_or(Error::OutOfBounds)?;
+        frame[0] = self.header;
+        frame[HEADER_LEN..].copy_from_slice(self.payload);
+        Ok(frame)
+    }
+}
+
+impl<'i> Reassembler<'i> {
+    pub fn new(scratch: &'i mut [u8]) -> Self {
+        Reassembler {
+            buf: scratch,
+            len: 0,
+            next_seq: None,
+        }
+    }
+
+    /// Process one received frame, returns the whole message once the last fragment arrives.
+    /// Message can then be read with [BufReader::new](crate::BufReader::new).
+    ///
+    /// First fragment always starts a new message, discarding an incomplete one.
+    /// On [Error::FragmentOutOfOrder] or [Error::OutOfBounds] (message does not fit into the scratch buffer),
+    /// the partial message is dropped and fragments are ignored until the next first one.
+    pub fn push(&mut self, frame: &[u8]) -> Result<Option<&[u8]>, Error> {
+        let (&header, payload) = frame.split_first().ok_or(Error::OutOfBounds)?;
+        let seq = header & SEQ_MASK;
+        if header & FIRST != 0 {
+            if seq != 0 {
+                self.next_seq = None;
+                return Err(Error::FragmentOutOfOrder);
+            }
+            self.len = 0;
+        } else if self.next_seq != Some(seq) {
+            self.next_seq = None;
+            return Err(Error::FragmentOutOfOrder);
+        }
+        let Some(dst) = self.buf.get_mut(self.len..self.len + payload.len()) else {
+            self.next_seq = None;
+            return Err(Error::OutOfBounds);
+        };
+        dst.copy_from_slice(payload);
+        self.len += payload.len();
+        if header & LAST != 0 {
+            self.next_seq = None;
+            Ok(Some(&self.buf[..self.len]))
+        } else {
+            self.next_seq = Some((seq + 1) & SEQ_MASK);
+            Ok(None)
+        }
+    }
+
+    /// Drop partially received message.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.next_seq = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fragment, Fragmenter, Reassembler};
+    use crate::{BufReader, BufWriter, Error, RefVec};
+
+    #[test]
+    fn round_trip() {
+        let mut scratch = [0u8; 512];
+        let mut wr = BufWriter::new(&mut scratch);
+        for i in 0..100u8 {
+            wr.write_bool(i % 3 == 0).unwrap();
+            wr.write_u8(i).unwrap();
+        }
+        wr.write_str("fragmented").unwrap();
+        let message = wr.finish().unwrap();
+
+        // mtu of 2 wraps the sequence number around
+        for mtu in [2, 8, 64] {
+            let mut rx_scratch = [0u8; 512];
+            let mut rx = Reassembler::new(&mut rx_scratch);
+            let count = Fragmenter::new(message, mtu).unwrap().count();
+            assert_eq!(count, message.len().div_ceil(mtu - 1));
+            let mut frame = [0u8; 64];
+            let mut received = None;
+            for (i, fragment) in Fragmenter::new(message, mtu).unwrap().enumerate() {
+                assert_eq!(fragment.is_first(), i == 0);
+                assert_eq!(fragment.is_last(), i == count - 1);
+                let frame = fragment.write_to(&mut frame).unwrap();
+                assert!(frame.len() <= mtu);
+                if let Some(m) = rx.push(frame).unwrap() {
+                    received = Some(m.len());
+                }
+            }
+            assert_eq!(received, Some(message.len()));
+            let mut rd = BufReader::new(&rx_scratch[..message.len()]);
+            for i in 0..100u8 {
+                assert_eq!(rd.read_bool(), Ok(i % 3 == 0));
+                assert_eq!(rd.read_u8(), Ok(i));
+            }
+            assert_eq!(rd.read_str(), Ok("fragmented"));
+        }
+    }
+
+    #[test]
+    fn empty_message() {
+        let mut fragments = Fragmenter::new(&[], 8).unwrap();
+        let fragment = fragments.next().unwrap();
+        assert!(fragment.is_first() && fragment.is_last());
+        assert!(fragment.payload.is_empty());
+        assert_eq!(fragments.next(), None);
+        assert_eq!(Fragmenter::new(&[], 1).err(), Some(Error::MtuTooSmall));
+    }
+
+    #[test]
+    fn lost_fragment() {
+        let message = [0u8; 20];
+        let mut frames = [[0u8; 4]; 7];
+        let mut scratch = [0u8; 32];
+        let mut rx = Reassembler::new(&mut scratch);
+        for (fragment, frame) in Fragmenter::new(&message, 4).unwrap().zip(frames.iter_mut()) {
+            fragment.write_to(frame).unwrap();
+        }
+        assert_eq!(rx.push(&frames[0]), Ok(None));
+        assert_eq!(rx.push(&frames[2]), Err(Error::FragmentOutOfOrder));
+        // ignored until the next first fragment
+        assert_eq!(rx.push(&frames[3]), Err(Error::FragmentOutOfOrder));
+        for frame in &frames[..6] {
+            assert_eq!(rx.push(frame), Ok(None));
+        }
+        assert_eq!(rx.push(&frames[6][..3]), Ok(Some(&message[..])));
+    }
+
+    #[test]
+    fn scratch_too_small() {
+        let message = [0u8; 20];
+        let mut scratch = [0u8; 16];
+        let mut rx = Reassembler::new(&mut scratch);
+        let mut frame = [0u8; 9];
+        let mut fragments = Fragmenter::new(&message, 9).unwrap();
+        for _ in 0..2 {
+            let frame = fragments.next().unwrap().write_to(&mut frame).unwrap();
+            assert_eq!(rx.push(frame), Ok(None));
+        }
+        let frame = fragments.next().unwrap().write_to(&mut frame).unwrap();
+        assert_eq!(rx.push(frame), Err(Error::OutOfBounds));
+    }
+
+    fn write_message(wr: &mut BufWriter) -> Result<(), Error> {
+        for i in 0..20u8 {
+            wr.write_bool(i % 3 == 0)?;
+            wr.write_u8(i)?;
+        }
+        wr.write(&RefVec::from(&["a", "bc", "", "def"][..]))?;
+        wr.write_bytes(&[0xAA; 40])?;
+        wr.write_str("fragmented")
+    }
+
+    #[test]
+    fn fragmented_writer() {
+        let mut buf = [0u8; 256];
+        let mut wr = BufWriter::new(&mut buf);
+        write_message(&mut wr).unwrap();
+        let message = wr.finish().unwrap();
+
+        for mtu in [2, 8, 12] {
+            let mut expected = Fragmenter::new(message, mtu).unwrap();
+            let mut rx_scratch = [0u8; 256];
+            let mut rx = Reassembler::new(&mut rx_scratch);
+            let mut received = None;
+            let mut send = |fragment: Fragment<'_>| {
+                assert_eq!(Some(fragment), expected.next());
+                let mut frame = [0u8; 16];
+                let frame = fragment.write_to(&mut frame)?;
+                if let Some(m) = rx.push(frame)? {
+                    received = Some(m.len());
+                }
+                Ok(())
+            };
+            // much smaller than the message, only has to fit one fragment and the top level sizes
+            let mut scratch = [0u8; 32];
+            let mut wr = BufWriter::new_fragmented(&mut scratch, mtu, &mut send).unwrap();
+            write_message(&mut wr).unwrap();
+            assert_eq!(wr.finish_size(), Ok(message.len()));
+            assert_eq!(received, Some(message.len()));
+            assert_eq!(expected.next(), None);
+            assert_eq!(&rx_scratch[..message.len()], message);
+        }
+    }
+
+    #[test]
+    fn fragmented_writer_sent_bytes() {
+        let mut send = |_: Fragment<'_>| Ok(());
+        let mut scratch = [0u8; 16];
+        let mut wr = BufWriter::new_fragmented(&mut scratch, 8, &mut send).unwrap();
+        let checkpoint = wr.checkpoint();
+        let slot = wr.reserve_slot(1).unwrap();
+        wr.write_slice(&[0; 20]).unwrap();
+        assert_eq!(wr.pos(), (21, 7));
+        assert_eq!(wr.patch_slot(slot, &[1]), Err(Error::InvalidSlot));
+        assert_eq!(wr.rollback(checkpoint), Err(Error::InvalidCheckpoint));
+
+        let checkpoint = wr.checkpoint();
+        wr.write_u8(0).unwrap();
+        assert_eq!(wr.rollback(checkpoint), Ok(()));
+        assert_eq!(wr.pos(), (21, 7));
+        assert_eq!(wr.finish().err(), Some(Error::WrongWriterKind));
+
+        let mut scratch = [0u8; 16];
+        assert_eq!(
+            BufWriter::new_fragmented(&mut scratch, 1, &mut send).err(),
+            Some(Error::MtuTooSmall)
+        );
+    }
+
+    #[test]
+    fn fragmented_writer_send_error() {
+        let mut send = |_: Fragment<'_>| Err(Error::OutOfBounds);
+        let mut scratch = [0u8; 8];
+        let mut wr = BufWriter::new_fragmented(&mut scratch, 4, &mut send).unwrap();
+        assert_eq!(wr.write_slice(&[0; 20]), Err(Error::OutOfBounds));
+    }
+}
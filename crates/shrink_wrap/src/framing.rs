@@ -0,0 +1,453 @@
+//! Framing of serialized messages for byte streams such as UART, with a CRC to detect corrupted ones.
+//!
+//! Message is followed by its CRC (little endian) and then encoded with COBS, ending with a 0x00 delimiter,
+//! or with SLIP, starting and ending with an END byte. Both encodings guarantee that the delimiter
+//! does not occur inside a frame, so [Deframer] can drop garbage and resynchronise on the next delimiter.
+
+use crate::Error;
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    /// Consistent Overhead Byte Stuffing, at most one extra byte per 254 bytes, frames end with 0x00
+    Cobs,
+    /// RFC 1055, special bytes are escaped with two byte sequences, frames start and end with 0xC0
+    Slip,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Crc {
+    None,
+    /// CRC-16/IBM-3740 (also known as CRC-16/CCITT-FALSE)
+    Crc16,
+    /// CRC-32/ISO-HDLC, same as used by Ethernet and zlib
+    Crc32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Framing {
+    pub encoding: Encoding,
+    pub crc: Crc,
+}
+
+/// Streaming decoder, collects frame bytes into a caller provided scratch buffer.
+pub struct Deframer<'i> {
+    framing: Framing,
+    buf: &'i mut [u8],
+    len: usize,
+    // Bytes left in the current COBS block, next byte is a code byte if 0
+    cobs_left: u8,
+    // Previous COBS block was shorter than 254 bytes, so zero is inserted before the next one
+    cobs_zero: bool,
+    slip_esc: bool,
+    // Frame is dropped because of an error, until the next delimiter
+    is_discarding: bool,
+}
+
+impl Crc {
+    pub const fn size_bytes(&self) -> usize {
+        match self {
+            Crc::None => 0,
+            Crc::Crc16 => 2,
+            Crc::Crc32 => 4,
+        }
+    }
+
+    /// CRC of `data`, little endian, only first [size_bytes](Self::size_bytes) bytes are used.
+    fn compute(&self, data: &[u8]) -> [u8; 4] {
+        match self {
+            Crc::None => [0; 4],
+            Crc::Crc16 => {
+                let [a, b] = crc16(data).to_le_bytes();
+                [a, b, 0, 0]
+            }
+            Crc::Crc32 => crc32(data).to_le_bytes(),
+        }
+    }
+}
+
+impl Framing {
+    pub const fn new(encoding: Encoding, crc: Crc) -> Self {
+        Framing { encoding, crc }
+    }
+
+    /// Worst case length of a frame carrying `payload_len` bytes, including delimiters.
+    pub const fn max_frame_len(&self, payload_len: usize) -> usize {
+        let len = payload_len + self.crc.size_bytes();
+        match self.encoding {
+            Encoding::Cobs => len + len / 254 + 2,
+            Encoding::Slip => len * 2 + 2,
+        }
+    }
+
+    /// Encode `payload`, e.g. output of [BufWriter::finish](crate::BufWriter::finish), into `frame`,
+    /// returning the used part of it.
+    pub fn encode<'f>(&self, payload: &[u8], frame: &'f mut [u8]) -> Result<&'f [u8], Error> {
+        let crc = self.crc.compute(payload);
+        let bytes = payload
+            .iter()
+            .chain(crc[..self.crc.size_bytes()].iter())
+            .copied();
+        let mut wr = FrameWriter { frame, len: 0 };
+        match self.encoding {
+            Encoding::Cobs => {
+                let mut code_idx = wr.push(0)?;
+                let mut code = 1;
+                for b in bytes {
+                    if b != 0 {
+                        wr.push(b)?;
+                        code += 1;
+                    }
+                    if b == 0 || code == 0xFF {
+                        wr.frame[code_idx] = code;
+                        code_idx = wr.push(0)?;
+                        code = 1;
+                    }
+                }
+                wr.frame[code_idx] = code;
+                wr.push(0)?;
+            }
+            Encoding::Slip => {
+                // leading END flushes any garbage received before the frame
+                wr.push(SLIP_END)?;
+                for b in bytes {
+                    match b {
+                        SLIP_END => {
+                            wr.push(SLIP_ESC)?;
+                            wr.push(SLIP_ESC_END)?;
+                        }
+                        SLIP_ESC => {
+                            wr.push(SLIP_ESC)?;
+                            wr.push(SLIP_ESC_ESC)?;
+                        }
+                        b => {
+                            wr.push(b)?;
+                        }
+                    }
+                }
+                wr.push(SLIP_END)?;
+            }
+        }
+        let len = wr.len;
+        Ok(&frame[..len])
+    }
+}
+
+struct FrameWriter<'f> {
+    frame: &'f mut [u8],
+    len: usize,
+}
+
+impl FrameWriter<'_> {
+    /// Returns index of the written byte.
+    fn push(&mut self, b: u8) -> Result<usize, Error> {
+        let dst = self.frame.get_mut(self.len).ok_or(Error::OutOfBounds)?;
+        *dst = b;
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+}
+
+impl<'i> Deframer<'i> {
+    /// `scratch` must be able to hold the largest expected payload and its CRC.
+    pub fn new(framing: Framing, scratch: &'i mut [u8]) -> Self {
+        Deframer {
+            framing,
+            buf: scratch,
+            len: 0,
+            cobs_left: 0,
+            cobs_zero: false,
+            slip_esc: false,
+            is_discarding: false,
+        }
+    }
+
+    /// Process one received byte, returns verified payload once a whole frame is received.
+    /// Payload can then be read with [BufReader::new](crate::BufReader::new).
+    ///
+    /// On [Error::MalformedFrame], [Error::CrcMismatch] or [Error::OutOfBounds] (payload does not fit into the scratch buffer),
+    /// the frame is dropped and bytes are ignored until the next delimiter.
+    /// Empty frames are skipped, so payloads without CRC must not be empty.
+    pub fn push(&mut self, byte: u8) -> Result<Option<&[u8]>, Error> {
+        let delimiter = match self.framing.encoding {
+            Encoding::Cobs => 0x00,
+            Encoding::Slip => SLIP_END,
+        };
+        if byte == delimiter {
+            let is_discarding = self.is_discarding;
+            let is_complete = self.cobs_left == 0 && !self.slip_esc;
+            let len = self.len;
+            self.reset();
+            if is_discarding || len == 0 {
+                return Ok(None);
+            }
+            return self.verify(is_complete, len).map(Some);
+        }
+        if self.is_discarding {
+            return Ok(None);
+        }
+        let result = match self.framing.encoding {
+            Encoding::Cobs => self.push_cobs(byte),
+            Encoding::Slip => self.push_slip(byte),
+        };
+        if result.is_err() {
+            self.is_discarding = true;
+        }
+        result.map(|_| None)
+    }
+
+    /// Drop partially received frame.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.cobs_left = 0;
+        self.cobs_zero = false;
+        self.slip_esc = false;
+        self.is_discarding = false;
+    }
+
+    fn push_cobs(&mut self, byte: u8) -> Result<(), Error> {
+        if self.cobs_left == 0 {
+            if self.cobs_zero {
+                self.put(0)?;
+            }
+            self.cobs_left = byte - 1;
+            self.cobs_zero = byte != 0xFF;
+            Ok(())
+        } else {
+            self.cobs_left -= 1;
+            self.put(byte)
+        }
+    }
+
+    fn push_slip(&mut self, byte: u8) -> Result<(), Error> {
+        if core::mem::replace(&mut self.slip_esc, false) {
+            match byte {
+                SLIP_ESC_END => self.put(SLIP_END),
+                SLIP_ESC_ESC => self.put(SLIP_ESC),
+                _ => Err(Error::MalformedFrame),
+            }
+        } else if byte == SLIP_ESC {
+            self.slip_esc = true;
+            Ok(())
+        } else {
+            self.put(byte)
+        }
+    }
+
+    fn put(&mut self, byte: u8) -> Result<(), Error> {
+        let dst = self.buf.get_mut(self.len).ok_or(Error::OutOfBounds)?;
+        *dst = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn verify(&self, is_complete: bool, len: usize) -> Result<&[u8], Error> {
+        let crc_len = self.framing.crc.size_bytes();
+        if !is_complete || len < crc_len {
+            return Err(Error::MalformedFrame);
+        }
+        let (payload, crc) = self.buf[..len].split_at(len - crc_len);
+        if self.framing.crc.compute(payload)[..crc_len] != *crc {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(payload)
+    }
+}
+
+/// CRC-16/IBM-3740: polynomial 0x1021, initial value 0xFFFF, not reflected.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-32/ISO-HDLC: polynomial 0x04C11DB7 (reflected), initial value and final XOR 0xFFFFFFFF.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc16, crc32, Crc, Deframer, Encoding, Framing};
+    use crate::{BufReader, BufWriter, Error};
+
+    const ALL: [Framing; 6] = [
+        Framing::new(Encoding::Cobs, Crc::None),
+        Framing::new(Encoding::Cobs, Crc::Crc16),
+        Framing::new(Encoding::Cobs, Crc::Crc32),
+        Framing::new(Encoding::Slip, Crc::None),
+        Framing::new(Encoding::Slip, Crc::Crc16),
+        Framing::new(Encoding::Slip, Crc::Crc32),
+    ];
+
+    fn push_all<'a>(rx: &'a mut Deframer, frame: &[u8]) -> Result<Option<&'a [u8]>, Error> {
+        let (last, rest) = frame.split_last().unwrap();
+        for &b in rest {
+            assert_eq!(rx.push(b), Ok(None));
+        }
+        rx.push(*last)
+    }
+
+    #[test]
+    fn crc_check_values() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn cobs_known_frames() {
+        let framing = Framing::new(Encoding::Cobs, Crc::None);
+        let mut frame = [0u8; 300];
+        assert_eq!(framing.encode(&[0], &mut frame), Ok(&[1, 1, 0][..]));
+        assert_eq!(
+            framing.encode(&[0x11, 0x22, 0x00, 0x33], &mut frame),
+            Ok(&[3, 0x11, 0x22, 2, 0x33, 0][..])
+        );
+        let payload = [0xAA; 254];
+        let frame = framing.encode(&payload, &mut frame).unwrap();
+        assert_eq!(frame.len(), 257);
+        assert_eq!((frame[0], frame[255], frame[256]), (0xFF, 1, 0));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut scratch = [0u8; 512];
+        let mut wr = BufWriter::new(&mut scratch);
+        for i in 0..=255u8 {
+            wr.write_u8(i).unwrap();
+        }
+        wr.write_str("framed").unwrap();
+        let message = wr.finish().unwrap();
+
+        for framing in ALL {
+            for message in [message, &[0], &[0xC0, 0xDB], &[0xAA; 254], &[0xAA; 508]] {
+                let mut frame = [0u8; 1100];
+                let frame = framing.encode(message, &mut frame).unwrap();
+                assert!(frame.len() <= framing.max_frame_len(message.len()));
+                let mut rx_scratch = [0u8; 512];
+                let mut rx = Deframer::new(framing, &mut rx_scratch);
+                assert_eq!(push_all(&mut rx, frame), Ok(Some(message)), "{framing:?}");
+            }
+        }
+        let framing = Framing::new(Encoding::Slip, Crc::Crc16);
+        let mut frame = [0u8; 1100];
+        let frame = framing.encode(message, &mut frame).unwrap();
+        let mut rx_scratch = [0u8; 512];
+        let mut rx = Deframer::new(framing, &mut rx_scratch);
+        let payload = push_all(&mut rx, frame).unwrap().unwrap();
+        let mut rd = BufReader::new(payload);
+        for i in 0..=255u8 {
+            assert_eq!(rd.read_u8(), Ok(i));
+        }
+        assert_eq!(rd.read_str(), Ok("framed"));
+    }
+
+    #[test]
+    fn resynchronise() {
+        for framing in ALL.into_iter().filter(|f| f.crc != Crc::None) {
+            let mut frame = [0u8; 32];
+            let frame = framing.encode(&[1, 2, 0, 3], &mut frame).unwrap();
+            let mut rx_scratch = [0u8; 32];
+            let mut rx = Deframer::new(framing, &mut rx_scratch);
+            // tail of a frame that started before the receiver was up
+            let mut received = 0;
+            for &b in [0x55, 0x02, 0xDB].iter().chain(frame) {
+                if let Ok(Some(payload)) = rx.push(b) {
+                    assert_eq!(payload, &[1, 2, 0, 3]);
+                    received += 1;
+                }
+            }
+            // SLIP frames start with END, so only COBS frame is lost
+            let expected = if framing.encoding == Encoding::Slip {
+                1
+            } else {
+                0
+            };
+            assert_eq!(received, expected);
+            assert_eq!(push_all(&mut rx, frame), Ok(Some(&[1, 2, 0, 3][..])));
+
+            let mut corrupted = [0u8; 32];
+            corrupted[..frame.len()].copy_from_slice(frame);
+            corrupted[2] ^= 0x04;
+            assert_eq!(
+                push_all(&mut rx, &corrupted[..frame.len()]),
+                Err(Error::CrcMismatch),
+                "{framing:?}"
+            );
+            assert_eq!(push_all(&mut rx, frame), Ok(Some(&[1, 2, 0, 3][..])));
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let mut rx_scratch = [0u8; 8];
+        let framing = Framing::new(Encoding::Slip, Crc::None);
+        let mut rx = Deframer::new(framing, &mut rx_scratch);
+        assert_eq!(rx.push(0xDB), Ok(None));
+        assert_eq!(rx.push(0x01), Err(Error::MalformedFrame));
+        // ignored until END
+        assert_eq!(rx.push(0x01), Ok(None));
+        assert_eq!(rx.push(0xC0), Ok(None));
+        assert_eq!(push_all(&mut rx, &[0x01, 0xC0]), Ok(Some(&[1][..])));
+
+        let mut rx_scratch = [0u8; 8];
+        let framing = Framing::new(Encoding::Cobs, Crc::Crc16);
+        let mut rx = Deframer::new(framing, &mut rx_scratch);
+        // block is cut short by the delimiter
+        assert_eq!(
+            push_all(&mut rx, &[0x05, 0x01, 0x00]),
+            Err(Error::MalformedFrame)
+        );
+        // shorter than CRC
+        assert_eq!(
+            push_all(&mut rx, &[0x02, 0x01, 0x00]),
+            Err(Error::MalformedFrame)
+        );
+    }
+
+    #[test]
+    fn scratch_too_small() {
+        let framing = Framing::new(Encoding::Cobs, Crc::Crc32);
+        let mut frame = [0u8; 32];
+        let frame = framing.encode(&[0xAA; 8], &mut frame).unwrap();
+        let mut rx_scratch = [0u8; 10];
+        let mut rx = Deframer::new(framing, &mut rx_scratch);
+        let (last, rest) = frame.split_last().unwrap();
+        let mut error = None;
+        for &b in rest {
+            if let Err(e) = rx.push(b) {
+                error.get_or_insert(e);
+            }
+        }
+        assert_eq!(error, Some(Error::OutOfBounds));
+        assert_eq!(rx.push(*last), Ok(None));
+
+        let mut frame = [0u8; 32];
+        let frame = framing.encode(&[0xAA; 6], &mut frame).unwrap();
+        assert_eq!(push_all(&mut rx, frame), Ok(Some(&[0xAA; 6][..])));
+    }
+}
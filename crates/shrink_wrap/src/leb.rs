@@ -0,0 +1,135 @@
+use crate::{BufReader, BufWriter, Error};
+
+/// Unsigned LEB128: each byte carries 7 bits of the number starting from the least significant ones,
+/// most significant bit is set if more bytes follow.
+pub(crate) fn write_unsigned(wr: &mut BufWriter, mut val: u128) -> Result<(), Error> {
+    loop {
+        let byte = (val & 0x7F) as u8;
+        val >>= 7;
+        if val == 0 {
+            return wr.write_u8(byte);
+        }
+        wr.write_u8(byte | 0x80)?;
+    }
+}
+
+/// Read unsigned LEB128 number, which must fit into `bits`.
+/// Returns MalformedLeb if there are more bytes than needed for `bits` or if the number is too large.
+pub(crate) fn read_unsigned(rd: &mut BufReader, bits: u32) -> Result<u128, Error> {
+    let max_bytes = bits.div_ceil(7);
+    let mut val = 0u128;
+    for i in 0..max_bytes {
+        let byte = rd.read_u8()?;
+        let shift = i * 7;
+        let payload = (byte & 0x7F) as u128;
+        if shift + 7 > bits && (payload >> (bits - shift)) != 0 {
+            return Err(Error::MalformedLeb);
+        }
+        val |= payload << shift;
+        if byte & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+    Err(Error::MalformedLeb)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BufReader, BufWriter, Error};
+
+    #[test]
+    fn write_leb_u32() {
+        let mut buf = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_leb_u32(0).unwrap();
+        wr.write_leb_u32(127).unwrap();
+        wr.write_leb_u32(300).unwrap();
+        wr.write_leb_u32(u32::MAX).unwrap();
+        assert_eq!(
+            wr.finish().unwrap(),
+            &[0x00, 0x7F, 0xAC, 0x02, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]
+        );
+    }
+
+    #[test]
+    fn write_leb_aligns_to_byte() {
+        let mut buf = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_bool(true).unwrap();
+        wr.write_leb_u16(1).unwrap();
+        assert_eq!(wr.finish().unwrap(), &[0x80, 0x01]);
+    }
+
+    #[test]
+    fn read_leb_u32() {
+        let buf = [0x00, 0x7F, 0xAC, 0x02, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_leb_u32(), Ok(0));
+        assert_eq!(rd.read_leb_u32(), Ok(127));
+        assert_eq!(rd.read_leb_u32(), Ok(300));
+        assert_eq!(rd.read_leb_u32(), Ok(u32::MAX));
+        assert_eq!(rd.bytes_left(), 0);
+    }
+
+    #[test]
+    fn read_leb_u32_too_large() {
+        let buf = [0xFF, 0xFF, 0xFF, 0xFF, 0x1F];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_leb_u32(), Err(Error::MalformedLeb));
+    }
+
+    #[test]
+    fn read_leb_u16_too_many_bytes() {
+        let buf = [0x80, 0x80, 0x80, 0x00];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_leb_u16(), Err(Error::MalformedLeb));
+    }
+
+    #[test]
+    fn read_leb_out_of_bounds() {
+        let buf = [0x80];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_leb_u64(), Err(Error::OutOfBounds));
+    }
+
+    #[test]
+    fn round_trip_leb_signed() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_leb_i16(-1).unwrap();
+        wr.write_leb_i32(63).unwrap();
+        wr.write_leb_i32(-64).unwrap();
+        wr.write_leb_i64(i64::MIN).unwrap();
+        wr.write_leb_i128(i128::MAX).unwrap();
+        let buf = wr.finish().unwrap();
+        assert_eq!(&buf[0..3], &[0x01, 0x7E, 0x7F]);
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read_leb_i16(), Ok(-1));
+        assert_eq!(rd.read_leb_i32(), Ok(63));
+        assert_eq!(rd.read_leb_i32(), Ok(-64));
+        assert_eq!(rd.read_leb_i64(), Ok(i64::MIN));
+        assert_eq!(rd.read_leb_i128(), Ok(i128::MAX));
+    }
+
+    #[test]
+    fn round_trip_leb_u16() {
+        let mut buf = [0u8; 3];
+        for i in 0..=u16::MAX {
+            let mut wr = BufWriter::new(&mut buf);
+            wr.write_leb_u16(i).unwrap();
+            let mut rd = BufReader::new(&buf);
+            assert_eq!(rd.read_leb_u16(), Ok(i));
+        }
+    }
+
+    #[test]
+    fn round_trip_leb_u128_max() {
+        let mut buf = [0u8; 19];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_leb_u128(u128::MAX).unwrap();
+        assert_eq!(wr.finish().unwrap().len(), 19);
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_leb_u128(), Ok(u128::MAX));
+    }
+}
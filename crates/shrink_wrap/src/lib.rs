@@ -1,21 +1,46 @@
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 pub mod buf_reader;
 pub mod buf_writer;
+#[cfg(feature = "error-context")]
+mod error_context;
+mod fixed;
+pub mod fragment;
+pub mod framing;
+mod leb;
+pub mod max_size;
+mod packed_vec;
+#[cfg(feature = "serde")]
+pub mod serde_bridge;
 pub mod traits;
 mod vec;
-pub(crate) mod vlu16n;
+pub(crate) mod vlun;
+mod zigzag;
 
-pub use buf_reader::BufReader;
+pub use buf_reader::{BufReader, ReadLimits};
 pub use buf_writer::BufWriter;
-pub use traits::{DeserializeShrinkWrap, ElementSize, SerializeShrinkWrap};
+#[cfg(feature = "alloc")]
+pub use buf_writer::BufWriterOwned;
+#[cfg(feature = "error-context")]
+pub use error_context::ErrorContext;
+pub use fixed::Fixed;
+pub use packed_vec::{
+    DeltaElement, DeltaVec, DeltaVecIter, Quantization, QuantizedVec, QuantizedVecIter,
+};
+pub use traits::{DeserializeShrinkWrap, ElementSize, ElementSizeOf, SerializeShrinkWrap};
+pub use vec::{RefVec, RefVecIndex, RefVecIter};
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Error {
     OutOfBounds,
     OutOfBoundsRev,
     OutOfBoundsRevCompact,
     MalformedVlu16N,
+    MalformedVlu32N,
+    MalformedVlu64N,
     MalformedLeb,
     MalformedUtf8,
     StrTooLong,
@@ -23,4 +48,28 @@ pub enum Error {
     ItemTooLong,
     EnumFutureVersionOrMalformedData,
     ImpliedSizeInVec,
+    LimitExceeded,
+    OutOfRange,
+    /// Bounded number is not in any of its allowed ranges, contains the number
+    ValueOutOfBounds(i128),
+    MtuTooSmall,
+    FragmentOutOfOrder,
+    InvalidSlot,
+    InvalidCheckpoint,
+    /// Writer was taken out of a BufWriterOwned and finished as a borrowed one, or the other way around
+    WrongWriterKind,
+    MalformedFrame,
+    CrcMismatch,
+    /// Error reported by a serde Serialize or Deserialize implementation
+    SerdeCustom,
+    /// Part of the serde data model that cannot be mapped onto the wire format, e.g. deserialize_any
+    SerdeUnsupported,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{self:?}")
+    }
 }
+
+impl core::error::Error for Error {}
@@ -0,0 +1,63 @@
+//! Const helpers used by generated code to compute `MAX_ENCODED_SIZE` of bounded types.
+//! Data is accounted in bits, including worst case alignment, sizes and lengths written to the reversed area
+//! in nibbles they will take after being encoded.
+
+/// Number of nibbles `val` takes when encoded as Vlu16N, Vlu32N or Vlu64N.
+pub const fn vlu_nibbles(val: u64) -> usize {
+    let bits = u64::BITS - val.leading_zeros();
+    let nibbles = bits.div_ceil(3) as usize;
+    if nibbles == 0 {
+        1
+    } else {
+        nibbles
+    }
+}
+
+/// Upper bound of message length in bytes with `bits` of data and `rev_nibbles` of encoded sizes and lengths.
+pub const fn bytes(bits: usize, rev_nibbles: usize) -> usize {
+    if rev_nibbles == 0 {
+        return bits.div_ceil(8);
+    }
+    // alignment to a nibble boundary and a possible padding nibble before the reversed values
+    (bits + 3 + 4 + rev_nibbles * 4).div_ceil(8)
+}
+
+pub const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bytes, vlu_nibbles};
+    use crate::BufWriter;
+
+    #[test]
+    fn nibbles() {
+        assert_eq!(vlu_nibbles(0), 1);
+        assert_eq!(vlu_nibbles(7), 1);
+        assert_eq!(vlu_nibbles(8), 2);
+        assert_eq!(vlu_nibbles(u16::MAX as u64), 6);
+        assert_eq!(vlu_nibbles(u64::MAX), 22);
+    }
+
+    #[test]
+    fn bytes_upper_bound() {
+        for bools in 0..=16 {
+            for len in [0, 1, 7, 8, 100] {
+                let mut buf = [0u8; 128];
+                let mut wr = BufWriter::new(&mut buf);
+                for _ in 0..bools {
+                    wr.write_bool(true).unwrap();
+                }
+                wr.write_bytes(&[0u8; 100][..len]).unwrap();
+                let actual = wr.finish().unwrap().len();
+                let bound = bytes(bools + 7 + len * 8, vlu_nibbles(len as u64));
+                assert!(actual <= bound, "{bools} {len}");
+            }
+        }
+    }
+}
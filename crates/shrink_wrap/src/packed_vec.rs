@@ -0,0 +1,517 @@
+//! Vectors of numbers with compact encodings for slowly changing telemetry samples.
+//!
+//! [DeltaVec] writes the first integer as is, followed by differences to the previous element as zigzag Vlu64N,
+//! so that a nibble or two is enough for values that change a little between samples.
+//! [QuantizedVec] maps f32 values from a declared range onto `bits` wide unsigned integers.
+//!
+//! Both are prefixed with the number of elements in the reversed area, same as [RefVec](crate::RefVec),
+//! and are deserialized lazily from a buffer without allocations.
+
+use crate::traits::ElementSize;
+use crate::{
+    BufReader, BufWriter, DeserializeShrinkWrap, ElementSizeOf, Error, SerializeShrinkWrap,
+};
+use core::fmt::{Debug, Formatter};
+
+/// Integers that can be delta encoded.
+/// Differences are computed in i64 with wrap around, which is exact for all widths up to 64 bits.
+pub trait DeltaElement: Copy + SerializeShrinkWrap + for<'i> DeserializeShrinkWrap<'i> {
+    fn to_i64(self) -> i64;
+
+    /// None if a malformed difference resulted in a value that does not fit
+    fn from_i64(val: i64) -> Option<Self>;
+}
+
+macro_rules! impl_delta_element {
+    ($($ty:ty),*) => {
+        $(
+        impl DeltaElement for $ty {
+            fn to_i64(self) -> i64 {
+                self as i64
+            }
+
+            fn from_i64(val: i64) -> Option<Self> {
+                <$ty>::try_from(val).ok()
+            }
+        }
+        )*
+    };
+}
+
+impl_delta_element!(u8, u16, u32, i8, i16, i32, i64);
+
+impl DeltaElement for u64 {
+    fn to_i64(self) -> i64 {
+        self as i64
+    }
+
+    fn from_i64(val: i64) -> Option<Self> {
+        Some(val as u64)
+    }
+}
+
+pub enum DeltaVec<'i, T> {
+    Slice {
+        slice: &'i [T],
+    },
+    Buf {
+        buf: BufReader<'i>,
+        elements_count: u32,
+    },
+}
+
+impl<'i, T: DeltaElement> DeltaVec<'i, T> {
+    pub fn iter(&self) -> DeltaVecIter<'i, T> {
+        match self {
+            DeltaVec::Slice { slice } => DeltaVecIter::Slice { slice },
+            DeltaVec::Buf {
+                buf,
+                elements_count,
+            } => DeltaVecIter::Buf {
+                buf: *buf,
+                prev: None,
+                remaining: *elements_count,
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            DeltaVec::Slice { slice } => slice.len(),
+            DeltaVec::Buf { elements_count, .. } => *elements_count as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'i, T> From<&'i [T]> for DeltaVec<'i, T> {
+    fn from(slice: &'i [T]) -> Self {
+        DeltaVec::Slice { slice }
+    }
+}
+
+impl<'i, T> ElementSizeOf for DeltaVec<'i, T> {
+    const ELEMENT_SIZE: ElementSize = ElementSize::Unsized;
+}
+
+impl<'i, T: DeltaElement> SerializeShrinkWrap for DeltaVec<'i, T> {
+    fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
+        wr.write_size_rev(self.len(), Error::VecTooLong)?;
+        let mut prev: Option<T> = None;
+        for val in self.iter() {
+            let val = val?;
+            match prev {
+                None => wr.write(&val)?,
+                Some(prev) => wr.write_vli64n(val.to_i64().wrapping_sub(prev.to_i64()))?,
+            }
+            prev = Some(val);
+        }
+        Ok(())
+    }
+}
+
+impl<'i, T: DeltaElement> DeserializeShrinkWrap<'i> for DeltaVec<'i, T> {
+    fn des_shrink_wrap<'di>(
+        rd: &'di mut BufReader<'i>,
+        _element_size: ElementSize,
+    ) -> Result<Self, Error> {
+        let elements_count = rd.read_size_rev()? as u32;
+        rd.count_elements(elements_count)?;
+        let buf = *rd;
+        // advance past the vector, differences must be read one by one anyway
+        let mut iter: DeltaVecIter<'i, T> = DeltaVecIter::Buf {
+            buf,
+            prev: None,
+            remaining: elements_count,
+        };
+        for val in &mut iter {
+            val?;
+        }
+        if let DeltaVecIter::Buf { buf, .. } = iter {
+            *rd = buf;
+        }
+        Ok(DeltaVec::Buf {
+            buf,
+            elements_count,
+        })
+    }
+}
+
+impl<'i, T: DeltaElement + PartialEq> PartialEq for DeltaVec<'i, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().zip(other.iter()).all(|(a, b)| match (a, b) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false,
+            })
+    }
+}
+
+impl<'i, T: DeltaElement + Debug> Debug for DeltaVec<'i, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        debug_list(f, self.iter())
+    }
+}
+
+pub enum DeltaVecIter<'i, T> {
+    Slice {
+        slice: &'i [T],
+    },
+    Buf {
+        buf: BufReader<'i>,
+        prev: Option<T>,
+        remaining: u32,
+    },
+}
+
+impl<'i, T: DeltaElement> Iterator for DeltaVecIter<'i, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            DeltaVecIter::Slice { slice } => {
+                let (item, rest) = slice.split_first()?;
+                *slice = rest;
+                Some(Ok(*item))
+            }
+            DeltaVecIter::Buf {
+                buf,
+                prev,
+                remaining,
+            } => {
+                if *remaining == 0 {
+                    return None;
+                }
+                *remaining -= 1;
+                let val = match prev {
+                    None => buf.read(ElementSize::Implied),
+                    Some(prev) => buf.read_vli64n().and_then(|delta| {
+                        T::from_i64(prev.to_i64().wrapping_add(delta)).ok_or(Error::OutOfRange)
+                    }),
+                };
+                match val {
+                    Ok(val) => *prev = Some(val),
+                    // all the following elements depend on this one
+                    Err(_) => *remaining = 0,
+                }
+                Some(val)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = match self {
+            DeltaVecIter::Slice { slice } => slice.len(),
+            DeltaVecIter::Buf { remaining, .. } => *remaining as usize,
+        };
+        (len, Some(len))
+    }
+}
+
+/// Linear mapping of f32 values in `min..=max` onto unsigned integers from 0 to `2^bits - 1`.
+/// Values outside of the range are clamped, NaN is sent as `min`.
+/// Fields are only set through [new](Self::new), so that `bits` is always from 1 to 32.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quantization {
+    min: f32,
+    max: f32,
+    bits: u8,
+}
+
+impl Quantization {
+    pub const fn new(min: f32, max: f32, bits: u8) -> Self {
+        assert!(bits >= 1 && bits <= 32, "bits must be from 1 to 32");
+        Quantization { min, max, bits }
+    }
+
+    pub fn min(&self) -> f32 {
+        self.min
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    fn max_code(&self) -> u32 {
+        u32::MAX >> (32 - self.bits)
+    }
+
+    pub fn quantize(&self, val: f32) -> u32 {
+        let (min, max) = (self.min as f64, self.max as f64);
+        let val = (val as f64).max(min).min(max);
+        let scaled = (val - min) / (max - min) * self.max_code() as f64;
+        // NaN is converted to 0
+        (scaled + 0.5) as u32
+    }
+
+    pub fn dequantize(&self, code: u32) -> f32 {
+        let (min, max) = (self.min as f64, self.max as f64);
+        let code = code.min(self.max_code());
+        (min + (max - min) * code as f64 / self.max_code() as f64) as f32
+    }
+
+    /// Difference between two adjacent values, maximum error is half of it.
+    pub fn step(&self) -> f32 {
+        ((self.max as f64 - self.min as f64) / self.max_code() as f64) as f32
+    }
+}
+
+/// f32 values sent as `bits` wide integers, see [Quantization].
+/// Quantization is declared in the schema, so it is provided by the generated code instead of being stored in a slice.
+pub enum QuantizedVec<'i> {
+    Slice {
+        slice: &'i [f32],
+    },
+    Buf {
+        buf: BufReader<'i>,
+        elements_count: u32,
+        quantization: Quantization,
+    },
+}
+
+impl<'i> QuantizedVec<'i> {
+    pub fn iter(&self) -> QuantizedVecIter<'i> {
+        match self {
+            QuantizedVec::Slice { slice } => QuantizedVecIter::Slice { slice },
+            QuantizedVec::Buf {
+                buf,
+                elements_count,
+                quantization,
+            } => QuantizedVecIter::Buf {
+                buf: *buf,
+                remaining: *elements_count,
+                quantization: *quantization,
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            QuantizedVec::Slice { slice } => slice.len(),
+            QuantizedVec::Buf { elements_count, .. } => *elements_count as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn ser_quantized(
+        &self,
+        wr: &mut BufWriter,
+        quantization: Quantization,
+    ) -> Result<(), Error> {
+        wr.write_size_rev(self.len(), Error::VecTooLong)?;
+        for val in self.iter() {
+            let code = quantization.quantize(val?);
+            wr.write_un(code as u64, quantization.bits)?;
+        }
+        Ok(())
+    }
+
+    pub fn des_quantized(
+        rd: &mut BufReader<'i>,
+        quantization: Quantization,
+    ) -> Result<Self, Error> {
+        let elements_count = rd.read_size_rev()? as u32;
+        rd.count_elements(elements_count)?;
+        let buf = *rd;
+        for _ in 0..elements_count {
+            rd.read_un(quantization.bits)?;
+        }
+        Ok(QuantizedVec::Buf {
+            buf,
+            elements_count,
+            quantization,
+        })
+    }
+}
+
+impl<'i> From<&'i [f32]> for QuantizedVec<'i> {
+    fn from(slice: &'i [f32]) -> Self {
+        QuantizedVec::Slice { slice }
+    }
+}
+
+impl<'i> PartialEq for QuantizedVec<'i> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().zip(other.iter()).all(|(a, b)| match (a, b) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false,
+            })
+    }
+}
+
+impl<'i> Debug for QuantizedVec<'i> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        debug_list(f, self.iter())
+    }
+}
+
+pub enum QuantizedVecIter<'i> {
+    Slice {
+        slice: &'i [f32],
+    },
+    Buf {
+        buf: BufReader<'i>,
+        remaining: u32,
+        quantization: Quantization,
+    },
+}
+
+impl<'i> Iterator for QuantizedVecIter<'i> {
+    type Item = Result<f32, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            QuantizedVecIter::Slice { slice } => {
+                let (item, rest) = slice.split_first()?;
+                *slice = rest;
+                Some(Ok(*item))
+            }
+            QuantizedVecIter::Buf {
+                buf,
+                remaining,
+                quantization,
+            } => {
+                if *remaining == 0 {
+                    return None;
+                }
+                *remaining -= 1;
+                let val = buf
+                    .read_un(quantization.bits)
+                    .map(|code| quantization.dequantize(code as u32));
+                if val.is_err() {
+                    *remaining = 0;
+                }
+                Some(val)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = match self {
+            QuantizedVecIter::Slice { slice } => slice.len(),
+            QuantizedVecIter::Buf { remaining, .. } => *remaining as usize,
+        };
+        (len, Some(len))
+    }
+}
+
+/// Elements as a list, with an error in place of the first one that could not be read.
+pub(crate) fn debug_list<T: Debug>(
+    f: &mut Formatter<'_>,
+    iter: impl Iterator<Item = Result<T, Error>>,
+) -> core::fmt::Result {
+    let mut list = f.debug_list();
+    for item in iter {
+        match item {
+            Ok(item) => list.entry(&item),
+            Err(e) => list.entry(&e),
+        };
+    }
+    list.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ser(buf: &mut [u8], f: impl FnOnce(&mut BufWriter) -> Result<(), Error>) -> &[u8] {
+        let mut wr = BufWriter::new(buf);
+        f(&mut wr).unwrap();
+        wr.finish().unwrap()
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let values = [1000i32, 1001, 999, 1010, -5, i32::MAX, i32::MIN];
+        let mut buf = [0u8; 64];
+        let bytes = ser(&mut buf, |wr| wr.write(&DeltaVec::from(&values[..])));
+        let mut rd = BufReader::new(bytes);
+        let vec: DeltaVec<'_, i32> = rd.read(ElementSize::Implied).unwrap();
+        assert_eq!(vec, DeltaVec::from(&values[..]));
+        assert_eq!(vec.iter().size_hint(), (7, Some(7)));
+        assert_eq!(rd.bytes_left(), 0);
+    }
+
+    #[test]
+    fn delta_is_compact() {
+        let values = [0x1234u16, 0x1235, 0x1233, 0x1234];
+        let mut buf = [0u8; 64];
+        let bytes = ser(&mut buf, |wr| wr.write(&DeltaVec::from(&values[..])));
+        // first value, then +1, -2 and +1 zigzag encoded, one nibble each, and the length in the last nibble
+        assert_eq!(bytes, &[0x34, 0x12, 0x23, 0x24]);
+    }
+
+    #[test]
+    fn delta_u64_wraps() {
+        let values = [u64::MAX, 0, u64::MAX, 1 << 63];
+        let mut buf = [0u8; 64];
+        let bytes = ser(&mut buf, |wr| wr.write(&DeltaVec::from(&values[..])));
+        let mut rd = BufReader::new(bytes);
+        let vec: DeltaVec<'_, u64> = rd.read(ElementSize::Implied).unwrap();
+        assert_eq!(vec, DeltaVec::from(&values[..]));
+    }
+
+    #[test]
+    fn delta_out_of_range() {
+        let mut buf = [0u8; 64];
+        let bytes = ser(&mut buf, |wr| {
+            wr.write_size_rev(2, Error::VecTooLong)?;
+            wr.write_u8(200)?;
+            wr.write_vli64n(100)
+        });
+        let mut rd = BufReader::new(bytes);
+        let r: Result<DeltaVec<'_, u8>, _> = rd.read(ElementSize::Implied);
+        assert_eq!(r.err(), Some(Error::OutOfRange));
+    }
+
+    #[test]
+    fn quantization() {
+        let q = Quantization::new(-40.0, 125.0, 12);
+        assert_eq!(q.quantize(-40.0), 0);
+        assert_eq!(q.quantize(125.0), 4095);
+        assert_eq!(q.quantize(-100.0), 0);
+        assert_eq!(q.quantize(f32::INFINITY), 4095);
+        assert_eq!(q.quantize(f32::NAN), 0);
+        assert_eq!(q.dequantize(0), -40.0);
+        assert_eq!(q.dequantize(4095), 125.0);
+        for val in [-39.9f32, 0.0, 21.5, 100.01] {
+            assert!((q.dequantize(q.quantize(val)) - val).abs() <= q.step() / 2.0);
+        }
+        let q = Quantization::new(0.0, 1.0, 32);
+        assert_eq!(q.quantize(1.0), u32::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "bits must be from 1 to 32")]
+    fn quantization_bits_checked() {
+        let _ = Quantization::new(0.0, 1.0, 33);
+    }
+
+    #[test]
+    fn quantized_round_trip() {
+        let q = Quantization::new(0.0, 10.0, 4);
+        let values = [0.0, 10.0, 5.0, 2.0];
+        let mut buf = [0u8; 64];
+        let bytes = ser(&mut buf, |wr| {
+            QuantizedVec::from(&values[..]).ser_quantized(wr, q)?;
+            wr.write_u8(0xAA)
+        });
+        assert_eq!(bytes, &[0x0F, 0x83, 0xAA, 0x04]);
+        let mut rd = BufReader::new(bytes);
+        let vec = QuantizedVec::des_quantized(&mut rd, q).unwrap();
+        assert_eq!(rd.read_u8(), Ok(0xAA));
+        let decoded = [0.0, 10.0, 8.0 * 10.0 / 15.0, 3.0 * 10.0 / 15.0];
+        assert_eq!(vec, QuantizedVec::from(&decoded[..]));
+    }
+}
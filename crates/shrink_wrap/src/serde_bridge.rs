@@ -0,0 +1,840 @@
+//! [serde] support, so that host-side types can be written and read without a .ww file,
+//! in the same layout as code generated from a .ww file with the same shape.
+//!
+//! Mapping of the serde data model:
+//! * bool, integers and floats are written with the corresponding [BufWriter] methods, char as u32;
+//! * strings and bytes are written with their length in the reversed area;
+//! * `Option` is a bool flag followed by the value;
+//! * structs, tuple structs and enums nested into other types are aligned to byte boundary
+//!   and written with their size in the reversed area, same as items referenced from other items,
+//!   top level one is written without a size;
+//! * enum discriminant is the variant index plus one, same as implicit discriminants in .ww files,
+//!   written as Vlu16N and followed by the variant fields;
+//! * sequences and maps are prefixed with the number of elements, same as [RefVec](crate::RefVec),
+//!   tuples and newtypes are written as their fields.
+//!
+//! Generated code writes enums without data variants without a size when they are elements of a [RefVec](crate::RefVec),
+//! see [ElementSize::UnsizedSelfDescribing](crate::ElementSize::UnsizedSelfDescribing).
+//! serde does not tell whether other variants of an enum carry data, so such enums have to be listed
+//! with [Serializer::set_self_describing] and [Deserializer::set_self_describing] to be compatible.
+//! Listed enums are written without a size wherever they are used.
+//!
+//! Similarly, `Result` is written as an `is_ok` flag followed by the value only when listed with
+//! [Serializer::set_result_enums] and [Deserializer::set_result_enums], serde only exposes its name,
+//! so otherwise it is written as any other enum.
+//!
+//! Format is not self-describing, so `deserialize_any` and `deserialize_ignored_any` return [Error::SerdeUnsupported].
+
+use crate::buf_writer::U16RevPos;
+use crate::{BufReader, BufWriter, Error};
+use core::fmt::{Display, Write};
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use serde::Deserialize;
+
+/// Serialize `value` into `wr`, call [BufWriter::finish] afterwards to get the encoded bytes.
+pub fn to_writer<T: Serialize + ?Sized>(value: &T, wr: &mut BufWriter) -> Result<(), Error> {
+    value.serialize(&mut Serializer::new(wr))
+}
+
+/// Deserialize `T` from `rd`, strings and bytes can be borrowed from the buffer.
+pub fn from_reader<'de, T: Deserialize<'de>>(rd: &mut BufReader<'de>) -> Result<T, Error> {
+    T::deserialize(&mut Deserializer::new(rd))
+}
+
+pub struct Serializer<'a, 'i> {
+    wr: &'a mut BufWriter<'i>,
+    // Top level struct or enum is written without a size
+    is_root: bool,
+    self_describing: &'a [&'a str],
+    result_enums: &'a [&'a str],
+}
+
+/// Nested item that is being written, its size is written once it's done.
+struct Item {
+    u16_rev_from: U16RevPos,
+    start: usize,
+}
+
+impl<'a, 'i> Serializer<'a, 'i> {
+    pub fn new(wr: &'a mut BufWriter<'i>) -> Self {
+        Serializer {
+            wr,
+            is_root: true,
+            self_describing: &[],
+            result_enums: &[],
+        }
+    }
+
+    /// Names of enums without data variants, that are written without a size, same as generated code does for vector elements.
+    pub fn set_self_describing(&mut self, enums: &'a [&'a str]) {
+        self.self_describing = enums;
+    }
+
+    /// Names of enums with two newtype variants, that are written as a flag that is true for the first variant,
+    /// followed by the value, same as generated code does for `Result<T, E>`. Usually `&["Result"]`.
+    pub fn set_result_enums(&mut self, enums: &'a [&'a str]) {
+        self.result_enums = enums;
+    }
+
+    fn start_item(&mut self) -> Option<Item> {
+        if core::mem::replace(&mut self.is_root, false) {
+            return None;
+        }
+        self.wr.align_byte();
+        Some(Item {
+            u16_rev_from: self.wr.u16_rev_pos(),
+            start: self.wr.pos().0,
+        })
+    }
+
+    fn end_item(&mut self, item: Option<Item>) -> Result<(), Error> {
+        let Some(item) = item else {
+            return Ok(());
+        };
+        self.wr
+            .encode_vlu16n_rev(item.u16_rev_from, self.wr.u16_rev_pos())?;
+        self.wr.align_byte();
+        let size = self.wr.pos().0 - item.start;
+        self.wr.write_size_rev(size, Error::ItemTooLong)
+    }
+
+    fn start_variant(&mut self, name: &str, variant_index: u32) -> Result<Option<Item>, Error> {
+        let item = if self.self_describing.contains(&name) {
+            self.is_root = false;
+            None
+        } else {
+            self.start_item()
+        };
+        let discriminant = u16::try_from(variant_index + 1).map_err(|_| Error::OutOfRange)?;
+        self.wr.write_vlu16n(discriminant)?;
+        Ok(item)
+    }
+
+    fn compound<'s>(&'s mut self, item: Option<Item>) -> Compound<'s, 'a, 'i> {
+        Compound { ser: self, item }
+    }
+}
+
+/// Fields or elements of a struct, enum variant, tuple, sequence or map.
+pub struct Compound<'s, 'a, 'i> {
+    ser: &'s mut Serializer<'a, 'i>,
+    item: Option<Item>,
+}
+
+impl<'s, 'a, 'i> ser::Serializer for &'s mut Serializer<'a, 'i> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'s, 'a, 'i>;
+    type SerializeTuple = Compound<'s, 'a, 'i>;
+    type SerializeTupleStruct = Compound<'s, 'a, 'i>;
+    type SerializeTupleVariant = Compound<'s, 'a, 'i>;
+    type SerializeMap = Compound<'s, 'a, 'i>;
+    type SerializeStruct = Compound<'s, 'a, 'i>;
+    type SerializeStructVariant = Compound<'s, 'a, 'i>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.wr.write_bool(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.wr.write_i8(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.wr.write_i16(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.wr.write_i32(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.wr.write_i64(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), Error> {
+        self.wr.write_i128(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.wr.write_u8(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.wr.write_u16(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.wr.write_u32(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.wr.write_u64(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), Error> {
+        self.wr.write_u128(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.wr.write_f32(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.wr.write_f64(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.wr.write_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.wr.write_str(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.wr.write_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.wr.write_bool(false)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        self.wr.write_bool(true)?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        let item = self.start_item();
+        self.end_item(item)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Error> {
+        let item = self.start_variant(name, variant_index)?;
+        self.end_item(item)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        if self.result_enums.contains(&name) {
+            self.is_root = false;
+            self.wr.write_bool(variant_index == 0)?;
+            return value.serialize(self);
+        }
+        let item = self.start_variant(name, variant_index)?;
+        value.serialize(&mut *self)?;
+        self.end_item(item)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        let len = len.ok_or(Error::SerdeUnsupported)?;
+        self.wr.write_size_rev(len, Error::VecTooLong)?;
+        self.is_root = false;
+        Ok(self.compound(None))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        self.is_root = false;
+        Ok(self.compound(None))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        let item = self.start_item();
+        Ok(self.compound(item))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        let item = self.start_variant(name, variant_index)?;
+        Ok(self.compound(item))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        self.serialize_seq(len)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        let item = self.start_item();
+        Ok(self.compound(item))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        let item = self.start_variant(name, variant_index)?;
+        Ok(self.compound(item))
+    }
+
+    /// Written as a string, without allocating an intermediate one.
+    fn collect_str<T: Display + ?Sized>(self, value: &T) -> Result<(), Error> {
+        struct Adapter<'w, 'i> {
+            wr: &'w mut BufWriter<'i>,
+            len: usize,
+            error: Option<Error>,
+        }
+        impl Write for Adapter<'_, '_> {
+            fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                self.wr.write_slice(s.as_bytes()).map_err(|e| {
+                    self.error = Some(e);
+                    core::fmt::Error
+                })?;
+                self.len += s.len();
+                Ok(())
+            }
+        }
+        // length goes into the reversed area, so it can be written after the string itself
+        self.wr.align_byte();
+        let mut adapter = Adapter {
+            wr: self.wr,
+            len: 0,
+            error: None,
+        };
+        if write!(adapter, "{value}").is_err() {
+            return Err(adapter.error.unwrap_or(Error::SerdeCustom));
+        }
+        let len = adapter.len;
+        self.wr.write_size_rev(len, Error::StrTooLong)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl Compound<'_, '_, '_> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.ser.end_item(self.item)
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeMap for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.element(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+pub struct Deserializer<'a, 'de> {
+    rd: &'a mut BufReader<'de>,
+    // Top level struct or enum is read without a size
+    is_root: bool,
+    self_describing: &'a [&'a str],
+    result_enums: &'a [&'a str],
+}
+
+impl<'a, 'de> Deserializer<'a, 'de> {
+    pub fn new(rd: &'a mut BufReader<'de>) -> Self {
+        Deserializer {
+            rd,
+            is_root: true,
+            self_describing: &[],
+            result_enums: &[],
+        }
+    }
+
+    /// Names of enums without data variants, that are read without a size, see [Serializer::set_self_describing].
+    pub fn set_self_describing(&mut self, enums: &'a [&'a str]) {
+        self.self_describing = enums;
+    }
+
+    /// Names of enums that are read as a flag followed by the value, see [Serializer::set_result_enums].
+    pub fn set_result_enums(&mut self, enums: &'a [&'a str]) {
+        self.result_enums = enums;
+    }
+
+    /// Read a value that can contain other values, counting it against [ReadLimits::max_depth](crate::ReadLimits::max_depth),
+    /// so that untrusted input for a recursive type such as `Option<Box<Node>>` cannot overflow the stack.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        self.rd.enter()?;
+        let result = f(self);
+        self.rd.leave();
+        result
+    }
+
+    /// Reader of a nested item, None for the top level one.
+    fn start_item(&mut self) -> Result<Option<BufReader<'de>>, Error> {
+        if core::mem::replace(&mut self.is_root, false) {
+            return Ok(None);
+        }
+        let size = self.rd.read_size_rev()?;
+        Ok(Some(self.rd.split(size)?))
+    }
+
+    fn visit_fields<V: Visitor<'de>>(
+        &mut self,
+        item: Option<BufReader<'de>>,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match item {
+            Some(mut rd) => {
+                let mut de = Deserializer {
+                    rd: &mut rd,
+                    is_root: false,
+                    self_describing: self.self_describing,
+                    result_enums: self.result_enums,
+                };
+                visitor.visit_seq(Elements { de: &mut de, len })
+            }
+            None => visitor.visit_seq(Elements { de: self, len }),
+        }
+    }
+
+    fn read_enum<V: Visitor<'de>>(
+        &mut self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        if variants.len() == 2 && self.result_enums.contains(&name) {
+            self.is_root = false;
+            let index = if self.rd.read_bool()? { 0 } else { 1 };
+            return visitor.visit_enum(Variant { de: self, index });
+        }
+        let item = if self.self_describing.contains(&name) {
+            self.is_root = false;
+            None
+        } else {
+            self.start_item()?
+        };
+        match item {
+            Some(mut rd) => {
+                let mut de = Deserializer {
+                    rd: &mut rd,
+                    is_root: false,
+                    self_describing: self.self_describing,
+                    result_enums: self.result_enums,
+                };
+                let index = read_variant_index(de.rd)?;
+                visitor.visit_enum(Variant { de: &mut de, index })
+            }
+            None => {
+                let index = read_variant_index(self.rd)?;
+                visitor.visit_enum(Variant { de: self, index })
+            }
+        }
+    }
+}
+
+/// Fields or elements of a struct, enum variant, tuple, sequence or map.
+struct Elements<'s, 'a, 'de> {
+    de: &'s mut Deserializer<'a, 'de>,
+    len: usize,
+}
+
+/// Enum variant with its index already read.
+struct Variant<'s, 'a, 'de> {
+    de: &'s mut Deserializer<'a, 'de>,
+    index: u32,
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'_, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::SerdeUnsupported)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_bool(self.rd.read_bool()?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i8(self.rd.read_i8()?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i16(self.rd.read_i16()?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i32(self.rd.read_i32()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(self.rd.read_i64()?)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i128(self.rd.read_i128()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u8(self.rd.read_u8()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u16(self.rd.read_u16()?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u32(self.rd.read_u32()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(self.rd.read_u64()?)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u128(self.rd.read_u128()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f32(self.rd.read_f32()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.rd.read_f64()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let c = char::from_u32(self.rd.read_u32()?).ok_or(Error::MalformedUtf8)?;
+        visitor.visit_char(c)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.rd.read_str()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_bytes(self.rd.read_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.rd.read_bool()? {
+            self.nested(|de| visitor.visit_some(de))
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.start_item()?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.nested(|de| visitor.visit_newtype_struct(de))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let len = self.rd.read_size_rev()?;
+        self.rd.count_elements(len as u32)?;
+        self.is_root = false;
+        self.nested(|de| visitor.visit_seq(Elements { de, len }))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.is_root = false;
+        self.nested(|de| visitor.visit_seq(Elements { de, len }))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let item = self.start_item()?;
+        self.nested(|de| de.visit_fields(item, len, visitor))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let len = self.rd.read_size_rev()?;
+        self.rd.count_elements(len as u32)?;
+        self.is_root = false;
+        self.nested(|de| visitor.visit_map(Elements { de, len }))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let item = self.start_item()?;
+        self.nested(|de| de.visit_fields(item, fields.len(), visitor))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.nested(|de| de.read_enum(name, variants, visitor))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::SerdeUnsupported)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::SerdeUnsupported)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, '_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Elements<'_, '_, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'s, 'a, 'de> de::EnumAccess<'de> for Variant<'s, 'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let index: de::value::U32Deserializer<Error> = self.index.into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_, '_, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements { de: self.de, len })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements {
+            de: self.de,
+            len: fields.len(),
+        })
+    }
+}
+
+fn read_variant_index(rd: &mut BufReader) -> Result<u32, Error> {
+    let discriminant = rd.read_vlu16n()? as u32;
+    discriminant
+        .checked_sub(1)
+        .ok_or(Error::EnumFutureVersionOrMalformedData)
+}
+
+/// `Result` is written as `is_ok` flag followed by the value, same as in generated code.
+/// Messages are dropped, as they cannot be stored without allocations.
+impl ser::Error for Error {
+    fn custom<T: Display>(_msg: T) -> Self {
+        Error::SerdeCustom
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(_msg: T) -> Self {
+        Error::SerdeCustom
+    }
+}
@@ -1,10 +1,24 @@
+use crate::vec::{des_item, ser_item};
 use crate::{BufReader, BufWriter, Error};
 
-pub trait SerializeShrinkWrap {
+pub trait SerializeShrinkWrap: ElementSizeOf {
     fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error>;
+
+    /// Exact number of bytes serializing self as a root object would take, nothing is written anywhere.
+    /// Errors are the same as when serializing into a large enough buffer.
+    fn encoded_size(&self) -> Result<usize, Error> {
+        let mut wr = BufWriter::new_size_counter();
+        self.ser_shrink_wrap(&mut wr)?;
+        wr.finish_size()
+    }
+}
+
+/// How a type is laid out when used as an element of a vector, array or other compound type.
+pub trait ElementSizeOf {
+    const ELEMENT_SIZE: ElementSize;
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ElementSize {
     Implied,
     /// Element size is unknown and stored at the back of the buffer.
@@ -18,79 +32,426 @@ pub enum ElementSize {
     UnsizedSelfDescribing,
 }
 
-pub trait DeserializeShrinkWrap<'i>: Sized {
+impl ElementSize {
+    /// Element size of two elements laid out one after another (e.g. in a tuple).
+    /// Unsized elements are written together with their size, so the result is self describing.
+    pub const fn add(self, other: ElementSize) -> ElementSize {
+        match (self, other) {
+            (ElementSize::Implied, _) | (_, ElementSize::Implied) => ElementSize::Implied,
+            (ElementSize::Sized { size_bytes: a }, ElementSize::Sized { size_bytes: b }) => {
+                ElementSize::Sized { size_bytes: a + b }
+            }
+            _ => ElementSize::UnsizedSelfDescribing,
+        }
+    }
+
+    /// Element size of `n` elements laid out one after another (e.g. in an array).
+    pub const fn repeat(self, n: usize) -> ElementSize {
+        match self {
+            ElementSize::Implied => ElementSize::Implied,
+            ElementSize::Sized { size_bytes } => ElementSize::Sized {
+                size_bytes: size_bytes * n,
+            },
+            _ if n == 0 => ElementSize::Sized { size_bytes: 0 },
+            _ => ElementSize::UnsizedSelfDescribing,
+        }
+    }
+
+    /// Element size of a value preceded by a 1 bit flag (Option or Result).
+    pub const fn flagged(self) -> ElementSize {
+        match self {
+            ElementSize::Implied => ElementSize::Implied,
+            _ => ElementSize::UnsizedSelfDescribing,
+        }
+    }
+
+    pub const fn is_unsized(&self) -> bool {
+        matches!(self, ElementSize::Unsized)
+    }
+}
+
+pub trait DeserializeShrinkWrap<'i>: ElementSizeOf + Sized {
     fn des_shrink_wrap<'di>(
         rd: &'di mut BufReader<'i>,
         element_size: ElementSize,
     ) -> Result<Self, Error>;
 }
 
+impl ElementSizeOf for bool {
+    const ELEMENT_SIZE: ElementSize = ElementSize::UnsizedSelfDescribing;
+}
+
 impl SerializeShrinkWrap for bool {
     fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
         wr.write_bool(*self)
     }
 }
 
-impl SerializeShrinkWrap for u8 {
+impl<'i> DeserializeShrinkWrap<'i> for bool {
+    fn des_shrink_wrap<'di>(
+        rd: &'di mut BufReader<'i>,
+        _element_size: ElementSize,
+    ) -> Result<Self, Error> {
+        rd.read_bool()
+    }
+}
+
+macro_rules! impl_primitive {
+    ($ty:ty, $write:ident, $read:ident) => {
+        impl ElementSizeOf for $ty {
+            const ELEMENT_SIZE: ElementSize = ElementSize::Sized {
+                size_bytes: core::mem::size_of::<$ty>(),
+            };
+        }
+
+        impl SerializeShrinkWrap for $ty {
+            fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
+                wr.$write(*self)
+            }
+        }
+
+        impl<'i> DeserializeShrinkWrap<'i> for $ty {
+            fn des_shrink_wrap<'di>(
+                rd: &'di mut BufReader<'i>,
+                _element_size: ElementSize,
+            ) -> Result<Self, Error> {
+                rd.$read()
+            }
+        }
+    };
+}
+
+impl_primitive!(u8, write_u8, read_u8);
+impl_primitive!(u16, write_u16, read_u16);
+impl_primitive!(u32, write_u32, read_u32);
+impl_primitive!(u64, write_u64, read_u64);
+impl_primitive!(u128, write_u128, read_u128);
+impl_primitive!(i8, write_i8, read_i8);
+impl_primitive!(i16, write_i16, read_i16);
+impl_primitive!(i32, write_i32, read_i32);
+impl_primitive!(i64, write_i64, read_i64);
+impl_primitive!(i128, write_i128, read_i128);
+impl_primitive!(f32, write_f32, read_f32);
+impl_primitive!(f64, write_f64, read_f64);
+
+/// Length is written at the back of the buffer, so str is self describing.
+impl ElementSizeOf for &str {
+    const ELEMENT_SIZE: ElementSize = ElementSize::UnsizedSelfDescribing;
+}
+
+impl SerializeShrinkWrap for &str {
+    fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
+        wr.write_str(self)
+    }
+}
+
+impl<'i> DeserializeShrinkWrap<'i> for &'i str {
+    fn des_shrink_wrap<'di>(
+        rd: &'di mut BufReader<'i>,
+        _element_size: ElementSize,
+    ) -> Result<Self, Error> {
+        rd.read_str()
+    }
+}
+
+/// Same as str, bytes are read without copying.
+impl ElementSizeOf for &[u8] {
+    const ELEMENT_SIZE: ElementSize = ElementSize::UnsizedSelfDescribing;
+}
+
+impl SerializeShrinkWrap for &[u8] {
+    fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
+        wr.write_bytes(self)
+    }
+}
+
+impl<'i> DeserializeShrinkWrap<'i> for &'i [u8] {
+    fn des_shrink_wrap<'di>(
+        rd: &'di mut BufReader<'i>,
+        _element_size: ElementSize,
+    ) -> Result<Self, Error> {
+        rd.read_bytes()
+    }
+}
+
+/// Elements are laid out one after another without length, unsized ones are written together with their size.
+impl<T: ElementSizeOf, const N: usize> ElementSizeOf for [T; N] {
+    const ELEMENT_SIZE: ElementSize = T::ELEMENT_SIZE.repeat(N);
+}
+
+impl<T: SerializeShrinkWrap, const N: usize> SerializeShrinkWrap for [T; N] {
+    fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
+        for item in self {
+            ser_item(wr, T::ELEMENT_SIZE.is_unsized(), item)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'i, T: DeserializeShrinkWrap<'i>, const N: usize> DeserializeShrinkWrap<'i> for [T; N] {
+    fn des_shrink_wrap<'di>(
+        rd: &'di mut BufReader<'i>,
+        _element_size: ElementSize,
+    ) -> Result<Self, Error> {
+        let mut error = None;
+        let items: [Option<T>; N] = core::array::from_fn(|_| {
+            if error.is_some() {
+                return None;
+            }
+            match des_item(rd, T::ELEMENT_SIZE) {
+                Ok(item) => Some(item),
+                Err(e) => {
+                    error = Some(e);
+                    None
+                }
+            }
+        });
+        if let Some(e) = error {
+            return Err(e);
+        }
+        // all the items are Some if no error occurred
+        Ok(items.map(|item| item.unwrap()))
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($t:ident $idx:tt),+) => {
+        impl<$($t: ElementSizeOf),+> ElementSizeOf for ($($t,)+) {
+            const ELEMENT_SIZE: ElementSize = ElementSize::Sized { size_bytes: 0 }
+                $(.add($t::ELEMENT_SIZE))+;
+        }
+
+        impl<$($t: SerializeShrinkWrap),+> SerializeShrinkWrap for ($($t,)+) {
+            fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
+                $(ser_item(wr, $t::ELEMENT_SIZE.is_unsized(), &self.$idx)?;)+
+                Ok(())
+            }
+        }
+
+        impl<'i, $($t: DeserializeShrinkWrap<'i>),+> DeserializeShrinkWrap<'i> for ($($t,)+) {
+            fn des_shrink_wrap<'di>(
+                rd: &'di mut BufReader<'i>,
+                _element_size: ElementSize,
+            ) -> Result<Self, Error> {
+                Ok(($(des_item::<$t>(rd, $t::ELEMENT_SIZE)?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// Presence flag is written as a 1 bit bool, so it is packed together with neighbouring bools and
+/// nibbles, value is written right after it.
+impl<T: ElementSizeOf> ElementSizeOf for Option<T> {
+    const ELEMENT_SIZE: ElementSize = T::ELEMENT_SIZE.flagged();
+}
+
+impl<T: ElementSizeOf, E: ElementSizeOf> ElementSizeOf for Result<T, E> {
+    const ELEMENT_SIZE: ElementSize = T::ELEMENT_SIZE.add(E::ELEMENT_SIZE).flagged();
+}
+
+impl<T: SerializeShrinkWrap> SerializeShrinkWrap for Option<T> {
+    fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
+        match self {
+            Some(val) => {
+                wr.write_bool(true)?;
+                ser_item(wr, T::ELEMENT_SIZE.is_unsized(), val)
+            }
+            None => wr.write_bool(false),
+        }
+    }
+}
+
+impl<T: SerializeShrinkWrap, E: SerializeShrinkWrap> SerializeShrinkWrap for Result<T, E> {
     fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
-        wr.write_u8(*self)
-    }
-}
-
-// Won't work with reordered bool's and u4's
-// impl<T: SerializeShrinkWrap> SerializeShrinkWrap for Option<T> {
-//     fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
-//         match self {
-//             Some(val) => {
-//                 wr.write_bool(true)?;
-//                 wr.write(val)
-//             }
-//             None => wr.write_bool(false),
-//         }
-//     }
-// }
-//
-// impl<T: SerializeShrinkWrap, E: SerializeShrinkWrap> SerializeShrinkWrap for Result<T, E> {
-//     fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
-//         match self {
-//             Ok(val) => {
-//                 wr.write_bool(true)?;
-//                 wr.write(val)
-//             }
-//             Err(err_code) => {
-//                 wr.write_bool(false)?;
-//                 wr.write(err_code)
-//             }
-//         }
-//     }
-// }
-
-impl<'i> DeserializeShrinkWrap<'i> for u8 {
+        match self {
+            Ok(val) => {
+                wr.write_bool(true)?;
+                ser_item(wr, T::ELEMENT_SIZE.is_unsized(), val)
+            }
+            Err(err_code) => {
+                wr.write_bool(false)?;
+                ser_item(wr, E::ELEMENT_SIZE.is_unsized(), err_code)
+            }
+        }
+    }
+}
+
+impl<'i, T: DeserializeShrinkWrap<'i>> DeserializeShrinkWrap<'i> for Option<T> {
+    fn des_shrink_wrap<'di>(
+        rd: &'di mut BufReader<'i>,
+        _element_size: ElementSize,
+    ) -> Result<Self, Error> {
+        let is_some = rd.read_bool()?;
+        if is_some {
+            Ok(Some(des_item(rd, T::ELEMENT_SIZE)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'i, T: DeserializeShrinkWrap<'i>, E: DeserializeShrinkWrap<'i>> DeserializeShrinkWrap<'i>
+    for Result<T, E>
+{
     fn des_shrink_wrap<'di>(
         rd: &'di mut BufReader<'i>,
         _element_size: ElementSize,
     ) -> Result<Self, Error> {
-        rd.read_u8()
-    }
-}
-
-// impl<'i, T: DeserializeShrinkWrap<'i>> DeserializeShrinkWrap<'i> for Option<T> {
-//     fn des_shrink_wrap<'di>(rd: &'di mut BufReader<'i>) -> Result<Self, Error> {
-//         let is_some = rd.read_bool()?;
-//         if is_some {
-//             Ok(Some(rd.read()?))
-//         } else {
-//             Ok(None)
-//         }
-//     }
-// }
-// impl<'i, T: DeserializeShrinkWrap<'i>, E: DeserializeShrinkWrap<'i>> DeserializeShrinkWrap<'i> for Result<T, E> {
-//     fn des_shrink_wrap<'di>(rd: &'di mut BufReader<'i>) -> Result<Self, Error> {
-//         let is_ok = rd.read_bool()?;
-//         if is_ok {
-//             Ok(Ok(rd.read()?))
-//         } else {
-//             Ok(Err(rd.read()?))
-//         }
-//     }
-// }
+        let is_ok = rd.read_bool()?;
+        if is_ok {
+            Ok(Ok(des_item(rd, T::ELEMENT_SIZE)?))
+        } else {
+            Ok(Err(des_item(rd, E::ELEMENT_SIZE)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BufReader, BufWriter, ElementSize, Error, RefVec};
+
+    #[test]
+    fn option_packs_with_bools() {
+        let mut buf = [0u8; 8];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_bool(true).unwrap();
+        wr.write(&Some(0xAAu8)).unwrap();
+        wr.write(&Option::<u8>::None).unwrap();
+        wr.write_bool(true).unwrap();
+        let buf = wr.finish().unwrap();
+        assert_eq!(buf, &[0b1100_0000, 0xAA, 0b0100_0000]);
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read_bool(), Ok(true));
+        let a: Option<u8> = rd.read(ElementSize::Implied).unwrap();
+        let b: Option<u8> = rd.read(ElementSize::Implied).unwrap();
+        assert_eq!(a, Some(0xAA));
+        assert_eq!(b, None);
+        assert_eq!(rd.read_bool(), Ok(true));
+    }
+
+    #[test]
+    fn result_round_trip() {
+        let mut buf = [0u8; 8];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&Result::<u8, u8>::Ok(0xAA)).unwrap();
+        wr.write(&Result::<u8, u8>::Err(0xCC)).unwrap();
+        let buf = wr.finish().unwrap();
+        assert_eq!(buf, &[0b1000_0000, 0xAA, 0b0000_0000, 0xCC]);
+
+        let mut rd = BufReader::new(buf);
+        let ok: Result<u8, u8> = rd.read(ElementSize::Implied).unwrap();
+        let err: Result<u8, u8> = rd.read(ElementSize::Implied).unwrap();
+        assert_eq!(ok, Ok(0xAA));
+        assert_eq!(err, Err(0xCC));
+    }
+
+    #[test]
+    fn primitives_round_trip() {
+        let mut buf = [0u8; 128];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&true).unwrap();
+        wr.write(&-2i8).unwrap();
+        wr.write(&0x1234u16).unwrap();
+        wr.write(&-5i32).unwrap();
+        wr.write(&u64::MAX).unwrap();
+        wr.write(&i128::MIN).unwrap();
+        wr.write(&0.25f32).unwrap();
+        wr.write(&-1.5f64).unwrap();
+        wr.write(&"abc").unwrap();
+        let buf = wr.finish().unwrap();
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read::<bool>(ElementSize::Implied), Ok(true));
+        assert_eq!(rd.read::<i8>(ElementSize::Implied), Ok(-2));
+        assert_eq!(rd.read::<u16>(ElementSize::Implied), Ok(0x1234));
+        assert_eq!(rd.read::<i32>(ElementSize::Implied), Ok(-5));
+        assert_eq!(rd.read::<u64>(ElementSize::Implied), Ok(u64::MAX));
+        assert_eq!(rd.read::<i128>(ElementSize::Implied), Ok(i128::MIN));
+        assert_eq!(rd.read::<f32>(ElementSize::Implied), Ok(0.25));
+        assert_eq!(rd.read::<f64>(ElementSize::Implied), Ok(-1.5));
+        assert_eq!(rd.read::<&str>(ElementSize::Implied), Ok("abc"));
+    }
+
+    #[test]
+    fn bytes_same_as_ref_vec() {
+        let bytes: &[u8] = &[1, 2, 3];
+        let mut buf = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&bytes).unwrap();
+        let buf = wr.finish().unwrap();
+
+        let mut buf_vec = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf_vec);
+        wr.write(&RefVec::from(bytes)).unwrap();
+        assert_eq!(buf, wr.finish().unwrap());
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read::<&[u8]>(ElementSize::Implied), Ok(bytes));
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read_bytes_max(2), Err(Error::VecTooLong));
+
+        let mut buf = [0u8; 16];
+        let mut wr = BufWriter::new(&mut buf);
+        assert_eq!(wr.write_bytes_max(bytes, 2), Err(Error::VecTooLong));
+    }
+
+    #[test]
+    fn arrays_and_tuples_round_trip() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&[1u16, 2, 3]).unwrap();
+        wr.write(&(0xAAu8, "xy", [true, false])).unwrap();
+        let buf = wr.finish().unwrap();
+        assert_eq!(buf, &[1, 0, 2, 0, 3, 0, 0xAA, b'x', b'y', 0b1000_0010]);
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read::<[u16; 3]>(ElementSize::Implied), Ok([1, 2, 3]));
+        assert_eq!(
+            rd.read::<(u8, &str, [bool; 2])>(ElementSize::Implied),
+            Ok((0xAA, "xy", [true, false]))
+        );
+    }
+
+    #[test]
+    fn element_sizes() {
+        use crate::ElementSizeOf;
+        assert_eq!(
+            <u16 as ElementSizeOf>::ELEMENT_SIZE,
+            ElementSize::Sized { size_bytes: 2 }
+        );
+        assert_eq!(
+            <f64 as ElementSizeOf>::ELEMENT_SIZE,
+            ElementSize::Sized { size_bytes: 8 }
+        );
+        assert_eq!(
+            <bool as ElementSizeOf>::ELEMENT_SIZE,
+            ElementSize::UnsizedSelfDescribing
+        );
+        assert_eq!(
+            <[u32; 4] as ElementSizeOf>::ELEMENT_SIZE,
+            ElementSize::Sized { size_bytes: 16 }
+        );
+        assert_eq!(
+            <(u8, i64) as ElementSizeOf>::ELEMENT_SIZE,
+            ElementSize::Sized { size_bytes: 9 }
+        );
+        assert_eq!(
+            <(u8, &str) as ElementSizeOf>::ELEMENT_SIZE,
+            ElementSize::UnsizedSelfDescribing
+        );
+        assert_eq!(
+            <Option<u8> as ElementSizeOf>::ELEMENT_SIZE,
+            ElementSize::UnsizedSelfDescribing
+        );
+    }
+}
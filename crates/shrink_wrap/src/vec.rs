@@ -1,60 +1,182 @@
+use crate::packed_vec::debug_list;
 use crate::traits::ElementSize;
-use crate::{BufReader, BufWriter, DeserializeShrinkWrap, Error, SerializeShrinkWrap};
+use crate::{
+    BufReader, BufWriter, DeserializeShrinkWrap, ElementSizeOf, Error, SerializeShrinkWrap,
+};
+use core::cell::RefCell;
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
 
-// pub enum Vec<'i, T, const S: u32, F> where F: Fn(usize) -> Option<T> {
+/// Vector that can be serialized from a slice, an iterator or a generator function,
+/// and is deserialized lazily from a buffer without allocations.
 pub enum RefVec<'i, T> {
-    // pub enum Vec<'i, T, const S: u32, I> where I: Iterator<Item=T> {
     Slice {
         slice: &'i [T],
         element_size: ElementSize,
     },
-    // Iterator {
-    //     it: I
-    // },
+    /// Elements are taken from the iterator during serialization, so it can only be serialized once.
+    Iter {
+        it: &'i RefCell<dyn ExactSizeIterator<Item = T> + 'i>,
+        element_size: ElementSize,
+    },
+    /// Elements are produced on demand by calling `gen` with indices from 0 to `len`.
+    Gen {
+        gen: &'i dyn Fn(usize) -> T,
+        len: usize,
+        element_size: ElementSize,
+    },
     Buf {
         buf: BufReader<'i>,
-        elements_count: u16,
+        elements_count: u32,
         element_size: ElementSize,
     },
-    // Gen {
-    //     gen: F,
-    //     len_elements: usize,
-    //     // element_size: ElementSize,
-    // },
 }
 
-impl<'i, 'di, T> RefVec<'i, T>
-where
-    T: SerializeShrinkWrap + DeserializeShrinkWrap<'i>,
-    // F: Fn(usize) -> Option<T>
-    // I: Iterator<Item=T>,
-{
+impl<'i, T> RefVec<'i, T> {
     pub fn iter(&self) -> RefVecIter<'i, T> {
         match self {
-            RefVec::Slice { .. } => {
-                unimplemented!()
-            }
+            RefVec::Slice { slice, .. } => RefVecIter::Slice { slice },
+            RefVec::Iter { it, .. } => RefVecIter::Iter { it },
+            RefVec::Gen { gen, len, .. } => RefVecIter::Gen {
+                gen: *gen,
+                pos: 0,
+                end: *len,
+            },
             RefVec::Buf {
                 buf,
                 elements_count,
                 element_size,
-            } => RefVecIter::Buf {
-                buf: *buf,
-                elements_count: *elements_count,
-                element_size: *element_size,
-                pos: 0,
-            },
+            } => RefVecIter::Buf(RefVecBufIter::new(*buf, *elements_count, *element_size)),
+        }
+    }
+
+    /// Deserialize elements of a buffer backed vector without requiring `T: Clone`.
+    /// Returns None for all the other kinds of vectors, use [iter](Self::iter) for them.
+    pub fn des_iter(&self) -> Option<RefVecBufIter<'i, T>> {
+        match self {
+            RefVec::Buf {
+                buf,
+                elements_count,
+                element_size,
+            } => Some(RefVecBufIter::new(*buf, *elements_count, *element_size)),
+            _ => None,
+        }
+    }
+
+    /// Get element at index `i`. Complexity is O(1) for slices, generators and buffers with sized elements.
+    /// Buffers with unsized elements are scanned from the start, see [build_index](Self::build_index) for O(1) access.
+    /// Returns None for iterator backed vectors, as their elements can only be taken once.
+    pub fn get(&self, i: usize) -> Option<Result<T, Error>>
+    where
+        T: DeserializeShrinkWrap<'i> + Clone,
+    {
+        match self {
+            RefVec::Slice { slice, .. } => slice.get(i).cloned().map(Ok),
+            RefVec::Iter { .. } => None,
+            RefVec::Gen { gen, len, .. } => (i < *len).then(|| Ok(gen(i))),
+            RefVec::Buf {
+                buf,
+                elements_count,
+                element_size,
+            } => (i < *elements_count as usize).then(|| des_at(*buf, *element_size, i)),
+        }
+    }
+
+    /// Read the reversed size table of a buffer backed vector with unsized elements and store end offsets
+    /// of all elements into `storage`, which must be at least [len](Self::len) long.
+    /// Other vectors do not need an index and resulting one is empty.
+    pub fn build_index<'s>(&self, storage: &'s mut [u32]) -> Result<RefVecIndex<'s>, Error> {
+        let RefVec::Buf {
+            buf,
+            elements_count,
+            element_size: ElementSize::Unsized,
+        } = self
+        else {
+            return Ok(RefVecIndex { ends: &[] });
+        };
+        let elements_count = *elements_count as usize;
+        let Some(ends) = storage.get_mut(..elements_count) else {
+            return Err(Error::OutOfBounds);
+        };
+        let mut rd = *buf;
+        let mut end = 0u32;
+        for e in ends.iter_mut() {
+            end = end
+                .checked_add(rd.read_size_rev()? as u32)
+                .ok_or(Error::OutOfBounds)?;
+            *e = end;
+        }
+        Ok(RefVecIndex { ends })
+    }
+
+    /// Same as [get](Self::get), but uses an index built with [build_index](Self::build_index) for this vector.
+    pub fn get_indexed(&self, index: &RefVecIndex, i: usize) -> Option<Result<T, Error>>
+    where
+        T: DeserializeShrinkWrap<'i> + Clone,
+    {
+        let RefVec::Buf {
+            buf,
+            element_size: ElementSize::Unsized,
+            ..
+        } = self
+        else {
+            return self.get(i);
+        };
+        if index.ends.len() != self.len() {
+            return self.get(i);
+        }
+        let end = *index.ends.get(i)? as usize;
+        let start = if i == 0 {
+            0
+        } else {
+            index.ends[i - 1] as usize
+        };
+        let mut rd = *buf;
+        let item = rd.read_slice(start).and_then(|_| {
+            let mut rd = rd.split(end - start)?;
+            rd.read(ElementSize::Unsized)
+        });
+        Some(item)
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            RefVec::Slice { slice, .. } => slice.len(),
+            RefVec::Iter { it, .. } => it.borrow().len(),
+            RefVec::Gen { len, .. } => *len,
+            RefVec::Buf { elements_count, .. } => *elements_count as usize,
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn element_size(&self) -> ElementSize {
         match self {
             RefVec::Slice { element_size, .. } => *element_size,
+            RefVec::Iter { element_size, .. } => *element_size,
+            RefVec::Gen { element_size, .. } => *element_size,
             RefVec::Buf { element_size, .. } => *element_size,
         }
     }
 }
 
+/// End offsets of unsized elements in a buffer backed [RefVec], relative to the start of the first one.
+pub struct RefVecIndex<'s> {
+    ends: &'s [u32],
+}
+
+/// Element size is chosen automatically based on T, so that e.g. vectors of primitives use sized encoding.
+impl<'i, T: ElementSizeOf> From<&'i [T]> for RefVec<'i, T> {
+    fn from(slice: &'i [T]) -> Self {
+        RefVec::Slice {
+            slice,
+            element_size: T::ELEMENT_SIZE,
+        }
+    }
+}
+
 impl<'i, T> SerializeShrinkWrap for RefVec<'i, T>
 where
     T: SerializeShrinkWrap + DeserializeShrinkWrap<'i>,
@@ -64,20 +186,33 @@ where
             return Err(Error::ImpliedSizeInVec);
         }
         let is_unsized = matches!(self.element_size(), ElementSize::Unsized);
+        wr.write_size_rev(self.len(), Error::VecTooLong)?;
         match self {
             RefVec::Slice { slice, .. } => {
-                let Ok(elements_count) = u16::try_from(slice.len()) else {
-                    return Err(Error::VecTooLong);
-                };
-                wr.write_u16_rev(elements_count)?;
                 for item in slice.iter() {
                     ser_item(wr, is_unsized, item)?;
                 }
             }
-            RefVec::Buf { elements_count, .. } => {
-                wr.write_u16_rev(*elements_count)?;
-                for item in self.iter() {
-                    let item = item?;
+            RefVec::Iter { it, .. } => {
+                let mut it = it.borrow_mut();
+                let len = it.len();
+                for item in (&mut *it).take(len) {
+                    ser_item(wr, is_unsized, &item)?;
+                }
+            }
+            RefVec::Gen { gen, len, .. } => {
+                for i in 0..*len {
+                    ser_item(wr, is_unsized, &gen(i))?;
+                }
+            }
+            RefVec::Buf {
+                buf,
+                elements_count,
+                element_size,
+            } => {
+                let mut buf = *buf;
+                for _ in 0..*elements_count {
+                    let item: T = des_item(&mut buf, *element_size)?;
                     ser_item(wr, is_unsized, &item)?;
                 }
             }
@@ -86,118 +221,287 @@ where
     }
 }
 
-fn ser_item<T: SerializeShrinkWrap>(
+/// Write an element of a vector, array, tuple, Option or Result.
+/// Unsized elements are aligned to byte boundary and their size is written at the back of the buffer.
+pub(crate) fn ser_item<T: SerializeShrinkWrap>(
     wr: &mut BufWriter,
     is_unsized: bool,
     item: &T,
 ) -> Result<(), Error> {
-    let unsized_start = wr.pos().0;
     let u16_rev_from = if is_unsized {
         wr.align_byte();
         Some(wr.u16_rev_pos())
     } else {
         None
     };
+    let unsized_start = wr.pos().0;
     wr.write(item)?;
     if let Some(u16_rev_from) = u16_rev_from {
         wr.encode_vlu16n_rev(u16_rev_from, wr.u16_rev_pos())?;
+        wr.align_byte();
         let size = wr.pos().0 - unsized_start;
-        let Ok(size) = u16::try_from(size) else {
-            return Err(Error::ItemTooLong);
-        };
-        wr.write_u16_rev(size)?;
+        wr.write_size_rev(size, Error::ItemTooLong)?;
     }
     Ok(())
 }
 
-impl<'i, T> DeserializeShrinkWrap<'i> for RefVec<'i, T> {
+/// Read `i`-th element of a vector starting at `rd`, skipping sized elements in O(1).
+fn des_at<'i, T: DeserializeShrinkWrap<'i>>(
+    mut rd: BufReader<'i>,
+    element_size: ElementSize,
+    i: usize,
+) -> Result<T, Error> {
+    match element_size {
+        ElementSize::Sized { size_bytes } => {
+            rd.read_slice(i * size_bytes)?;
+        }
+        _ => {
+            for _ in 0..i {
+                let _item: T = des_item(&mut rd, element_size)?;
+            }
+        }
+    }
+    des_item(&mut rd, element_size)
+}
+
+/// Read an element written with [ser_item].
+/// Element itself is read with Implied size, so that e.g. nested vectors use their own element size.
+pub(crate) fn des_item<'i, T: DeserializeShrinkWrap<'i>>(
+    rd: &mut BufReader<'i>,
+    element_size: ElementSize,
+) -> Result<T, Error> {
+    match element_size {
+        ElementSize::Implied => Err(Error::ImpliedSizeInVec),
+        ElementSize::Unsized => {
+            let len = rd.read_size_rev()?;
+            let mut rd = rd.split(len)?;
+            rd.read(ElementSize::Implied)
+        }
+        ElementSize::Sized { .. } | ElementSize::UnsizedSelfDescribing => {
+            rd.read(ElementSize::Implied)
+        }
+    }
+}
+
+impl<'i, T> ElementSizeOf for RefVec<'i, T> {
+    const ELEMENT_SIZE: ElementSize = ElementSize::Unsized;
+}
+
+/// `element_size` is the size of vector elements, Implied means that T::ELEMENT_SIZE is used.
+impl<'i, T: DeserializeShrinkWrap<'i>> DeserializeShrinkWrap<'i> for RefVec<'i, T> {
     fn des_shrink_wrap<'di>(
         rd: &'di mut BufReader<'i>,
         element_size: ElementSize,
     ) -> Result<Self, Error> {
-        let elements_count = rd.read_vlu16n_rev()?;
-        // let bytes_left = rd.bytes_left();
-        dbg!(elements_count);
+        let element_size = match element_size {
+            ElementSize::Implied => T::ELEMENT_SIZE,
+            element_size => element_size,
+        };
+        let elements_count = rd.read_size_rev()? as u32;
+        rd.count_elements(elements_count)?;
+        let buf = *rd;
+        // advance past the vector, so that following data can be read
+        match element_size {
+            ElementSize::Implied => return Err(Error::ImpliedSizeInVec),
+            ElementSize::Sized { size_bytes } => {
+                rd.read_slice(elements_count as usize * size_bytes)?;
+            }
+            ElementSize::Unsized => {
+                // sizes are in the reversed area, elements themselves are only read when iterated
+                let mut len = 0u32;
+                for _ in 0..elements_count {
+                    len = len
+                        .checked_add(rd.read_size_rev()? as u32)
+                        .ok_or(Error::OutOfBounds)?;
+                }
+                rd.read_slice(len as usize)?;
+            }
+            ElementSize::UnsizedSelfDescribing => {
+                for _ in 0..elements_count {
+                    let _item: T = des_item(rd, element_size)?;
+                }
+            }
+        }
         Ok(RefVec::Buf {
-            // buf: rd.split(bytes_left)?,
-            buf: *rd,
+            buf,
             elements_count,
             element_size,
         })
     }
 }
 
+/// Vectors are equal if they have the same elements, regardless of how they are stored.
+/// Note that comparing [RefVec::Iter] consumes the underlying iterator.
+impl<'i, T> PartialEq for RefVec<'i, T>
+where
+    T: DeserializeShrinkWrap<'i> + Clone + PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        self.iter().zip(other.iter()).all(|(a, b)| match (a, b) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        })
+    }
+}
+
+/// Iterator backed vectors are printed without their elements, so that they are not consumed.
+impl<'i, T> Debug for RefVec<'i, T>
+where
+    T: DeserializeShrinkWrap<'i> + Clone + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RefVec::Iter { .. } => write!(f, "RefVec::Iter {{ len: {} }}", self.len()),
+            _ => debug_list(f, self.iter()),
+        }
+    }
+}
+
 pub enum RefVecIter<'i, T> {
     Slice {
         slice: &'i [T],
-        pos: usize,
     },
-    Buf {
-        buf: BufReader<'i>,
-        elements_count: u16,
-        element_size: ElementSize,
-        pos: u16,
+    Iter {
+        it: &'i RefCell<dyn ExactSizeIterator<Item = T> + 'i>,
+    },
+    Gen {
+        gen: &'i dyn Fn(usize) -> T,
+        pos: usize,
+        end: usize,
     },
-    // Gen {
-    //     gen: F,
-    //     len_elements: usize,
-    //     // element_size: ElementSize,
-    // },
+    Buf(RefVecBufIter<'i, T>),
 }
 
-impl<'i, T: DeserializeShrinkWrap<'i>> Iterator for RefVecIter<'i, T> {
+impl<'i, T: DeserializeShrinkWrap<'i> + Clone> Iterator for RefVecIter<'i, T> {
     type Item = Result<T, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            RefVecIter::Slice { .. } => {
-                unimplemented!()
+            RefVecIter::Slice { slice } => {
+                let (item, rest) = slice.split_first()?;
+                *slice = rest;
+                Some(Ok(item.clone()))
             }
-            RefVecIter::Buf {
-                buf,
-                elements_count,
-                element_size,
-                pos,
-            } => {
-                if *pos == *elements_count {
+            RefVecIter::Iter { it } => it.borrow_mut().next().map(Ok),
+            RefVecIter::Gen { gen, pos, end } => {
+                if *pos >= *end {
                     return None;
                 }
+                let item = gen(*pos);
                 *pos += 1;
-                let item = match element_size {
-                    ElementSize::Implied => {
-                        *pos = *elements_count;
-                        return Some(Err(Error::ImpliedSizeInVec));
-                    }
-                    ElementSize::Unsized => {
-                        let len = match buf.read_vlu16n_rev() {
-                            Ok(len) => len,
-                            Err(e) => {
-                                return Some(Err(e));
-                            }
-                        };
-                        dbg!(len);
-                        let mut buf = match buf.split(len as usize) {
-                            Ok(buf) => buf,
-                            Err(e) => {
-                                return Some(Err(e));
-                            }
-                        };
-                        buf.read(*element_size)
-                    }
-                    ElementSize::Sized { .. } => buf.read(*element_size),
-                    ElementSize::UnsizedSelfDescribing => buf.read(*element_size),
-                };
-                Some(item)
+                Some(Ok(item))
             }
+            RefVecIter::Buf(it) => it.next(),
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = match self {
+            RefVecIter::Slice { slice } => slice.len(),
+            RefVecIter::Iter { it } => it.borrow().len(),
+            RefVecIter::Gen { pos, end, .. } => end - pos,
+            RefVecIter::Buf(it) => it.len(),
+        };
+        (len, Some(len))
+    }
+}
+
+impl<'i, T: DeserializeShrinkWrap<'i> + Clone> ExactSizeIterator for RefVecIter<'i, T> {}
+
+/// Iterating from the back is O(1) per element for sized elements and O(n) for unsized ones.
+/// Iterator backed vectors can only be taken from the front, next_back() always returns None for them.
+impl<'i, T: DeserializeShrinkWrap<'i> + Clone> DoubleEndedIterator for RefVecIter<'i, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            RefVecIter::Slice { slice } => {
+                let (item, rest) = slice.split_last()?;
+                *slice = rest;
+                Some(Ok(item.clone()))
+            }
+            RefVecIter::Iter { .. } => None,
+            RefVecIter::Gen { gen, pos, end } => {
+                if *pos >= *end {
+                    return None;
+                }
+                *end -= 1;
+                Some(Ok(gen(*end)))
+            }
+            RefVecIter::Buf(it) => it.next_back(),
+        }
+    }
+}
+
+/// Deserializes elements of a buffer backed vector one by one, see [RefVec::des_iter].
+pub struct RefVecBufIter<'i, T> {
+    buf: BufReader<'i>,
+    start: BufReader<'i>,
+    element_size: ElementSize,
+    pos: u32,
+    end: u32,
+    _phantom: PhantomData<T>,
+}
+
+impl<'i, T> RefVecBufIter<'i, T> {
+    fn new(buf: BufReader<'i>, elements_count: u32, element_size: ElementSize) -> Self {
+        RefVecBufIter {
+            buf,
+            start: buf,
+            element_size,
+            pos: 0,
+            end: elements_count,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<'i, T: DeserializeShrinkWrap<'i>> Iterator for RefVecBufIter<'i, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.end {
+            return None;
+        }
+        self.pos += 1;
+        if matches!(self.element_size, ElementSize::Implied) {
+            // error is returned only once, no point in reading further
+            self.pos = self.end;
+        }
+        Some(des_item(&mut self.buf, self.element_size))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.end - self.pos) as usize;
+        (len, Some(len))
+    }
+}
+
+impl<'i, T: DeserializeShrinkWrap<'i>> ExactSizeIterator for RefVecBufIter<'i, T> {}
+
+/// Iterating from the back is O(1) per element for sized elements and O(n) for unsized ones.
+impl<'i, T: DeserializeShrinkWrap<'i>> DoubleEndedIterator for RefVecBufIter<'i, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.end {
+            return None;
+        }
+        self.end -= 1;
+        if matches!(self.element_size, ElementSize::Implied) {
+            self.end = self.pos;
+        }
+        Some(des_at(self.start, self.element_size, self.end as usize))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::traits::ElementSize;
     use crate::vec::RefVec;
-    use crate::{BufReader, BufWriter, DeserializeShrinkWrap, Error, SerializeShrinkWrap};
+    use crate::{
+        BufReader, BufWriter, DeserializeShrinkWrap, ElementSizeOf, Error, SerializeShrinkWrap,
+    };
+    use core::cell::RefCell;
 
     #[test]
     fn read_vec_sized() {
@@ -215,7 +519,7 @@ mod tests {
         let mut buf = [0u8; 64];
         let mut wr = BufWriter::new(&mut buf);
         let arr = RefVec::Slice {
-            slice: &[0xAB, 0xCD],
+            slice: &[0xABu8, 0xCD],
             element_size: ElementSize::Sized { size_bytes: 1 },
         };
         wr.write(&arr).unwrap();
@@ -242,6 +546,9 @@ mod tests {
             byte: u8,
             additional_data: &'i [u8],
         }
+        impl<'i> ElementSizeOf for Evolved<'i> {
+            const ELEMENT_SIZE: ElementSize = ElementSize::Unsized;
+        }
         impl<'i> SerializeShrinkWrap for Evolved<'i> {
             fn ser_shrink_wrap(&self, wr: &mut BufWriter) -> Result<(), Error> {
                 wr.write_u8(self.byte)?;
@@ -276,4 +583,214 @@ mod tests {
             Ok(&[0xAB, 0x12, 0x34, 0x56, 0xCD, 0x78, 0x02, 0x42][..])
         );
     }
+
+    #[test]
+    fn vec_of_primitives_is_sized() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        let arr = RefVec::from(&[0x1234u16, 0x5678][..]);
+        wr.write(&arr).unwrap();
+        let buf = wr.finish().unwrap();
+        assert_eq!(buf, &[0x34, 0x12, 0x78, 0x56, 0x02]);
+
+        let mut rd = BufReader::new(buf);
+        let arr: RefVec<'_, u16> = rd.read(u16::ELEMENT_SIZE).unwrap();
+        let mut iter = arr.iter();
+        assert_eq!(iter.next(), Some(Ok(0x1234)));
+        assert_eq!(iter.next(), Some(Ok(0x5678)));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn vec_of_str() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        let arr = RefVec::from(&["ab", "c"][..]);
+        wr.write(&arr).unwrap();
+        let buf = wr.finish().unwrap();
+
+        let mut rd = BufReader::new(buf);
+        let arr: RefVec<'_, &str> = rd.read(<&str>::ELEMENT_SIZE).unwrap();
+        let mut iter = arr.iter();
+        assert_eq!(iter.next(), Some(Ok("ab")));
+        assert_eq!(iter.next(), Some(Ok("c")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn large_vec() {
+        let data = [0xAAu8; 70_000];
+        let arr = RefVec::from(&data[..]);
+        let mut buf = [0u8; 70_016];
+        let mut wr = BufWriter::new(&mut buf);
+        assert_eq!(wr.write(&arr), Err(Error::VecTooLong));
+        wr.with_large_payload(true, |wr| wr.write(&arr)).unwrap();
+        let buf = wr.finish().unwrap();
+
+        let mut rd = BufReader::new(buf);
+        let arr: RefVec<'_, u8> = rd
+            .with_large_payload(true, |rd| rd.read(u8::ELEMENT_SIZE))
+            .unwrap();
+        assert_eq!(arr.iter().count(), 70_000);
+        assert!(arr.iter().all(|b| b == Ok(0xAA)));
+    }
+
+    #[test]
+    fn slice_iter() {
+        let arr = RefVec::from(&[1u8, 2, 3][..]);
+        assert_eq!(arr.len(), 3);
+        let mut iter = arr.iter();
+        assert_eq!(iter.next(), Some(Ok(1)));
+        assert_eq!(iter.next(), Some(Ok(2)));
+        assert_eq!(iter.next(), Some(Ok(3)));
+        assert_eq!(iter.next(), None);
+        assert!(arr.des_iter().is_none());
+    }
+
+    #[test]
+    fn write_vec_from_iter() {
+        let it = RefCell::new((0..3u8).map(|x| x * 2));
+        let arr = RefVec::Iter {
+            it: &it,
+            element_size: u8::ELEMENT_SIZE,
+        };
+        assert_eq!(arr.len(), 3);
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&arr).unwrap();
+        assert_eq!(wr.finish(), Ok(&[0, 2, 4, 0x03][..]));
+        assert!(arr.is_empty());
+    }
+
+    #[test]
+    fn iter_rev_from_iter() {
+        let it = RefCell::new(0..3u8);
+        let arr = RefVec::Iter {
+            it: &it,
+            element_size: u8::ELEMENT_SIZE,
+        };
+        let mut iter = arr.iter();
+        assert_eq!(iter.next_back(), None);
+        assert_eq!(iter.next(), Some(Ok(0)));
+        assert_eq!(iter.len(), 2);
+    }
+
+    #[test]
+    fn write_vec_from_gen() {
+        let gen = |i: usize| ["a", "bc"][i];
+        let arr = RefVec::Gen {
+            gen: &gen,
+            len: 2,
+            element_size: <&str>::ELEMENT_SIZE,
+        };
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&arr).unwrap();
+        let buf = wr.finish().unwrap();
+
+        let mut rd = BufReader::new(buf);
+        let arr_des: RefVec<'_, &str> = rd.read(<&str>::ELEMENT_SIZE).unwrap();
+        assert!(arr_des == RefVec::from(&["a", "bc"][..]));
+        assert!(arr_des == arr);
+    }
+
+    #[test]
+    fn slice_and_buf_eq() {
+        let buf = [0xAB, 0xCD, 0x02];
+        let mut rd = BufReader::new(&buf);
+        let arr: RefVec<'_, u8> = rd.read(u8::ELEMENT_SIZE).unwrap();
+        assert_eq!(arr.len(), 2);
+        assert!(arr == RefVec::from(&[0xAB, 0xCD][..]));
+        assert!(arr != RefVec::from(&[0xAB][..]));
+        assert!(arr != RefVec::from(&[0xAB, 0xCE][..]));
+    }
+
+    #[test]
+    fn read_vec_advances_reader() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&RefVec::from(&["ab", "c"][..])).unwrap();
+        wr.write_u8(0xAA).unwrap();
+        wr.write(&RefVec::from(&[0x1234u16][..])).unwrap();
+        wr.write_u8(0xBB).unwrap();
+        let buf = wr.finish().unwrap();
+
+        let mut rd = BufReader::new(buf);
+        let strs: RefVec<'_, &str> = rd.read(<&str>::ELEMENT_SIZE).unwrap();
+        assert_eq!(rd.read_u8(), Ok(0xAA));
+        let nums: RefVec<'_, u16> = rd.read(u16::ELEMENT_SIZE).unwrap();
+        assert_eq!(rd.read_u8(), Ok(0xBB));
+        assert!(strs == RefVec::from(&["ab", "c"][..]));
+        assert!(nums == RefVec::from(&[0x1234][..]));
+    }
+
+    #[test]
+    fn get_sized() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_bool(true).unwrap();
+        wr.write(&RefVec::from(&[10u16, 20, 30, 40][..])).unwrap();
+        let buf = wr.finish().unwrap();
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read_bool(), Ok(true));
+        let arr: RefVec<'_, u16> = rd.read(u16::ELEMENT_SIZE).unwrap();
+        assert_eq!(arr.get(0), Some(Ok(10)));
+        assert_eq!(arr.get(3), Some(Ok(40)));
+        assert_eq!(arr.get(2), Some(Ok(30)));
+        assert_eq!(arr.get(4), None);
+    }
+
+    #[test]
+    fn get_unsized() {
+        let buf = [0xAB, 0x12, 0x34, 0x56, 0xCD, 0x78, 0x02, 0x42];
+        let mut rd = BufReader::new(&buf);
+        let arr: RefVec<'_, u8> = rd.read(ElementSize::Unsized).unwrap();
+        assert_eq!(arr.get(1), Some(Ok(0xCD)));
+        assert_eq!(arr.get(0), Some(Ok(0xAB)));
+        assert_eq!(arr.get(2), None);
+
+        let arr = RefVec::from(&["a", "b"][..]);
+        assert_eq!(arr.get(1), Some(Ok("b")));
+    }
+
+    #[test]
+    fn get_indexed() {
+        let buf = [0xAB, 0x12, 0x34, 0x56, 0xCD, 0x78, 0x02, 0x42];
+        let mut rd = BufReader::new(&buf);
+        let arr: RefVec<'_, u8> = rd.read(ElementSize::Unsized).unwrap();
+        let mut storage = [0u32; 1];
+        assert!(arr.build_index(&mut storage).is_err());
+        let mut storage = [0u32; 4];
+        let index = arr.build_index(&mut storage).unwrap();
+        assert_eq!(arr.get_indexed(&index, 1), Some(Ok(0xCD)));
+        assert_eq!(arr.get_indexed(&index, 0), Some(Ok(0xAB)));
+        assert_eq!(arr.get_indexed(&index, 2), None);
+        assert_eq!(&storage[..2], &[4, 6]);
+    }
+
+    #[test]
+    fn iter_rev() {
+        let buf = [0xAB, 0xCD, 0xEF, 0x03];
+        let mut rd = BufReader::new(&buf);
+        let arr: RefVec<'_, u8> = rd.read(u8::ELEMENT_SIZE).unwrap();
+        let mut iter = arr.iter();
+        assert_eq!(iter.len(), 3);
+        assert_eq!(iter.next_back(), Some(Ok(0xEF)));
+        assert_eq!(iter.next(), Some(Ok(0xAB)));
+        assert_eq!(iter.len(), 1);
+        assert_eq!(iter.next_back(), Some(Ok(0xCD)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+
+        let strs = RefVec::from(&["a", "bc", "d"][..]);
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write(&strs).unwrap();
+        let buf = wr.finish().unwrap();
+        let mut rd = BufReader::new(buf);
+        let arr: RefVec<'_, &str> = rd.read(<&str>::ELEMENT_SIZE).unwrap();
+        assert!(arr.iter().rev().eq(strs.iter().rev()));
+        assert!(arr.iter().rev().map(|s| s.unwrap()).eq(["d", "bc", "a"]));
+    }
 }
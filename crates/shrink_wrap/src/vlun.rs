@@ -0,0 +1,309 @@
+use crate::{BufReader, BufWriter, Error};
+
+macro_rules! vlu_n {
+    ($name:ident, $ty:ty, $max_nibbles:literal, $malformed:ident, $doc:literal) => {
+        #[doc = $doc]
+        /// Each nibbles carries 1 bit indicating whether there are more nibbles + 3 bits from the original number.
+        #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+        pub(crate) struct $name(pub $ty);
+
+        impl $name {
+            pub(crate) fn len_nibbles(&self) -> usize {
+                let bits = <$ty>::BITS - self.0.leading_zeros();
+                (bits.div_ceil(3) as usize).max(1)
+            }
+
+            pub(crate) fn write_forward(&self, wr: &mut BufWriter) -> Result<(), Error> {
+                let len_nibbles = self.len_nibbles();
+                for i in (0..len_nibbles).rev() {
+                    let nib = ((self.0 >> (i * 3)) & 0b111) as u8;
+                    if i != 0 {
+                        wr.write_u4(nib | 0b1000)?;
+                    } else {
+                        wr.write_u4(nib)?;
+                    }
+                }
+                Ok(())
+            }
+
+            // Reversed area is always encoded with Vlu32N, which is equal to Vlu16N for smaller values
+            #[allow(dead_code)]
+            pub(crate) fn write_reversed(&self, wr: &mut BufWriter) -> Result<(), Error> {
+                let mut val = self.0;
+                let len_nibbles = self.len_nibbles();
+                for i in 0..len_nibbles {
+                    let nib = (val & 0b111) as u8;
+                    // nibbles are read back starting from the most significant one
+                    if i != 0 {
+                        wr.write_u4(nib | 0b1000)
+                            .map_err(|_| Error::OutOfBoundsRevCompact)?;
+                    } else {
+                        wr.write_u4(nib).map_err(|_| Error::OutOfBoundsRevCompact)?;
+                    }
+                    val >>= 3;
+                }
+                Ok(())
+            }
+
+            pub(crate) fn read_forward(rd: &mut BufReader) -> Result<Self, Error> {
+                Self::read(|| rd.read_u4())
+            }
+
+            pub(crate) fn read_reversed(rd: &mut BufReader) -> Result<Self, Error> {
+                Self::read(|| rd.read_u4_rev())
+            }
+
+            /// Bits that do not fit into the number are ignored, but there cannot be more nibbles than
+            /// needed to encode the largest value.
+            fn read(mut read_u4: impl FnMut() -> Result<u8, Error>) -> Result<Self, Error> {
+                let mut num: $ty = 0;
+                for i in 0..$max_nibbles {
+                    let nib = read_u4()?;
+                    if i == $max_nibbles - 1 && nib & 0b1000 != 0 {
+                        return Err(Error::$malformed);
+                    }
+                    num |= (nib & 0b111) as $ty;
+                    if nib & 0b1000 == 0 {
+                        break;
+                    }
+                    num <<= 3;
+                }
+                Ok($name(num))
+            }
+        }
+    };
+}
+
+vlu_n!(
+    Vlu16N,
+    u16,
+    6,
+    MalformedVlu16N,
+    "Variable length encoded u16 based on nibbles."
+);
+vlu_n!(
+    Vlu32N,
+    u32,
+    11,
+    MalformedVlu32N,
+    "Variable length encoded u32 based on nibbles."
+);
+vlu_n!(
+    Vlu64N,
+    u64,
+    22,
+    MalformedVlu64N,
+    "Variable length encoded u64 based on nibbles."
+);
+
+#[cfg(test)]
+mod test {
+    use crate::vlun::{Vlu16N, Vlu32N, Vlu64N};
+    use crate::{BufReader, BufWriter, Error};
+
+    #[test]
+    fn read_vlu32n_single_nibble() {
+        let buf = [0b0111_0010, 0b0000_0001];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_vlu32n(), Ok(7));
+        assert_eq!(rd.read_vlu32n(), Ok(2));
+        assert_eq!(rd.read_vlu32n(), Ok(0));
+        assert_eq!(rd.read_vlu32n(), Ok(1));
+        assert_eq!(rd.bytes_left(), 0);
+    }
+
+    #[test]
+    fn read_vlu32n_multi_nibble() {
+        let buf = [0b1111_0111, 0b1001_0000, 0b1000_0111];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_vlu32n(), Ok(63));
+        assert_eq!(rd.read_vlu32n(), Ok(0b001000));
+        assert_eq!(rd.read_vlu32n(), Ok(0b111));
+        assert_eq!(rd.bytes_left(), 0);
+    }
+
+    #[test]
+    fn read_vlu32n_max() {
+        let buf = [0b1011_1111, 0xff, 0xff, 0xff, 0xff, 0x70];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_vlu32n(), Ok(u32::MAX));
+        assert_eq!(rd.read_u4(), Ok(0));
+        assert_eq!(rd.bytes_left(), 0);
+    }
+
+    #[test]
+    fn read_vlu32n_max_plus1() {
+        // ignore bit 33
+        let buf = [0b1111_1111, 0xff, 0xff, 0xff, 0xff, 0x70];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_vlu32n(), Ok(u32::MAX));
+        assert_eq!(rd.read_u4(), Ok(0));
+        assert_eq!(rd.bytes_left(), 0);
+    }
+
+    #[test]
+    fn read_vlu32n_max_plus_nibble() {
+        // more nibbles than expected for u32
+        let buf = [0xff, 0xff, 0xff, 0xff, 0xff, 0xf0];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_vlu32n(), Err(Error::MalformedVlu32N));
+        assert_eq!(rd.read_u4(), Ok(0));
+        assert_eq!(rd.bytes_left(), 0);
+    }
+
+    #[test]
+    fn read_vlu16n_max_plus_nibble() {
+        let buf = [0xff, 0xff, 0xff];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_vlu16n(), Err(Error::MalformedVlu16N));
+    }
+
+    #[test]
+    fn read_vlu64n_max_plus_nibble() {
+        let buf = [0xff; 11];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_vlu64n(), Err(Error::MalformedVlu64N));
+    }
+
+    #[test]
+    fn read_vlu64n_out_of_bounds() {
+        let buf = [0xff, 0xff];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(rd.read_vlu64n(), Err(Error::OutOfBounds));
+    }
+
+    #[test]
+    fn write_vlu16n_forward() {
+        let mut buf = [0u8; 4];
+        let mut wr = BufWriter::new(&mut buf);
+        Vlu16N(0b1000).write_forward(&mut wr).unwrap();
+        Vlu16N(0b101010).write_forward(&mut wr).unwrap();
+        Vlu16N(5).write_forward(&mut wr).unwrap();
+        assert_eq!(
+            wr.finish().unwrap(),
+            &[0b1001_0000, 0b1101_0010, 0b0101_0000]
+        );
+    }
+
+    #[test]
+    fn write_vlu16n_reversed() {
+        let mut buf = [0u8; 4];
+        let mut wr = BufWriter::new(&mut buf);
+        Vlu16N(0b1000).write_reversed(&mut wr).unwrap();
+        Vlu16N(0b101010).write_reversed(&mut wr).unwrap();
+        Vlu16N(5).write_reversed(&mut wr).unwrap();
+        assert_eq!(
+            wr.finish().unwrap(),
+            &[0b0000_1001, 0b0010_1101, 0b0101_0000]
+        );
+    }
+
+    #[test]
+    fn read_vlu16n_reversed() {
+        // Note that we would read 0 from the back even if it wasn't actually written.
+        // This edge case is handled by writing one 0 nibble before writing reversed Vlu16N in BufWriter::finish().
+        let buf = [0b0000_1001, 0b0010_1101, 0b0101_0000];
+        let mut rd = BufReader::new(&buf);
+        assert_eq!(Vlu16N::read_reversed(&mut rd).unwrap().0, 0);
+        assert_eq!(Vlu16N::read_reversed(&mut rd).unwrap().0, 5);
+        assert_eq!(Vlu16N::read_reversed(&mut rd).unwrap().0, 42);
+        assert_eq!(Vlu16N::read_reversed(&mut rd).unwrap().0, 8);
+    }
+
+    #[test]
+    fn round_trip_vlu16n() {
+        let mut buf = [0u8; 3];
+        for i in 0..=u16::MAX {
+            let mut wr = BufWriter::new(&mut buf);
+            Vlu16N(i).write_forward(&mut wr).unwrap();
+            let mut rd = BufReader::new(&buf);
+            assert_eq!(Vlu16N::read_forward(&mut rd), Ok(Vlu16N(i)));
+        }
+    }
+
+    #[test]
+    fn len_nibbles() {
+        assert_eq!(Vlu16N(0).len_nibbles(), 1);
+        assert_eq!(Vlu16N(7).len_nibbles(), 1);
+        assert_eq!(Vlu16N(8).len_nibbles(), 2);
+        assert_eq!(Vlu16N(u16::MAX).len_nibbles(), 6);
+        assert_eq!(Vlu32N(u32::MAX).len_nibbles(), 11);
+        assert_eq!(Vlu64N(u64::MAX).len_nibbles(), 22);
+    }
+
+    #[test]
+    fn write_vlu32n_max() {
+        let mut buf = [0u8; 6];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_vlu32n(u32::MAX).unwrap();
+        assert_eq!(
+            wr.finish().unwrap(),
+            &[0b1011_1111, 0xff, 0xff, 0xff, 0xff, 0x70]
+        );
+    }
+
+    #[test]
+    fn round_trip_vlu32n() {
+        let mut buf = [0u8; 6];
+        let values = (0..=u16::MAX as u32)
+            .chain((0..=u32::MAX).step_by(65_521))
+            .chain(0..32)
+            .map(|i| i.wrapping_shl(i));
+        for i in values.chain([u32::MAX - 1, u32::MAX]) {
+            let mut wr = BufWriter::new(&mut buf);
+            wr.write_vlu32n(i).unwrap();
+            let mut rd = BufReader::new(&buf);
+            assert_eq!(rd.read_vlu32n(), Ok(i));
+        }
+    }
+
+    #[test]
+    fn round_trip_vlu64n() {
+        let mut buf = [0u8; 11];
+        for shift in 0..64 {
+            for i in [1u64 << shift, (1u64 << shift) - 1, u64::MAX >> shift] {
+                let mut wr = BufWriter::new(&mut buf);
+                wr.write_vlu64n(i).unwrap();
+                let mut rd = BufReader::new(&buf);
+                assert_eq!(rd.read_vlu64n(), Ok(i));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_vlu32n_reversed() {
+        for i in [0, 7, 8, 0xAAAA, u16::MAX as u32 + 1, u32::MAX] {
+            let mut buf = [0u8; 6];
+            let mut wr = BufWriter::new(&mut buf);
+            Vlu32N(i).write_reversed(&mut wr).unwrap();
+            let len = Vlu32N(i).len_nibbles();
+            let written = &buf[..len.div_ceil(2)];
+            let mut rd = BufReader::new(written);
+            if len % 2 == 1 {
+                // reader would read padding 0 nibble first
+                assert_eq!(Vlu32N::read_reversed(&mut rd), Ok(Vlu32N(0)));
+            }
+            assert_eq!(Vlu32N::read_reversed(&mut rd), Ok(Vlu32N(i)));
+        }
+    }
+
+    #[test]
+    fn round_trip_signed() {
+        let mut buf = [0u8; 64];
+        let mut wr = BufWriter::new(&mut buf);
+        wr.write_vli16n(-1).unwrap();
+        wr.write_vli32n(3).unwrap();
+        wr.write_vli32n(-4).unwrap();
+        wr.write_vli32n(i32::MIN).unwrap();
+        wr.write_vli64n(i64::MAX).unwrap();
+        let buf = wr.finish().unwrap();
+        assert_eq!(buf[0], 0b0001_0110);
+
+        let mut rd = BufReader::new(buf);
+        assert_eq!(rd.read_vli16n(), Ok(-1));
+        assert_eq!(rd.read_vli32n(), Ok(3));
+        assert_eq!(rd.read_vli32n(), Ok(-4));
+        assert_eq!(rd.read_vli32n(), Ok(i32::MIN));
+        assert_eq!(rd.read_vli64n(), Ok(i64::MAX));
+    }
+}
@@ -0,0 +1,16 @@
+/// ZigZag encoding maps signed numbers to unsigned ones, so that numbers with small absolute value stay small.
+pub(crate) fn encode(val: i128, bits: u32) -> u128 {
+    ((val << 1) ^ (val >> (bits - 1))) as u128 & mask(bits)
+}
+
+pub(crate) fn decode(val: u128) -> i128 {
+    ((val >> 1) as i128) ^ -((val & 1) as i128)
+}
+
+fn mask(bits: u32) -> u128 {
+    if bits == 128 {
+        u128::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
@@ -0,0 +1,65 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use wire_weaver_core::ast::file::{File, FileSource};
+use wire_weaver_core::dyn_value;
+
+const USAGE: &str = "Usage:
+    ww dump <schema.ww> <Root> [hex bytes]
+        Print annotated hex dump of a message, bytes are read from stdin if not provided.
+        Whitespace, commas and 0x prefixes are ignored, e.g.: ww dump api.ww Request 0x12,0x30 aa";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("dump") if args.len() >= 3 => dump(&args[1], &args[2], &args[3..]),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn dump(schema_path: &str, root: &str, hex: &[String]) -> Result<(), String> {
+    let contents =
+        std::fs::read_to_string(schema_path).map_err(|e| format!("{schema_path}: {e}"))?;
+    let syn_file = syn::parse_file(&contents).map_err(|e| format!("{schema_path}: {e}"))?;
+    let source = FileSource::File(PathBuf::from(schema_path));
+    let (file, _warnings) =
+        File::from_syn(source, syn_file).map_err(|e| format!("{schema_path}: {e:?}"))?;
+
+    let hex = if hex.is_empty() {
+        let mut stdin = String::new();
+        std::io::stdin()
+            .read_to_string(&mut stdin)
+            .map_err(|e| format!("stdin: {e}"))?;
+        stdin
+    } else {
+        hex.join(" ")
+    };
+    let buf = parse_hex(&hex)?;
+    let dump = dyn_value::hex_dump(&file, root, &buf).map_err(|e| format!("{e:?}"))?;
+    print!("{dump}");
+    Ok(())
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: String = hex
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|word| word.trim_start_matches("0x"))
+        .collect();
+    if !digits.is_ascii() || !digits.len().is_multiple_of(2) {
+        return Err(format!("expected hex bytes: {digits}"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("not a hex byte: {}", &digits[i..i + 2]))
+        })
+        .collect()
+}
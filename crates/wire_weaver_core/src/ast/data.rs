@@ -1,9 +1,9 @@
 use crate::ast::ident::Ident;
 use crate::ast::syn_convert::{
-    collect_unknown_attributes, take_default_attr, take_id_attr, take_since_attr,
-    SynConversionError, SynConversionWarning,
+    collect_unknown_attributes, take_default_attr, take_delta_attr, take_id_attr,
+    take_quantize_attr, take_since_attr, SynConversionError, SynConversionWarning,
 };
-use crate::ast::ty::Type;
+use crate::ast::ty::{Type, VecEncoding};
 use crate::ast::value::Value;
 use crate::ast::version::Version;
 use proc_macro2::{Span, TokenStream};
@@ -42,7 +42,7 @@ pub struct Variant {
     // attrs
     pub ident: Ident,
     pub fields: Fields,
-    pub discriminant: u32,
+    pub discriminant: u64,
     pub since: Option<Version>,
 }
 
@@ -51,9 +51,22 @@ impl Field {
         def_order_idx: u32,
         mut field: syn::Field,
     ) -> Result<(Self, Vec<SynConversionWarning>), Vec<SynConversionError>> {
-        let (ty, mut warnings) = Type::from_syn(field.ty)?;
+        let (mut ty, mut warnings) = Type::from_syn(field.ty)?;
         let mut errors = vec![];
         let default = take_default_attr(&mut field.attrs, &mut errors);
+        let delta = take_delta_attr(&mut field.attrs).map(|_| VecEncoding::Delta);
+        let quantize = take_quantize_attr(&mut field.attrs, &mut errors);
+        match (delta, quantize) {
+            (Some(_), Some(_)) => errors.push(SynConversionError::WrongVecEncodingAttr(
+                "#[delta] and #[quantize] cannot be used together".into(),
+            )),
+            (Some(encoding), None) | (None, Some(encoding)) => {
+                if let Err(e) = ty.set_vec_encoding(encoding) {
+                    errors.push(e);
+                }
+            }
+            (None, None) => {}
+        }
         if errors.is_empty() {
             collect_unknown_attributes(&mut field.attrs, &mut warnings);
             Ok((
@@ -74,9 +87,14 @@ impl Field {
         }
     }
 
-    pub(crate) fn handle_eob(&self) -> TokenStream {
+    /// `?` that also pushes `path` into the error context on the error path.
+    pub(crate) fn handle_err(&self, path: &str) -> TokenStream {
+        quote!(.map_err(|e| rd.push_error_path(e, #path))?)
+    }
+
+    pub(crate) fn handle_eob(&self, path: &str) -> TokenStream {
         match &self.default {
-            None => quote!(?),
+            None => self.handle_err(path),
             Some(value) => {
                 let value = value.to_lit();
                 quote!(.unwrap_or(#value))
@@ -85,6 +103,18 @@ impl Field {
     }
 }
 
+impl Fields {
+    /// Named or unnamed fields in definition order, nothing for unit.
+    pub fn iter(&self) -> impl Iterator<Item = &Field> {
+        let fields: &[Field] = match self {
+            Fields::Named(fields_named) => &fields_named.named,
+            Fields::Unnamed(fields_unnamed) => &fields_unnamed.unnamed,
+            Fields::Unit => &[],
+        };
+        fields.iter()
+    }
+}
+
 impl Variant {
     pub(crate) fn discriminant_lit(&self) -> syn::Lit {
         Lit::Int(LitInt::new(
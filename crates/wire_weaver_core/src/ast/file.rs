@@ -1,5 +1,6 @@
 use crate::ast::item::Item;
-use crate::ast::syn_convert::{SynConversionError, SynConversionWarning};
+use crate::ast::path::Path;
+use crate::ast::syn_convert::{take_large_payload_attr, SynConversionError, SynConversionWarning};
 use crate::ast::version::Version;
 use std::path::PathBuf;
 
@@ -22,14 +23,18 @@ pub enum FileSource {
 impl File {
     pub fn from_syn(
         source: FileSource,
-        file: syn::File,
+        mut file: syn::File,
     ) -> Result<(Self, Vec<SynConversionWarning>), Vec<SynConversionError>> {
+        let large_payload = take_large_payload_attr(&mut file.attrs).is_some();
         let mut items = vec![];
         let mut errors = vec![];
         let mut warnings = vec![];
         for item in file.items {
             match Item::from_syn(item) {
-                Ok((Some(item), w)) => {
+                Ok((Some(mut item), w)) => {
+                    if large_payload {
+                        item.set_large_payload();
+                    }
                     items.push(item);
                     warnings.extend(w);
                 }
@@ -42,6 +47,10 @@ impl File {
             }
         }
         if errors.is_empty() {
+            Self::mark_paths(&mut items, Item::contains_ref_types, |path| {
+                path.has_lifetime = true
+            });
+            Self::mark_paths(&mut items, Item::is_bounded, |path| path.is_bounded = true);
             let version = source.file_version();
             Ok((
                 File {
@@ -57,6 +66,35 @@ impl File {
     }
 }
 
+impl File {
+    /// Call `mark` on all the paths to items for which `is_marked` is true, until no more items are marked.
+    /// Used to propagate properties through paths, e.g. items containing borrowed data (str, bytes) are generated
+    /// with a lifetime in no_alloc mode, so do items referring to them, directly or through other items.
+    fn mark_paths(items: &mut [Item], is_marked: impl Fn(&Item) -> bool, mark: impl Fn(&mut Path)) {
+        let mut marked_items: Vec<String> = vec![];
+        loop {
+            for item in items.iter_mut() {
+                item.visit_paths_mut(&mut |path| {
+                    if let Some(ident) = path.segments.last() {
+                        if marked_items.contains(&ident.sym) {
+                            mark(path);
+                        }
+                    }
+                });
+            }
+            let marked = marked_items.len();
+            marked_items = items
+                .iter()
+                .filter(|item| is_marked(item))
+                .map(|item| item.ident().sym.clone())
+                .collect();
+            if marked_items.len() == marked {
+                break;
+            }
+        }
+    }
+}
+
 impl FileSource {
     pub fn file_version(&self) -> Version {
         Version { major: 0, minor: 1 }
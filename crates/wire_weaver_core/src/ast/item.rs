@@ -1,7 +1,9 @@
 use crate::ast::data::{Field, Fields, FieldsNamed, FieldsUnnamed, Variant};
 use crate::ast::ident::Ident;
+use crate::ast::path::Path;
 use crate::ast::syn_convert::{
-    collect_unknown_attributes, take_final_attr, take_since_attr, SynConversionError,
+    collect_unknown_attributes, take_final_attr, take_large_payload_attr,
+    take_max_encoded_size_attr, take_repr_attr, take_since_attr, SynConversionError,
     SynConversionWarning,
 };
 use crate::ast::ty::Type;
@@ -18,6 +20,10 @@ pub struct ItemStruct {
     // attrs
     // generics
     pub is_final: bool,
+    /// Use 32 bit lengths and sizes, set with `#[large_payload]` on the item or `#![large_payload]` on the file
+    pub large_payload: bool,
+    /// `#[max_encoded_size]` requires all fields to be bounded, MAX_ENCODED_SIZE is generated for all bounded items anyway
+    pub require_max_size: bool,
     pub ident: Ident,
     pub fields: Vec<Field>,
 }
@@ -27,10 +33,42 @@ pub struct ItemEnum {
     // attrs
     // generics
     pub is_final: bool,
+    /// Use 32 bit lengths and sizes, set with `#[large_payload]` on the item or `#![large_payload]` on the file
+    pub large_payload: bool,
+    /// `#[max_encoded_size]` requires all fields to be bounded, MAX_ENCODED_SIZE is generated for all bounded items anyway
+    pub require_max_size: bool,
     pub ident: Ident,
+    pub repr: Repr,
     pub variants: Vec<Variant>,
 }
 
+/// Discriminant encoding, `#[repr(vlu16n)]` is used if not specified.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Repr {
+    Vlu16N,
+    Vlu32N,
+    Vlu64N,
+}
+
+impl Repr {
+    pub(crate) fn from_ident(ident: &str) -> Option<Self> {
+        match ident {
+            "vlu16n" => Some(Repr::Vlu16N),
+            "vlu32n" => Some(Repr::Vlu32N),
+            "vlu64n" => Some(Repr::Vlu64N),
+            _ => None,
+        }
+    }
+
+    pub fn max_discriminant(&self) -> u64 {
+        match self {
+            Repr::Vlu16N => u16::MAX as u64,
+            Repr::Vlu32N => u32::MAX as u64,
+            Repr::Vlu64N => u64::MAX,
+        }
+    }
+}
+
 impl Item {
     pub(crate) fn from_syn(
         item: syn::Item,
@@ -55,6 +93,59 @@ impl Item {
             _ => Ok((None, vec![SynConversionWarning::UnknownFileItem])),
         }
     }
+
+    pub fn ident(&self) -> &Ident {
+        match self {
+            Item::Enum(item_enum) => &item_enum.ident,
+            Item::Struct(item_struct) => &item_struct.ident,
+        }
+    }
+
+    pub fn contains_ref_types(&self) -> bool {
+        match self {
+            Item::Enum(item_enum) => item_enum.contains_ref_types(),
+            Item::Struct(item_struct) => item_struct.contains_ref_types(),
+        }
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        match self {
+            Item::Enum(item_enum) => item_enum.is_bounded(),
+            Item::Struct(item_struct) => item_struct.is_bounded(),
+        }
+    }
+
+    /// Call `f` on all the paths to other items in all the fields.
+    pub(crate) fn visit_paths_mut(&mut self, f: &mut impl FnMut(&mut Path)) {
+        match self {
+            Item::Enum(item_enum) => {
+                for variant in &mut item_enum.variants {
+                    match &mut variant.fields {
+                        Fields::Named(fields_named) => fields_named
+                            .named
+                            .iter_mut()
+                            .for_each(|field| field.ty.visit_paths_mut(f)),
+                        Fields::Unnamed(fields_unnamed) => fields_unnamed
+                            .unnamed
+                            .iter_mut()
+                            .for_each(|field| field.ty.visit_paths_mut(f)),
+                        Fields::Unit => {}
+                    }
+                }
+            }
+            Item::Struct(item_struct) => item_struct
+                .fields
+                .iter_mut()
+                .for_each(|field| field.ty.visit_paths_mut(f)),
+        }
+    }
+
+    pub(crate) fn set_large_payload(&mut self) {
+        match self {
+            Item::Enum(item_enum) => item_enum.large_payload = true,
+            Item::Struct(item_struct) => item_struct.large_payload = true,
+        }
+    }
 }
 
 impl ItemStruct {
@@ -77,11 +168,16 @@ impl ItemStruct {
             };
         }
         if errors.is_empty() {
+            let is_final = take_final_attr(&mut item_struct.attrs).is_some();
+            let large_payload = take_large_payload_attr(&mut item_struct.attrs).is_some();
+            let require_max_size = take_max_encoded_size_attr(&mut item_struct.attrs).is_some();
             collect_unknown_attributes(&mut item_struct.attrs, &mut warnings);
             Ok((
                 ItemStruct {
                     ident: item_struct.ident.into(),
-                    is_final: take_final_attr(&mut item_struct.attrs).is_some(),
+                    is_final,
+                    large_payload,
+                    require_max_size,
                     fields,
                 },
                 warnings,
@@ -91,6 +187,11 @@ impl ItemStruct {
         }
     }
 
+    /// All fields have an upper bound on their size, so MAX_ENCODED_SIZE can be generated.
+    pub fn is_bounded(&self) -> bool {
+        self.fields.iter().all(|f| f.ty.is_bounded())
+    }
+
     pub fn contains_ref_types(&self) -> bool {
         for f in &self.fields {
             if f.ty.is_ref() {
@@ -117,6 +218,25 @@ impl ItemEnum {
         false
     }
 
+    /// All fields of all variants have an upper bound on their size, so MAX_ENCODED_SIZE can be generated.
+    pub fn is_bounded(&self) -> bool {
+        self.variants.iter().all(|variant| match &variant.fields {
+            Fields::Named(fields_named) => fields_named.named.iter().all(|f| f.ty.is_bounded()),
+            Fields::Unnamed(fields_unnamed) => {
+                fields_unnamed.unnamed.iter().all(|f| f.ty.is_bounded())
+            }
+            Fields::Unit => true,
+        })
+    }
+
+    pub fn contains_ref_types(&self) -> bool {
+        self.variants.iter().any(|variant| match &variant.fields {
+            Fields::Named(fields_named) => fields_named.named.iter().any(|f| f.ty.is_ref()),
+            Fields::Unnamed(fields_unnamed) => fields_unnamed.unnamed.iter().any(|f| f.ty.is_ref()),
+            Fields::Unit => false,
+        })
+    }
+
     fn from_syn(
         mut item_enum: syn::ItemEnum,
     ) -> Result<(Self, Vec<SynConversionWarning>), Vec<SynConversionError>> {
@@ -136,14 +256,26 @@ impl ItemEnum {
             });
             collect_unknown_attributes(&mut variant.attrs, &mut warnings);
         }
+        let repr = take_repr_attr(&mut item_enum.attrs, &mut errors).unwrap_or(Repr::Vlu16N);
+        if variants
+            .iter()
+            .any(|v| v.discriminant > repr.max_discriminant())
+        {
+            errors.push(SynConversionError::WrongDiscriminant);
+        }
         if errors.is_empty() {
             let is_final = take_final_attr(&mut item_enum.attrs).is_some();
+            let large_payload = take_large_payload_attr(&mut item_enum.attrs).is_some();
+            let require_max_size = take_max_encoded_size_attr(&mut item_enum.attrs).is_some();
             collect_unknown_attributes(&mut item_enum.attrs, &mut warnings);
             Ok((
                 ItemEnum {
                     ident: item_enum.ident.into(),
+                    repr,
                     variants,
                     is_final,
+                    large_payload,
+                    require_max_size,
                 },
                 warnings,
             ))
@@ -154,9 +286,9 @@ impl ItemEnum {
 
     fn get_discriminant(
         errors: &mut Vec<SynConversionError>,
-        latest_discriminant: &mut u32,
+        latest_discriminant: &mut u64,
         variant: &syn::Variant,
-    ) -> u32 {
+    ) -> u64 {
         variant
             .discriminant
             .as_ref()
@@ -168,11 +300,11 @@ impl ItemEnum {
                         d
                     } else {
                         errors.push(SynConversionError::WrongDiscriminant);
-                        u32::MAX
+                        u64::MAX
                     }
                 } else {
                     errors.push(SynConversionError::WrongDiscriminant);
-                    u32::MAX
+                    u64::MAX
                 }
             })
             .unwrap_or_else(|| {
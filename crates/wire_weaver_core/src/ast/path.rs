@@ -4,12 +4,18 @@ use crate::ast::ident::Ident;
 pub struct Path {
     pub segments: Vec<Ident>,
     // arguments
+    /// Referenced item contains borrowed data and is generated with `<'i>` in no_alloc mode.
+    pub has_lifetime: bool,
+    /// Referenced item only has fields with an upper bound on their size.
+    pub is_bounded: bool,
 }
 
 impl Path {
     pub fn new_ident(ident: Ident) -> Self {
         Path {
             segments: vec![ident],
+            has_lifetime: false,
+            is_bounded: false,
         }
     }
 }
@@ -1,6 +1,8 @@
+use crate::ast::item::Repr;
+use crate::ast::ty::VecEncoding;
 use crate::ast::value::Value;
 use crate::Version;
-use syn::{Expr, Lit, Meta};
+use syn::{Expr, Lit, LitInt, Meta};
 
 /// Take `#[id = integer]` attribute and return the number
 pub(crate) fn take_id_attr(attrs: &mut Vec<syn::Attribute>) -> Option<u32> {
@@ -55,6 +57,108 @@ pub(crate) fn take_default_attr(
     }
 }
 
+/// Take `#[repr(vlu16n)]`, `#[repr(vlu32n)]` or `#[repr(vlu64n)]` attribute
+pub(crate) fn take_repr_attr(
+    attrs: &mut Vec<syn::Attribute>,
+    errors: &mut Vec<SynConversionError>,
+) -> Option<Repr> {
+    let (attr_idx, _) = attrs
+        .iter()
+        .enumerate()
+        .find(|(_, a)| a.path().is_ident("repr"))?;
+    let attr = attrs.remove(attr_idx);
+    let Ok(ident) = attr.parse_args::<syn::Ident>() else {
+        errors.push(SynConversionError::WrongReprAttr(
+            "Expected repr(vlu16n), repr(vlu32n) or repr(vlu64n)".into(),
+        ));
+        return None;
+    };
+    let repr = Repr::from_ident(ident.to_string().as_str());
+    if repr.is_none() {
+        errors.push(SynConversionError::WrongReprAttr(format!(
+            "Unsupported repr: {ident}"
+        )));
+    }
+    repr
+}
+
+/// Take `#[large_payload]` or `#![large_payload]` attribute
+pub(crate) fn take_large_payload_attr(attrs: &mut Vec<syn::Attribute>) -> Option<()> {
+    let (attr_idx, _) = attrs
+        .iter()
+        .enumerate()
+        .find(|(_, a)| a.path().is_ident("large_payload"))?;
+    let _attr = attrs.remove(attr_idx);
+    Some(())
+}
+
+/// Take `#[max_encoded_size]` attribute
+pub(crate) fn take_max_encoded_size_attr(attrs: &mut Vec<syn::Attribute>) -> Option<()> {
+    let (attr_idx, _) = attrs
+        .iter()
+        .enumerate()
+        .find(|(_, a)| a.path().is_ident("max_encoded_size"))?;
+    let _attr = attrs.remove(attr_idx);
+    Some(())
+}
+
+/// Take `#[delta]` attribute
+pub(crate) fn take_delta_attr(attrs: &mut Vec<syn::Attribute>) -> Option<()> {
+    let (attr_idx, _) = attrs
+        .iter()
+        .enumerate()
+        .find(|(_, a)| a.path().is_ident("delta"))?;
+    let _attr = attrs.remove(attr_idx);
+    Some(())
+}
+
+/// Take `#[quantize(min = -1.0, max = 1.0, bits = 12)]` attribute
+pub(crate) fn take_quantize_attr(
+    attrs: &mut Vec<syn::Attribute>,
+    errors: &mut Vec<SynConversionError>,
+) -> Option<VecEncoding> {
+    let (attr_idx, _) = attrs
+        .iter()
+        .enumerate()
+        .find(|(_, a)| a.path().is_ident("quantize"))?;
+    let attr = attrs.remove(attr_idx);
+    let (mut min, mut max, mut bits) = (None, None, None);
+    let result = attr.parse_nested_meta(|meta| {
+        let input = meta.value()?;
+        if meta.path.is_ident("bits") {
+            bits = Some(input.parse::<LitInt>()?.base10_parse::<u8>()?);
+            return Ok(());
+        }
+        let is_negative = input.parse::<Option<syn::Token![-]>>()?.is_some();
+        let val: f32 = match input.parse::<Lit>()? {
+            Lit::Float(lit_float) => lit_float.base10_parse()?,
+            Lit::Int(lit_int) => lit_int.base10_parse()?,
+            lit => return Err(syn::Error::new(lit.span(), "expected number")),
+        };
+        let val = if is_negative { -val } else { val };
+        if meta.path.is_ident("min") {
+            min = Some(val);
+        } else if meta.path.is_ident("max") {
+            max = Some(val);
+        } else {
+            return Err(meta.error("expected min, max or bits"));
+        }
+        Ok(())
+    });
+    let usage = "Expected quantize(min = lit, max = lit, bits = 1..=32)";
+    let (Ok(()), Some(min), Some(max), Some(bits)) = (result, min, max, bits) else {
+        errors.push(SynConversionError::WrongVecEncodingAttr(usage.into()));
+        return None;
+    };
+    if !(1..=32).contains(&bits) || !min.is_finite() || !max.is_finite() || min >= max {
+        errors.push(SynConversionError::WrongVecEncodingAttr(format!(
+            "{usage}, with min < max, got min = {min}, max = {max}, bits = {bits}"
+        )));
+        return None;
+    }
+    Some(VecEncoding::Quantized { min, max, bits })
+}
+
 pub(crate) fn take_final_attr(attrs: &mut Vec<syn::Attribute>) -> Option<()> {
     let (attr_idx, _) = attrs
         .iter()
@@ -85,6 +189,10 @@ pub enum SynConversionWarning {
 #[derive(Debug)]
 pub enum SynConversionError {
     UnknownType,
+    WrongGenericArguments(String),
     WrongDefaultAttr(String),
+    WrongReprAttr(String),
+    WrongVecEncodingAttr(String),
+    WrongBounds(String),
     WrongDiscriminant,
 }
@@ -1,18 +1,25 @@
 use crate::ast::ident::Ident;
 use crate::ast::path::Path;
 use crate::ast::syn_convert::{SynConversionError, SynConversionWarning};
+use std::ops::RangeInclusive;
 
 #[derive(Debug)]
 pub enum Type {
     // Array,
     Bool,
     Discrete(TypeDiscrete),
-    // VariableLength,
+    VariableLength(TypeVariableLength),
     Floating(TypeFloating),
+    /// `q7`, `q15`, `q31`, `q63` or `fixed<i32, 16>`, `shrink_wrap::Fixed` serialized as the underlying integer.
+    Fixed(TypeFixed),
     String,
+    /// `bytes` or `bytes<N>` with at most N bytes, `&'i [u8]` in no_alloc mode.
+    Bytes(Option<u32>),
     Path(Path),
-    // Option(Path),
-    // Result(Path, Path),
+    /// `Vec<T>` of whole byte integers, floats or fixed-point numbers, `RefVec<'i, T>` or one of its compact variants in no_alloc mode.
+    Vec(TypeVec),
+    Option(Box<Type>),
+    Result(Box<Type>, Box<Type>),
 }
 
 #[derive(Debug)]
@@ -20,7 +27,195 @@ pub struct TypeDiscrete {
     pub is_signed: bool,
     pub bits: u16,
     // unit
-    // bounds
+    /// Allowed ranges and values, e.g. `u8<{0..=8}, 12, 16>`, any value is allowed if empty
+    pub bounds: Vec<RangeInclusive<i128>>,
+}
+
+/// Integer literal or a negated one.
+fn int_value(expr: &syn::Expr) -> Result<i128, SynConversionError> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(lit_int),
+            ..
+        }) => lit_int
+            .base10_parse()
+            .map_err(|e| SynConversionError::WrongBounds(format!("{e}"))),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => Ok(-int_value(expr)?),
+        syn::Expr::Paren(syn::ExprParen { expr, .. }) => int_value(expr),
+        _ => Err(SynConversionError::WrongBounds(
+            "Expected integer literal".into(),
+        )),
+    }
+}
+
+/// Discrete number encoded with a variable number of bytes, e.g. `leb<u32>`.
+#[derive(Debug)]
+pub struct TypeVariableLength {
+    pub encoding: VariableLengthEncoding,
+    pub discrete: TypeDiscrete,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VariableLengthEncoding {
+    /// LEB128, zigzag encoded if signed
+    Leb,
+    /// Nibble based Vlu16N, Vlu32N or Vlu64N, zigzag encoded if signed
+    Nib,
+}
+
+impl VariableLengthEncoding {
+    fn supported_bits(&self) -> &'static [u16] {
+        match self {
+            VariableLengthEncoding::Leb => &[16, 32, 64, 128],
+            VariableLengthEncoding::Nib => &[16, 32, 64],
+        }
+    }
+}
+
+impl TypeDiscrete {
+    /// Parse `u1` to `u64`, `i2` to `i64`, `u128` and `i128`.
+    fn from_ident(ident: &str) -> Option<Self> {
+        let (is_signed, bits) = if let Some(bits) = ident.strip_prefix('u') {
+            (false, bits)
+        } else {
+            (true, ident.strip_prefix('i')?)
+        };
+        let bits: u16 = bits.parse().ok()?;
+        let min_bits = if is_signed { 2 } else { 1 };
+        if (min_bits..=64).contains(&bits) || bits == 128 {
+            Some(TypeDiscrete {
+                is_signed,
+                bits,
+                bounds: vec![],
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Smallest and largest value that fits into the number, regardless of bounds.
+    fn limits(&self) -> RangeInclusive<i128> {
+        match (self.is_signed, self.bits) {
+            (false, 128) => 0..=i128::MAX,
+            (true, 128) => i128::MIN..=i128::MAX,
+            (false, bits) => 0..=(1i128 << bits) - 1,
+            (true, bits) => -(1i128 << (bits - 1))..=(1i128 << (bits - 1)) - 1,
+        }
+    }
+
+    /// Whether `val` is one of the allowed values, always true for numbers without bounds.
+    pub fn is_allowed(&self, val: i128) -> bool {
+        self.bounds.is_empty() || self.bounds.iter().any(|range| range.contains(&val))
+    }
+
+    /// Parse `<{1..=512}>` or `<{0..=8}, 12, 16>`, ranges must be inside braces, so that they are valid Rust syntax.
+    fn bounds(&mut self, arguments: &syn::PathArguments) -> Result<(), SynConversionError> {
+        let arguments = match arguments {
+            syn::PathArguments::None => return Ok(()),
+            syn::PathArguments::AngleBracketed(arguments) => arguments,
+            syn::PathArguments::Parenthesized(_) => {
+                return Err(SynConversionError::WrongBounds(
+                    "Expected u8<{0..=8}, 12, 16> or similar".into(),
+                ))
+            }
+        };
+        for arg in &arguments.args {
+            let syn::GenericArgument::Const(expr) = arg else {
+                return Err(SynConversionError::WrongBounds(
+                    "Expected ranges and values".into(),
+                ));
+            };
+            let range = self.bound(expr)?;
+            if range.is_empty()
+                || !self.limits().contains(range.start())
+                || !self.limits().contains(range.end())
+            {
+                return Err(SynConversionError::WrongBounds(format!(
+                    "{range:?} is empty or does not fit into {}{}",
+                    if self.is_signed { 'i' } else { 'u' },
+                    self.bits
+                )));
+            }
+            self.bounds.push(range);
+        }
+        Ok(())
+    }
+
+    /// Convert `{1..=512}`, `{-8..8}`, `{..=8}`, `12` or `{-12}` to an inclusive range.
+    fn bound(&self, expr: &syn::Expr) -> Result<RangeInclusive<i128>, SynConversionError> {
+        match expr {
+            syn::Expr::Block(syn::ExprBlock { block, .. }) => match block.stmts.as_slice() {
+                [syn::Stmt::Expr(expr, None)] => self.bound(expr),
+                _ => Err(SynConversionError::WrongBounds(
+                    "Expected one range or value in braces".into(),
+                )),
+            },
+            syn::Expr::Range(range) => {
+                let start = match &range.start {
+                    Some(start) => int_value(start)?,
+                    None => *self.limits().start(),
+                };
+                let end = match (&range.end, &range.limits) {
+                    (Some(end), syn::RangeLimits::Closed(_)) => int_value(end)?,
+                    (Some(end), syn::RangeLimits::HalfOpen(_)) => int_value(end)? - 1,
+                    (None, _) => *self.limits().end(),
+                };
+                Ok(start..=end)
+            }
+            expr => {
+                let val = int_value(expr)?;
+                Ok(val..=val)
+            }
+        }
+    }
+
+    /// Whether the number is stored in the whole number of bytes.
+    pub fn is_byte_aligned(&self) -> bool {
+        [8, 16, 32, 64, 128].contains(&self.bits)
+    }
+}
+
+/// Q-format number with `frac_bits` out of `discrete.bits` after the binary point.
+#[derive(Debug)]
+pub struct TypeFixed {
+    pub discrete: TypeDiscrete,
+    pub frac_bits: u8,
+}
+
+impl TypeFixed {
+    /// Parse `q7`, `q15`, `q31` and `q63`, signed numbers with all but the sign bit after the binary point.
+    fn from_ident(ident: &str) -> Option<Self> {
+        let frac_bits: u8 = ident.strip_prefix('q')?.parse().ok()?;
+        let bits = frac_bits as u16 + 1;
+        [8, 16, 32, 64].contains(&bits).then_some(TypeFixed {
+            discrete: TypeDiscrete {
+                is_signed: true,
+                bits,
+                bounds: vec![],
+            },
+            frac_bits,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct TypeVec {
+    pub element: Box<Type>,
+    pub encoding: VecEncoding,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VecEncoding {
+    /// Elements as is, same as RefVec
+    Plain,
+    /// `#[delta]`: first element as is, then differences to the previous one as zigzag Vlu64N
+    Delta,
+    /// `#[quantize(min = -1.0, max = 1.0, bits = 12)]`: f32 values in the range as `bits` wide integers
+    Quantized { min: f32, max: f32, bits: u8 },
 }
 
 #[derive(Debug)]
@@ -38,31 +233,48 @@ impl Type {
                 if type_path.path.segments.len() == 1 {
                     let path_segment = type_path.path.segments.first().unwrap();
                     let ident = path_segment.ident.to_string();
-                    if ident.starts_with('f') {
+                    if ident == "fixed" {
+                        Self::fixed(&path_segment.arguments)
+                    } else if let Some(fixed) = TypeFixed::from_ident(&ident) {
+                        Ok((Type::Fixed(fixed), vec![]))
+                    } else if ident.starts_with('f') {
                         let bits: u16 = ident.strip_prefix('f').unwrap().parse().unwrap();
                         Ok((Type::Floating(TypeFloating { bits }), vec![]))
-                    } else if ident.starts_with('u') {
-                        let bits: u16 = ident.strip_prefix('u').unwrap().parse().unwrap();
-                        Ok((
-                            Type::Discrete(TypeDiscrete {
-                                is_signed: false,
-                                bits,
-                            }),
-                            vec![],
-                        ))
-                    } else if ident.starts_with('i') {
-                        let bits: u16 = ident.strip_prefix('i').unwrap().parse().unwrap();
-                        Ok((
-                            Type::Discrete(TypeDiscrete {
-                                is_signed: true,
-                                bits,
-                            }),
-                            vec![],
-                        ))
+                    } else if let Some(mut discrete) = TypeDiscrete::from_ident(&ident) {
+                        discrete
+                            .bounds(&path_segment.arguments)
+                            .map_err(|e| vec![e])?;
+                        Ok((Type::Discrete(discrete), vec![]))
                     } else if ident == "bool" {
                         Ok((Type::Bool, vec![]))
                     } else if ident == "String" {
                         Ok((Type::String, vec![]))
+                    } else if ident == "bytes" {
+                        Self::bytes(&path_segment.arguments)
+                    } else if ident == "leb" {
+                        Self::variable_length(VariableLengthEncoding::Leb, &path_segment.arguments)
+                    } else if ident == "nib" {
+                        Self::variable_length(VariableLengthEncoding::Nib, &path_segment.arguments)
+                    } else if ident == "Vec" {
+                        Self::vec(&path_segment.arguments)
+                    } else if ident == "Option" {
+                        let (mut args, warnings) = Self::generic_args(&path_segment.arguments)?;
+                        if args.len() != 1 {
+                            return Err(vec![SynConversionError::WrongGenericArguments(
+                                "Option<T> expects exactly one type argument".into(),
+                            )]);
+                        }
+                        Ok((Type::Option(Box::new(args.remove(0))), warnings))
+                    } else if ident == "Result" {
+                        let (mut args, warnings) = Self::generic_args(&path_segment.arguments)?;
+                        if args.len() != 2 {
+                            return Err(vec![SynConversionError::WrongGenericArguments(
+                                "Result<T, E> expects exactly two type arguments".into(),
+                            )]);
+                        }
+                        let err_ty = args.remove(1);
+                        let ok_ty = args.remove(0);
+                        Ok((Type::Result(Box::new(ok_ty), Box::new(err_ty)), warnings))
                     } else {
                         Ok((Type::Path(Path::new_ident(Ident::new(ident))), vec![]))
                     }
@@ -73,4 +285,208 @@ impl Type {
             _ => Err(vec![SynConversionError::UnknownType]),
         }
     }
+
+    /// Convert `leb<u32>`, `nib<u32>` and similar types.
+    fn variable_length(
+        encoding: VariableLengthEncoding,
+        arguments: &syn::PathArguments,
+    ) -> Result<(Self, Vec<SynConversionWarning>), Vec<SynConversionError>> {
+        let (mut args, warnings) = Self::generic_args(arguments)?;
+        if args.len() != 1 {
+            return Err(vec![SynConversionError::WrongGenericArguments(
+                "Variable length number expects exactly one type argument".into(),
+            )]);
+        }
+        match args.remove(0) {
+            Type::Discrete(discrete)
+                if encoding.supported_bits().contains(&discrete.bits)
+                    && discrete.bounds.is_empty() =>
+            {
+                Ok((
+                    Type::VariableLength(TypeVariableLength { encoding, discrete }),
+                    warnings,
+                ))
+            }
+            _ => Err(vec![SynConversionError::WrongGenericArguments(format!(
+                "{encoding:?} number expects one of {:?} bit discrete types without bounds",
+                encoding.supported_bits()
+            ))]),
+        }
+    }
+
+    /// Convert `bytes` and `bytes<N>`.
+    fn bytes(
+        arguments: &syn::PathArguments,
+    ) -> Result<(Self, Vec<SynConversionWarning>), Vec<SynConversionError>> {
+        let arguments = match arguments {
+            syn::PathArguments::None => return Ok((Type::Bytes(None), vec![])),
+            syn::PathArguments::AngleBracketed(arguments) => arguments,
+            syn::PathArguments::Parenthesized(_) => {
+                return Err(vec![SynConversionError::WrongGenericArguments(
+                    "Expected bytes or bytes<N>".into(),
+                )]);
+            }
+        };
+        if arguments.args.len() == 1 {
+            if let Some(syn::GenericArgument::Const(syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(lit_int),
+                ..
+            }))) = arguments.args.first()
+            {
+                if let Ok(max_len) = lit_int.base10_parse() {
+                    return Ok((Type::Bytes(Some(max_len)), vec![]));
+                }
+            }
+        }
+        Err(vec![SynConversionError::WrongGenericArguments(
+            "bytes<N> expects maximum length as an integer literal".into(),
+        )])
+    }
+
+    /// Convert `fixed<i32, 16>`.
+    fn fixed(
+        arguments: &syn::PathArguments,
+    ) -> Result<(Self, Vec<SynConversionWarning>), Vec<SynConversionError>> {
+        let error = || {
+            vec![SynConversionError::WrongGenericArguments(
+                "fixed<T, N> expects i8 to i64 or u8 to u64 without bounds and at most T::BITS fractional bits"
+                    .into(),
+            )]
+        };
+        let syn::PathArguments::AngleBracketed(arguments) = arguments else {
+            return Err(error());
+        };
+        let mut args = arguments.args.iter();
+        let (
+            Some(syn::GenericArgument::Type(ty)),
+            Some(syn::GenericArgument::Const(syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(frac_bits),
+                ..
+            }))),
+            None,
+        ) = (args.next(), args.next(), args.next())
+        else {
+            return Err(error());
+        };
+        let (Type::Discrete(discrete), warnings) = Type::from_syn(ty.clone())? else {
+            return Err(error());
+        };
+        if !discrete.bounds.is_empty() {
+            return Err(error());
+        }
+        let frac_bits: u8 = frac_bits.base10_parse().map_err(|_| error())?;
+        if !discrete.is_byte_aligned() || discrete.bits > 64 || frac_bits as u16 > discrete.bits {
+            return Err(error());
+        }
+        Ok((
+            Type::Fixed(TypeFixed {
+                discrete,
+                frac_bits,
+            }),
+            warnings,
+        ))
+    }
+
+    /// Convert `Vec<T>`, numbers are the only supported elements for now.
+    fn vec(
+        arguments: &syn::PathArguments,
+    ) -> Result<(Self, Vec<SynConversionWarning>), Vec<SynConversionError>> {
+        let (mut args, warnings) = Self::generic_args(arguments)?;
+        if args.len() != 1 {
+            return Err(vec![SynConversionError::WrongGenericArguments(
+                "Vec<T> expects exactly one type argument".into(),
+            )]);
+        }
+        let element = args.remove(0);
+        let is_number = match &element {
+            Type::Discrete(discrete) => discrete.is_byte_aligned() && discrete.bounds.is_empty(),
+            Type::Floating(_) | Type::Fixed(_) => true,
+            _ => false,
+        };
+        if !is_number {
+            return Err(vec![SynConversionError::WrongGenericArguments(
+                "Vec<T> expects u8 to u128, i8 to i128 without bounds, f32, f64 or fixed-point elements".into(),
+            )]);
+        }
+        Ok((
+            Type::Vec(TypeVec {
+                element: Box::new(element),
+                encoding: VecEncoding::Plain,
+            }),
+            warnings,
+        ))
+    }
+
+    /// Apply `#[delta]` or `#[quantize(..)]` field attribute, only Vec<integer> and Vec<f32> respectively can use them.
+    pub(crate) fn set_vec_encoding(
+        &mut self,
+        encoding: VecEncoding,
+    ) -> Result<(), SynConversionError> {
+        let Type::Vec(ty_vec) = self else {
+            return Err(SynConversionError::WrongVecEncodingAttr(
+                "#[delta] and #[quantize] can only be used on Vec<T>".into(),
+            ));
+        };
+        let supported = match (encoding, ty_vec.element.as_ref()) {
+            (VecEncoding::Plain, _) => true,
+            (VecEncoding::Delta, Type::Discrete(discrete)) => discrete.bits <= 64,
+            (VecEncoding::Quantized { .. }, Type::Floating(floating)) => floating.bits == 32,
+            _ => false,
+        };
+        if !supported {
+            return Err(SynConversionError::WrongVecEncodingAttr(format!(
+                "{encoding:?} is not supported for Vec<{:?}>, expected Vec<u8..u64>, Vec<i8..i64> for delta and Vec<f32> for quantize",
+                ty_vec.element
+            )));
+        }
+        ty_vec.encoding = encoding;
+        Ok(())
+    }
+
+    /// Call `f` on all the paths to other items, including ones inside Option and Result.
+    pub(crate) fn visit_paths_mut(&mut self, f: &mut impl FnMut(&mut Path)) {
+        match self {
+            Type::Path(path) => f(path),
+            Type::Option(some_ty) => some_ty.visit_paths_mut(f),
+            Type::Result(ok_ty, err_ty) => {
+                ok_ty.visit_paths_mut(f);
+                err_ty.visit_paths_mut(f);
+            }
+            _ => {}
+        }
+    }
+
+    /// Convert all type arguments of a path segment, e.g. `T` and `E` in `Result<T, E>`.
+    fn generic_args(
+        arguments: &syn::PathArguments,
+    ) -> Result<(Vec<Self>, Vec<SynConversionWarning>), Vec<SynConversionError>> {
+        let syn::PathArguments::AngleBracketed(arguments) = arguments else {
+            return Err(vec![SynConversionError::WrongGenericArguments(
+                "Expected <...>".into(),
+            )]);
+        };
+        let mut types = vec![];
+        let mut errors = vec![];
+        let mut warnings = vec![];
+        for arg in &arguments.args {
+            let syn::GenericArgument::Type(ty) = arg else {
+                errors.push(SynConversionError::WrongGenericArguments(
+                    "Expected type argument".into(),
+                ));
+                continue;
+            };
+            match Type::from_syn(ty.clone()) {
+                Ok((ty, w)) => {
+                    types.push(ty);
+                    warnings.extend(w);
+                }
+                Err(e) => errors.extend(e),
+            }
+        }
+        if errors.is_empty() {
+            Ok((types, warnings))
+        } else {
+            Err(errors)
+        }
+    }
 }
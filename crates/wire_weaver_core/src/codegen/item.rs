@@ -1,5 +1,6 @@
 use crate::ast::data::{Field, Fields, Variant};
-use crate::ast::item::{ItemEnum, ItemStruct};
+use crate::ast::item::{ItemEnum, ItemStruct, Repr};
+use crate::codegen::ty::vlu_nibbles;
 use proc_macro2::{Ident, Span, TokenStream};
 use quote::{quote, ToTokens, TokenStreamExt};
 use syn::{Lit, LitInt};
@@ -39,6 +40,62 @@ pub fn struct_def(item_struct: &ItemStruct, no_alloc: bool) -> TokenStream {
     ts
 }
 
+/// `MAX_ENCODED_SIZE` const for bounded structs, compile error if it was required with `#[max_encoded_size]`.
+pub fn struct_max_size(item_struct: &ItemStruct, no_alloc: bool) -> TokenStream {
+    let ident: Ident = (&item_struct.ident).into();
+    if !item_struct.is_bounded() {
+        return unbounded_error(
+            &ident,
+            item_struct.require_max_size,
+            item_struct.fields.iter(),
+        );
+    }
+    let (bits, rev_nibbles) = fields_max_size(item_struct.fields.iter());
+    let lifetime = if no_alloc && item_struct.contains_ref_types() {
+        quote!(<'i>)
+    } else {
+        quote!()
+    };
+    max_size_const(ident, lifetime, bits, rev_nibbles)
+}
+
+fn fields_max_size<'a>(fields: impl Iterator<Item = &'a Field>) -> (TokenStream, TokenStream) {
+    let (bits, rev_nibbles): (Vec<_>, Vec<_>) = fields.map(|f| f.ty.max_size()).unzip();
+    (quote!(0 #(+ #bits)*), quote!(0 #(+ #rev_nibbles)*))
+}
+
+fn unbounded_error<'a>(
+    ident: &Ident,
+    required: bool,
+    mut fields: impl Iterator<Item = &'a Field>,
+) -> TokenStream {
+    if !required {
+        return quote!();
+    }
+    let field = fields
+        .find(|f| !f.ty.is_bounded())
+        .map(|f| f.ident.sym.as_str())
+        .unwrap_or_default();
+    let msg = format!(
+        "{ident} has no MAX_ENCODED_SIZE: field `{field}` is unbounded (String, bytes without max length or a type containing them)"
+    );
+    quote!(compile_error!(#msg);)
+}
+
+fn max_size_const(
+    ident: Ident,
+    lifetime: TokenStream,
+    bits: TokenStream,
+    rev_nibbles: TokenStream,
+) -> TokenStream {
+    quote! {
+        impl #lifetime #ident #lifetime {
+            /// Upper bound of the serialized size in bytes.
+            pub const MAX_ENCODED_SIZE: usize = shrink_wrap::max_size::bytes(#bits, #rev_nibbles);
+        }
+    }
+}
+
 struct CGStructSer<'a> {
     item_struct: &'a ItemStruct,
     no_alloc: bool,
@@ -59,26 +116,48 @@ pub fn struct_serdes(item_struct: &ItemStruct, no_alloc: bool) -> TokenStream {
         item_struct,
         no_alloc,
     };
-    // let lifetime = if no_alloc && item_struct.contains_ref_types() {
-    //     quote!(<'i>)
-    // } else {
-    //     quote!()
-    // };
-    serdes(struct_name, struct_ser, struct_des)
+    let lifetime = no_alloc && item_struct.contains_ref_types();
+    // structs are always evolvable, so their size is not known in advance
+    let element_size = quote!(shrink_wrap::ElementSize::Unsized);
+    serdes(
+        struct_name,
+        lifetime,
+        element_size,
+        item_struct.large_payload,
+        struct_ser,
+        struct_des,
+    )
 }
 
-fn serdes(ty_name: Ident, ser: impl ToTokens, des: impl ToTokens) -> TokenStream {
-    let lifetime = quote!();
+fn serdes(
+    ty_name: Ident,
+    lifetime: bool,
+    element_size: TokenStream,
+    large_payload: bool,
+    ser: impl ToTokens,
+    des: impl ToTokens,
+) -> TokenStream {
+    let lifetime = if lifetime { quote!(<'i>) } else { quote!() };
+    let ty_name_str = ty_name.to_string();
     quote! {
+        impl #lifetime shrink_wrap::ElementSizeOf for #ty_name #lifetime {
+            const ELEMENT_SIZE: shrink_wrap::ElementSize = #element_size;
+        }
+
         impl #lifetime shrink_wrap::SerializeShrinkWrap for #ty_name #lifetime {
             fn ser_shrink_wrap(&self, wr: &mut shrink_wrap::BufWriter) -> Result<(), shrink_wrap::Error> {
-                #ser
+                wr.with_large_payload(#large_payload, |wr| {
+                    #ser
+                })
             }
         }
 
         impl<'i> shrink_wrap::DeserializeShrinkWrap<'i> for #ty_name #lifetime {
             fn des_shrink_wrap<'di>(rd: &'di mut shrink_wrap::BufReader<'i>, _element_size: shrink_wrap::ElementSize) -> Result<Self, shrink_wrap::Error> {
-                #des
+                rd.with_large_payload(#large_payload, |rd| {
+                    #des
+                })
+                .map_err(|e| rd.push_error_path(e, #ty_name_str))
             }
         }
     }
@@ -86,10 +165,27 @@ fn serdes(ty_name: Ident, ser: impl ToTokens, des: impl ToTokens) -> TokenStream
 
 impl<'a> ToTokens for CGStructSer<'a> {
     fn to_tokens(&self, tokens: &mut TokenStream) {
-        for struct_field in &self.item_struct.fields {
+        let fields = &self.item_struct.fields;
+        // trailing fields with defaults can be dropped if they do not fit, see BufWriter::set_truncate_evolvable
+        let evolvable_from = fields.len()
+            - fields
+                .iter()
+                .rev()
+                .take_while(|f| f.default.is_some())
+                .count();
+        for (idx, struct_field) in fields.iter().enumerate() {
             let field_name: Ident = (&struct_field.ident).into();
             let field_path = quote!(self.#field_name);
-            tokens.append_all(struct_field.ty.buf_write(field_path, false, self.no_alloc));
+            let write = struct_field.ty.buf_write(field_path, false, self.no_alloc);
+            if idx < evolvable_from {
+                tokens.append_all(write);
+            } else {
+                tokens.append_all(quote! {
+                    if !wr.write_evolvable(|wr| { #write Ok(()) })? {
+                        return Ok(());
+                    }
+                });
+            }
         }
         tokens.append_all(quote! {
             Ok(())
@@ -103,13 +199,16 @@ impl<'a> ToTokens for CGStructDes<'a> {
         for struct_field in &self.item_struct.fields {
             let field_name: Ident = (&struct_field.ident).into();
             field_names.push(field_name.clone());
-            let handle_eob = struct_field.handle_eob();
-            // let x = rd.read_()?; or let x = rd.read_().unwrap_or(default);
-            tokens.append_all(
-                struct_field
-                    .ty
-                    .buf_read(field_name, handle_eob, self.no_alloc),
-            );
+            let path = struct_field.ident.sym.as_str();
+            let handle_eob = struct_field.handle_eob(path);
+            let handle_err = struct_field.handle_err(path);
+            // let x = rd.read_().map_err(..)?; or let x = rd.read_().unwrap_or(default);
+            tokens.append_all(struct_field.ty.buf_read(
+                field_name,
+                handle_eob,
+                handle_err,
+                self.no_alloc,
+            ));
         }
         let struct_name: Ident = (&self.item_struct.ident).into();
         tokens.append_all(quote! {
@@ -120,22 +219,46 @@ impl<'a> ToTokens for CGStructDes<'a> {
     }
 }
 
+impl Repr {
+    fn std_repr(&self) -> Ident {
+        let ty = match self {
+            Repr::Vlu16N => "u16",
+            Repr::Vlu32N => "u32",
+            Repr::Vlu64N => "u64",
+        };
+        Ident::new(ty, Span::call_site())
+    }
+
+    fn fn_name(&self, prefix: &str) -> Ident {
+        let bits = match self {
+            Repr::Vlu16N => 16,
+            Repr::Vlu32N => 32,
+            Repr::Vlu64N => 64,
+        };
+        Ident::new(format!("{prefix}_vlu{bits}n").as_str(), Span::call_site())
+    }
+}
+
 pub fn enum_def(item_enum: &ItemEnum, no_alloc: bool) -> TokenStream {
     let enum_name: Ident = (&item_enum.ident).into();
     let variants = CGEnumFieldsDef {
         variants: &item_enum.variants,
         no_alloc,
     };
-    let lifetime = if false { quote!(<'i>) } else { quote!() };
-    // TODO: respect specified repr
+    let lifetime = if no_alloc && item_enum.contains_ref_types() {
+        quote!(<'i>)
+    } else {
+        quote!()
+    };
+    let repr = item_enum.repr.std_repr();
     let ts = quote! {
         #[derive(Debug)]
-        #[repr(u16)]
+        #[repr(#repr)]
         pub enum #enum_name #lifetime { #variants }
 
-        impl #enum_name {
-            pub fn discriminant(&self) -> u16 {
-                unsafe { *<*const _>::from(self).cast::<u16>() }
+        impl #lifetime #enum_name #lifetime {
+            pub fn discriminant(&self) -> #repr {
+                unsafe { *<*const _>::from(self).cast::<#repr>() }
             }
         }
     };
@@ -191,12 +314,55 @@ pub fn enum_serdes(item_enum: &ItemEnum, no_alloc: bool) -> TokenStream {
         item_enum,
         no_alloc,
     };
-    // let lifetime = if no_alloc && item_struct.contains_ref_types() {
-    //     quote!(<'i>)
-    // } else {
-    //     quote!()
-    // };
-    serdes(enum_name, enum_ser, enum_des)
+    let lifetime = no_alloc && item_enum.contains_ref_types();
+    // discriminant is self describing, data variants are evolvable and require size to be written
+    let element_size = if item_enum.contains_data_fields() {
+        quote!(shrink_wrap::ElementSize::Unsized)
+    } else {
+        quote!(shrink_wrap::ElementSize::UnsizedSelfDescribing)
+    };
+    serdes(
+        enum_name,
+        lifetime,
+        element_size,
+        item_enum.large_payload,
+        enum_ser,
+        enum_des,
+    )
+}
+
+/// `MAX_ENCODED_SIZE` const for bounded enums: discriminant and the largest variant.
+pub fn enum_max_size(item_enum: &ItemEnum, no_alloc: bool) -> TokenStream {
+    let ident: Ident = (&item_enum.ident).into();
+    if !item_enum.is_bounded() {
+        let fields = item_enum.variants.iter().flat_map(|v| v.fields.iter());
+        return unbounded_error(&ident, item_enum.require_max_size, fields);
+    }
+    let max_discriminant = item_enum
+        .variants
+        .iter()
+        .map(|v| v.discriminant)
+        .max()
+        .unwrap_or(0);
+    let discriminant_bits = 3 + 4 * vlu_nibbles(max_discriminant);
+    let mut bits = quote!(0);
+    let mut rev_nibbles = quote!(0);
+    for variant in &item_enum.variants {
+        let (variant_bits, variant_rev_nibbles) = fields_max_size(variant.fields.iter());
+        bits = quote!(shrink_wrap::max_size::max(#bits, #variant_bits));
+        rev_nibbles = quote!(shrink_wrap::max_size::max(#rev_nibbles, #variant_rev_nibbles));
+    }
+    let lifetime = if no_alloc && item_enum.contains_ref_types() {
+        quote!(<'i>)
+    } else {
+        quote!()
+    };
+    max_size_const(
+        ident,
+        lifetime,
+        quote!(#discriminant_bits + #bits),
+        rev_nibbles,
+    )
 }
 
 struct CGEnumSer<'a> {
@@ -215,7 +381,8 @@ impl<'a> ToTokens for CGEnumSer<'a> {
         // if self.item_enum.variants.is_empty() {
         //     tokens.append_all(quote!( wr.write_vlu16n(0)?; ));
         // } else {
-        tokens.append_all(quote!( wr.write_vlu16n(self.discriminant())?; ));
+        let write_discriminant = self.item_enum.repr.fn_name("write");
+        tokens.append_all(quote!( wr.#write_discriminant(self.discriminant())?; ));
         // }
 
         // if self.item_enum.is_final && !self.item_enum.contains_data_fields() {
@@ -297,8 +464,9 @@ impl<'a> ToTokens for CGEnumDes<'a> {
         //         _ => { return Err(shrink_wrap::Error::EnumFutureVersionOrMalformedData); }
         //     })
         // };
+        let read_discriminant = self.item_enum.repr.fn_name("read");
         tokens.append_all(quote! {
-            let discriminant = rd.read_vlu16n()?;
+            let discriminant = rd.#read_discriminant()?;
             Ok(match discriminant {
                 #known_variants
                 _ => { return Err(shrink_wrap::Error::EnumFutureVersionOrMalformedData); }
@@ -346,11 +514,14 @@ impl<'a> ToTokens for CGEnumVariantsDes<'a> {
                     for field in &fields_named.named {
                         let field_name: Ident = (&field.ident).into();
                         field_names.push(field_name.clone());
-                        let handle_eob = field.handle_eob();
-                        // let x = rd.read_()?; or let x = rd.read_().unwrap_or(default);
+                        let path = format!("{}.{}", variant.ident.sym, field.ident.sym);
+                        let handle_eob = field.handle_eob(&path);
+                        let handle_err = field.handle_err(&path);
+                        // let x = rd.read_().map_err(..)?; or let x = rd.read_().unwrap_or(default);
                         des_fields.append_all(field.ty.buf_read(
                             field_name,
                             handle_eob,
+                            handle_err,
                             self.no_alloc,
                         ));
                     }
@@ -362,11 +533,14 @@ impl<'a> ToTokens for CGEnumVariantsDes<'a> {
                     for field in &fields_unnamed.unnamed {
                         let field_name: Ident = (&field.ident).into();
                         field_names.push(field_name.clone());
-                        let handle_eob = field.handle_eob();
-                        // let x = rd.read_()?; or let x = rd.read_().unwrap_or(default);
+                        let path = format!("{}.{}", variant.ident.sym, field.ident.sym);
+                        let handle_eob = field.handle_eob(&path);
+                        let handle_err = field.handle_err(&path);
+                        // let x = rd.read_().map_err(..)?; or let x = rd.read_().unwrap_or(default);
                         des_fields.append_all(field.ty.buf_read(
                             field_name,
                             handle_eob,
+                            handle_err,
                             self.no_alloc,
                         ));
                     }
@@ -383,8 +557,9 @@ impl<'a> ToTokens for CGEnumVariantsDes<'a> {
 #[cfg(test)]
 mod tests {
     use crate::ast::data::Field;
+    use crate::ast::file::{File, FileSource};
     use crate::ast::ident::Ident;
-    use crate::ast::item::ItemStruct;
+    use crate::ast::item::{Item, ItemStruct};
     use crate::ast::ty::Type;
     use crate::ast::value::Value;
     use crate::ast::version::Version;
@@ -394,6 +569,8 @@ mod tests {
     fn construct_struct_one() -> ItemStruct {
         ItemStruct {
             is_final: false,
+            large_payload: false,
+            require_max_size: false,
             ident: Ident::new("X1"),
             fields: vec![
                 Field {
@@ -417,6 +594,8 @@ mod tests {
     fn construct_struct_two() -> ItemStruct {
         ItemStruct {
             is_final: false,
+            large_payload: false,
+            require_max_size: false,
             ident: Ident::new("X2"),
             fields: vec![
                 Field {
@@ -484,4 +663,62 @@ mod tests {
             }
         };
     }
+
+    /// Definition and serdes of the first struct in `schema`, generated for alloc targets.
+    fn alloc_struct(schema: &str) -> String {
+        let file = syn::parse_file(schema).unwrap();
+        let (file, _) = File::from_syn(FileSource::Registry, file).unwrap();
+        let Some(Item::Struct(item_struct)) = file.items.first() else {
+            panic!("expected a struct");
+        };
+        let mut ts = item::struct_def(item_struct, false);
+        ts.extend(item::struct_serdes(item_struct, false));
+        ts.to_string()
+    }
+
+    #[test]
+    fn bytes_alloc() {
+        let cg = alloc_struct("struct X { a: bytes, b: bytes<8> }");
+        assert!(cg.contains("pub struct X { pub a : Vec < u8 > , pub b : Vec < u8 > , }"));
+        assert!(cg.contains("wr . write_bytes (self . a . as_slice ()) ?"));
+        assert!(cg.contains("wr . write_bytes_max (self . b . as_slice () , 8usize) ?"));
+        assert!(cg.contains("let a = rd . read_bytes () . map_err"));
+        assert!(cg.contains("let b = rd . read_bytes_max (8usize) . map_err"));
+        assert_eq!(cg.matches("? . to_vec () ;").count(), 2);
+    }
+
+    #[test]
+    fn nested_item_size_counts_last_byte() {
+        let cg = alloc_struct("struct X { y: Y, a: u8 } struct Y { z: u4 }");
+        // Y ends mid-byte, partially used byte is counted in its size, same as RefVec elements
+        assert!(cg.contains(
+            "wr . encode_vlu16n_rev (u16_rev_from , wr . u16_rev_pos ()) ? ; wr . align_byte () ; let size = wr . pos () . 0 - unsized_start ;"
+        ));
+    }
+
+    #[test]
+    fn vec_alloc() {
+        let cg = alloc_struct(
+            "struct X { a: Vec<u16>, #[delta] b: Vec<i32>, #[quantize(min = -1.0, max = 1.0, bits = 8)] c: Vec<f32> }",
+        );
+        let (ser, des) = cg
+            .split_once("impl < 'i > shrink_wrap :: DeserializeShrinkWrap")
+            .unwrap();
+        // serializer of a struct without lifetime has no 'i in scope
+        assert!(!ser.contains("'i"));
+        assert!(ser.contains("wr . write (& < shrink_wrap :: RefVec < '_ , u16 > > :: from (self . a . as_slice ())) ?"));
+        assert!(ser.contains("wr . write (& < shrink_wrap :: DeltaVec < '_ , i32 > > :: from (self . b . as_slice ())) ?"));
+        assert!(ser.contains("(& < shrink_wrap :: QuantizedVec < '_ > > :: from (self . c . as_slice ())) . ser_quantized (wr ,"));
+        assert!(des.contains("let a : shrink_wrap :: RefVec < 'i , u16 > = rd . read"));
+        assert!(des.contains("let b : shrink_wrap :: DeltaVec < 'i , i32 > = rd . read"));
+        assert!(des.contains("let c : shrink_wrap :: QuantizedVec < 'i > = shrink_wrap :: QuantizedVec :: des_quantized"));
+        assert!(des.contains(
+            "let a = a . des_iter () . into_iter () . flatten () . collect :: < Result < Vec < _ > , _ >> ()"
+        ));
+        assert_eq!(
+            des.matches(". iter () . collect :: < Result < Vec < _ > , _ >> ()")
+                .count(),
+            2
+        );
+    }
 }
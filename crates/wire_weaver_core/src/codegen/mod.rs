@@ -13,10 +13,12 @@ pub fn rust_no_std_file(file: &File) -> TokenStream {
             Item::Enum(item_enum) => {
                 ts.append_all(item::enum_def(item_enum, true));
                 ts.append_all(item::enum_serdes(item_enum, true));
+                ts.append_all(item::enum_max_size(item_enum, true));
             }
             Item::Struct(item_struct) => {
                 ts.append_all(item::struct_def(item_struct, true));
                 ts.append_all(item::struct_serdes(item_struct, true));
+                ts.append_all(item::struct_max_size(item_struct, true));
             }
         }
     }
@@ -1,5 +1,7 @@
-use crate::ast::ty::{Type, TypeDiscrete};
-use proc_macro2::{Ident, Span, TokenStream};
+use crate::ast::ty::{
+    Type, TypeDiscrete, TypeVariableLength, TypeVec, VariableLengthEncoding, VecEncoding,
+};
+use proc_macro2::{Ident, Literal, Span, TokenStream};
 use quote::quote;
 
 impl TypeDiscrete {
@@ -10,6 +12,113 @@ impl TypeDiscrete {
             'u'
         }
     }
+
+    /// Smallest Rust integer that fits the number, e.g. u16 for u12.
+    fn rust_ty(&self) -> Ident {
+        let bits = [8, 16, 32, 64, 128]
+            .into_iter()
+            .find(|b| *b >= self.bits)
+            .unwrap_or(128);
+        Ident::new(format!("{}{bits}", self.sign()).as_str(), Span::call_site())
+    }
+
+    /// `1..=512 | 12 | 16` pattern of allowed values if the number is bounded.
+    fn bounds_pat(&self) -> Option<TokenStream> {
+        if self.bounds.is_empty() {
+            return None;
+        }
+        let ranges = self.bounds.iter().map(|range| {
+            let (start, end) = (int_lit(*range.start()), int_lit(*range.end()));
+            if range.start() == range.end() {
+                start
+            } else {
+                quote!(#start..=#end)
+            }
+        });
+        Some(quote!(#(#ranges)|*))
+    }
+
+    /// Error for a value out of bounds, u128 values above i128::MAX saturate, same as in dyn_value.
+    fn out_of_bounds(&self, val: TokenStream) -> TokenStream {
+        if !self.is_signed && self.bits == 128 {
+            quote!(shrink_wrap::Error::ValueOutOfBounds(i128::try_from(#val).unwrap_or(i128::MAX)))
+        } else {
+            quote!(shrink_wrap::Error::ValueOutOfBounds(#val as i128))
+        }
+    }
+
+    /// Numbers other than whole bytes and u4 are packed bit by bit with write_un / write_in.
+    fn is_bit_packed(&self) -> bool {
+        let is_nib = self.bits == 4 && !self.is_signed;
+        !self.is_byte_aligned() && !is_nib
+    }
+}
+
+/// Same as shrink_wrap::max_size::vlu_nibbles, for values known during codegen.
+pub(crate) fn vlu_nibbles(val: u64) -> usize {
+    let bits = u64::BITS - val.leading_zeros();
+    (bits.div_ceil(3) as usize).max(1)
+}
+
+impl TypeVariableLength {
+    fn fn_name(&self, prefix: &str) -> Ident {
+        let sign = self.discrete.sign();
+        let bits = self.discrete.bits;
+        let fn_name = match self.encoding {
+            VariableLengthEncoding::Leb => format!("{prefix}_leb_{sign}{bits}"),
+            VariableLengthEncoding::Nib => format!("{prefix}_vl{sign}{bits}n"),
+        };
+        Ident::new(fn_name.as_str(), Span::call_site())
+    }
+}
+
+impl TypeVec {
+    /// shrink_wrap type used in no_alloc mode, and to serialize and deserialize `Vec<T>` otherwise.
+    /// Serializer has no `'i` in scope, so `'_` is used there.
+    fn ref_vec(&self, lifetime: TokenStream) -> TokenStream {
+        let element = self.element.ty_def(true);
+        match self.encoding {
+            VecEncoding::Plain => quote!(shrink_wrap::RefVec<#lifetime, #element>),
+            VecEncoding::Delta => quote!(shrink_wrap::DeltaVec<#lifetime, #element>),
+            VecEncoding::Quantized { .. } => quote!(shrink_wrap::QuantizedVec<#lifetime>),
+        }
+    }
+
+    /// Iterator over deserialized elements, read RefVec is always buffer backed, so its elements need not be Clone.
+    fn des_iter(&self) -> TokenStream {
+        match self.encoding {
+            VecEncoding::Plain => quote!(des_iter().into_iter().flatten()),
+            VecEncoding::Delta | VecEncoding::Quantized { .. } => quote!(iter()),
+        }
+    }
+
+    fn quantization(&self) -> Option<TokenStream> {
+        let VecEncoding::Quantized { min, max, bits } = self.encoding else {
+            return None;
+        };
+        let (min, max) = (f32_lit(min), f32_lit(max));
+        Some(quote!(shrink_wrap::Quantization::new(#min, #max, #bits)))
+    }
+}
+
+/// Unsuffixed integer literal, so that it fits any type it is compared with.
+fn int_lit(val: i128) -> TokenStream {
+    let lit = Literal::u128_unsuffixed(val.unsigned_abs());
+    if val < 0 {
+        quote!(-#lit)
+    } else {
+        quote!(#lit)
+    }
+}
+
+/// Negative literals are a minus sign followed by a literal.
+fn f32_lit(val: f32) -> TokenStream {
+    let lit = Literal::f32_suffixed(val.abs());
+    if val.is_sign_negative() {
+        quote!(-#lit)
+    } else {
+        quote!(#lit)
+    }
 }
 
 impl Type {
@@ -17,17 +126,14 @@ impl Type {
         match self {
             Type::Bool => quote!(bool),
             Type::Discrete(ty_discrete) => {
-                let is_nib = ty_discrete.bits == 4 && !ty_discrete.is_signed;
-                if [8, 16, 32, 64, 128].contains(&ty_discrete.bits) {
-                    let sign = ty_discrete.sign();
-                    let ty = format!("{sign}{}", ty_discrete.bits);
-                    let ty = Ident::new(ty.as_str(), Span::call_site());
-                    quote!(#ty)
-                } else if is_nib {
-                    quote!(u8)
-                } else {
-                    unimplemented!()
-                }
+                let ty = ty_discrete.rust_ty();
+                quote!(#ty)
+            }
+            Type::VariableLength(ty_var) => {
+                let sign = ty_var.discrete.sign();
+                let ty = format!("{sign}{}", ty_var.discrete.bits);
+                let ty = Ident::new(ty.as_str(), Span::call_site());
+                quote!(#ty)
             }
             Type::Floating(ty_floating) => {
                 if ty_floating.bits == 32 || ty_floating.bits == 64 {
@@ -38,6 +144,11 @@ impl Type {
                     unimplemented!()
                 }
             }
+            Type::Fixed(ty_fixed) => {
+                let ty = ty_fixed.discrete.rust_ty();
+                let frac_bits = Literal::u8_unsuffixed(ty_fixed.frac_bits);
+                quote!(shrink_wrap::Fixed<#ty, #frac_bits>)
+            }
             Type::String => {
                 if no_alloc {
                     quote!(&'i str)
@@ -45,9 +156,37 @@ impl Type {
                     quote!(String)
                 }
             }
+            Type::Bytes(_) => {
+                if no_alloc {
+                    quote!(&'i [u8])
+                } else {
+                    quote!(Vec<u8>)
+                }
+            }
             Type::Path(path) => {
                 let segments = &path.segments;
-                quote!(#(#segments)::*)
+                if no_alloc && path.has_lifetime {
+                    quote!(#(#segments)::*<'i>)
+                } else {
+                    quote!(#(#segments)::*)
+                }
+            }
+            Type::Vec(ty_vec) => {
+                if no_alloc {
+                    ty_vec.ref_vec(quote!('i))
+                } else {
+                    let element = ty_vec.element.ty_def(no_alloc);
+                    quote!(Vec<#element>)
+                }
+            }
+            Type::Option(some_ty) => {
+                let some_ty = some_ty.ty_def(no_alloc);
+                quote!(Option<#some_ty>)
+            }
+            Type::Result(ok_ty, err_ty) => {
+                let ok_ty = ok_ty.ty_def(no_alloc);
+                let err_ty = err_ty.ty_def(no_alloc);
+                quote!(Result<#ok_ty, #err_ty>)
             }
         }
     }
@@ -56,10 +195,15 @@ impl Type {
         match self {
             Type::Bool => true,
             Type::Discrete(_) => true,
+            Type::VariableLength(_) => false,
             Type::Floating(_) => true,
+            Type::Fixed(_) => true,
             Type::String => false,
+            Type::Bytes(_) => false,
             // TODO: need to resolve path's before codegen
             Type::Path(_) => todo!(),
+            Type::Vec(_) => false,
+            Type::Option(_) | Type::Result(_, _) => false,
         }
     }
 
@@ -67,9 +211,92 @@ impl Type {
         match self {
             Type::Bool => false,
             Type::Discrete(_) => false,
+            Type::VariableLength(_) => false,
             Type::Floating(_) => false,
+            Type::Fixed(_) => false,
+            Type::String => true,
+            Type::Bytes(_) => true,
+            Type::Path(path) => path.has_lifetime,
+            Type::Vec(_) => true,
+            Type::Option(some_ty) => some_ty.is_ref(),
+            Type::Result(ok_ty, err_ty) => ok_ty.is_ref() || err_ty.is_ref(),
+        }
+    }
+
+    /// Whether there is an upper bound on the encoded size, paths must be resolved beforehand.
+    pub fn is_bounded(&self) -> bool {
+        match self {
+            Type::Bool => true,
+            Type::Discrete(_) => true,
+            Type::VariableLength(_) => true,
+            Type::Floating(_) => true,
+            Type::Fixed(_) => true,
             Type::String => false,
-            Type::Path(_) => false,
+            Type::Bytes(max_len) => max_len.is_some(),
+            Type::Path(path) => path.is_bounded,
+            Type::Vec(_) => false,
+            Type::Option(some_ty) => some_ty.is_bounded(),
+            Type::Result(ok_ty, err_ty) => ok_ty.is_bounded() && err_ty.is_bounded(),
+        }
+    }
+
+    /// Const expressions of the maximum number of bits (including worst case alignment) and reversed nibbles,
+    /// see shrink_wrap::max_size. Must only be called on bounded types.
+    pub fn max_size(&self) -> (TokenStream, TokenStream) {
+        let lit = |val: usize| {
+            let lit = Literal::usize_unsuffixed(val);
+            quote!(#lit)
+        };
+        let aligned = |align_bits: usize, bits: usize| (lit(align_bits - 1 + bits), lit(0));
+        match self {
+            Type::Bool => (lit(1), lit(0)),
+            Type::Discrete(ty_discrete) => {
+                let bits = ty_discrete.bits as usize;
+                if ty_discrete.is_bit_packed() {
+                    (lit(bits), lit(0))
+                } else if ty_discrete.is_byte_aligned() {
+                    aligned(8, bits)
+                } else {
+                    aligned(4, bits)
+                }
+            }
+            Type::VariableLength(ty_var) => {
+                let bits = ty_var.discrete.bits as usize;
+                match ty_var.encoding {
+                    VariableLengthEncoding::Leb => aligned(8, bits.div_ceil(7) * 8),
+                    VariableLengthEncoding::Nib => aligned(4, bits.div_ceil(3) * 4),
+                }
+            }
+            Type::Floating(ty_floating) => aligned(8, ty_floating.bits as usize),
+            Type::Fixed(ty_fixed) => aligned(8, ty_fixed.discrete.bits as usize),
+            Type::String | Type::Bytes(None) | Type::Vec(_) => {
+                unreachable!("max_size() called on unbounded type")
+            }
+            Type::Bytes(Some(max_len)) => {
+                let max_len = *max_len as usize;
+                let rev_nibbles = lit(vlu_nibbles(max_len as u64));
+                (lit(7 + max_len * 8), rev_nibbles)
+            }
+            Type::Path(path) => {
+                let segments = &path.segments;
+                let max_size = quote!(#(#segments)::*::MAX_ENCODED_SIZE);
+                (
+                    quote!(7 + #max_size * 8),
+                    quote!(shrink_wrap::max_size::vlu_nibbles(#max_size as u64)),
+                )
+            }
+            Type::Option(some_ty) => {
+                let (bits, rev_nibbles) = some_ty.max_size();
+                (quote!(1 + #bits), rev_nibbles)
+            }
+            Type::Result(ok_ty, err_ty) => {
+                let (ok_bits, ok_rev_nibbles) = ok_ty.max_size();
+                let (err_bits, err_rev_nibbles) = err_ty.max_size();
+                (
+                    quote!(1 + shrink_wrap::max_size::max(#ok_bits, #err_bits)),
+                    quote!(shrink_wrap::max_size::max(#ok_rev_nibbles, #err_rev_nibbles)),
+                )
+            }
         }
     }
 
@@ -79,10 +306,30 @@ impl Type {
         } else {
             (field_path.clone(), quote!(& #field_path))
         };
-        match self {
-            Type::Bool | Type::Discrete(_) | Type::Floating(_) => {
+        let check = match self {
+            Type::Discrete(ty_discrete) => ty_discrete.bounds_pat().map(|pat| {
+                let err = ty_discrete.out_of_bounds(field_path_by_value.clone());
+                quote! {
+                    if !matches!(#field_path_by_value, #pat) {
+                        return Err(#err);
+                    }
+                }
+            }),
+            _ => None,
+        };
+        let write = match self {
+            Type::Discrete(ty_discrete) if ty_discrete.is_bit_packed() => {
+                let bits = ty_discrete.bits as u8;
+                if ty_discrete.is_signed {
+                    quote!(wr.write_in(#field_path_by_value as i64, #bits)?;)
+                } else {
+                    quote!(wr.write_un(#field_path_by_value as u64, #bits)?;)
+                }
+            }
+            Type::Bool | Type::Discrete(_) | Type::VariableLength(_) | Type::Floating(_) => {
                 let fn_name = match self {
                     Type::Bool => Ident::new("write_bool", Span::call_site()),
+                    Type::VariableLength(ty_var) => ty_var.fn_name("write"),
                     Type::Discrete(ty_discrete) => {
                         let sign = ty_discrete.sign();
                         let fn_name = format!("write_{sign}{}", ty_discrete.bits);
@@ -96,6 +343,7 @@ impl Type {
                 };
                 quote!(wr.#fn_name(#field_path_by_value)?;)
             }
+            Type::Fixed(_) => quote!(wr.write(#field_path_by_ref)?;),
             Type::String => {
                 if no_alloc {
                     quote!(wr.write_str(#field_path)?;)
@@ -103,35 +351,116 @@ impl Type {
                     quote!(wr.write_str(#field_path.as_str())?;)
                 }
             }
+            Type::Bytes(max_len) => {
+                let val = if no_alloc {
+                    field_path
+                } else {
+                    quote!(#field_path.as_slice())
+                };
+                match max_len {
+                    Some(max_len) => {
+                        let max_len = *max_len as usize;
+                        quote!(wr.write_bytes_max(#val, #max_len)?;)
+                    }
+                    None => quote!(wr.write_bytes(#val)?;),
+                }
+            }
             Type::Path(_) => {
                 quote! {
+                    wr.align_byte();
                     let u16_rev_from = wr.u16_rev_pos();
                     // let handle = wr.write_u16_rev(0)?;
                     let unsized_start = wr.pos().0;
                     wr.write(#field_path_by_ref)?;
-                    let size = wr.pos().0 - unsized_start;
                     wr.encode_vlu16n_rev(u16_rev_from, wr.u16_rev_pos())?;
-                    let Ok(size) = u16::try_from(size) else {
-                        return Err(shrink_wrap::Error::ItemTooLong);
-                    };
-                    wr.write_u16_rev(size)?;
+                    // item can end in the middle of a byte if it has no lengths or sizes of its own
+                    wr.align_byte();
+                    let size = wr.pos().0 - unsized_start;
+                    wr.write_size_rev(size, shrink_wrap::Error::ItemTooLong)?;
                     // wr.update_u16_rev(handle, size as u16)?;
                     // wr.encode_vlu16n_rev(handle)?;
                 }
             }
-        }
+            Type::Vec(ty_vec) => {
+                let vec = if no_alloc {
+                    field_path_by_ref
+                } else {
+                    let ref_vec = ty_vec.ref_vec(quote!('_));
+                    quote!(&<#ref_vec>::from(#field_path.as_slice()))
+                };
+                match ty_vec.quantization() {
+                    Some(quantization) => quote!((#vec).ser_quantized(wr, #quantization)?;),
+                    None => quote!(wr.write(#vec)?;),
+                }
+            }
+            Type::Option(some_ty) => {
+                let ser_some = some_ty.buf_write(quote!(val), true, no_alloc);
+                quote! {
+                    match #field_path_by_ref {
+                        Some(val) => {
+                            wr.write_bool(true)?;
+                            #ser_some
+                        }
+                        None => {
+                            wr.write_bool(false)?;
+                        }
+                    }
+                }
+            }
+            Type::Result(ok_ty, err_ty) => {
+                let ser_ok = ok_ty.buf_write(quote!(val), true, no_alloc);
+                let ser_err = err_ty.buf_write(quote!(val), true, no_alloc);
+                quote! {
+                    match #field_path_by_ref {
+                        Ok(val) => {
+                            wr.write_bool(true)?;
+                            #ser_ok
+                        }
+                        Err(val) => {
+                            wr.write_bool(false)?;
+                            #ser_err
+                        }
+                    }
+                }
+            }
+        };
+        quote!(#check #write)
     }
 
     pub fn buf_read(
         &self,
         variable_name: Ident,
         handle_eob: TokenStream,
+        handle_err: TokenStream,
         no_alloc: bool,
     ) -> TokenStream {
-        match self {
-            Type::Bool | Type::Discrete(_) | Type::Floating(_) => {
+        let check = match self {
+            Type::Discrete(ty_discrete) => ty_discrete.bounds_pat().map(|pat| {
+                let err = ty_discrete.out_of_bounds(quote!(val));
+                quote! {
+                    let #variable_name = match #variable_name {
+                        #pat => Ok(#variable_name),
+                        val => Err(#err),
+                    } #handle_err;
+                }
+            }),
+            _ => None,
+        };
+        let read = match self {
+            Type::Discrete(ty_discrete) if ty_discrete.is_bit_packed() => {
+                let bits = ty_discrete.bits as u8;
+                let ty = ty_discrete.rust_ty();
+                let fn_name = if ty_discrete.is_signed {
+                    quote!(read_in)
+                } else {
+                    quote!(read_un)
+                };
+                quote!(let #variable_name = rd.#fn_name(#bits).map(|val| val as #ty) #handle_eob;)
+            }
+            Type::Bool | Type::Discrete(_) | Type::VariableLength(_) | Type::Floating(_) => {
                 let fn_name = match self {
                     Type::Bool => Ident::new("read_bool", Span::call_site()),
+                    Type::VariableLength(ty_var) => ty_var.fn_name("read"),
                     Type::Discrete(ty_discrete) => {
                         let sign = ty_discrete.sign();
                         let fn_name = format!("read_{sign}{}", ty_discrete.bits);
@@ -145,6 +474,9 @@ impl Type {
                 };
                 quote!(let #variable_name = rd.#fn_name() #handle_eob;)
             }
+            Type::Fixed(_) => {
+                quote!(let #variable_name = rd.read(shrink_wrap::ElementSize::Implied) #handle_eob;)
+            }
             Type::String => {
                 if no_alloc {
                     quote!(let #variable_name = rd.read_str() #handle_eob;)
@@ -152,13 +484,85 @@ impl Type {
                     quote!(let #variable_name = rd.read_str() #handle_eob .to_string();)
                 }
             }
+            Type::Bytes(max_len) => {
+                let read = match max_len {
+                    Some(max_len) => {
+                        let max_len = *max_len as usize;
+                        quote!(rd.read_bytes_max(#max_len))
+                    }
+                    None => quote!(rd.read_bytes()),
+                };
+                if no_alloc {
+                    quote!(let #variable_name = #read #handle_eob;)
+                } else {
+                    quote!(let #variable_name = #read #handle_eob .to_vec();)
+                }
+            }
             Type::Path(_) => {
                 quote! {
-                    let size = rd.read_vlu16n_rev()? as usize;
-                    let mut rd_split = rd.split(size)?;
-                    let #variable_name = rd_split.read(shrink_wrap::ElementSize::Implied)?;
+                    let size = rd.read_size_rev() #handle_err;
+                    let mut rd_split = rd.split(size) #handle_err;
+                    let #variable_name = rd_split.read(shrink_wrap::ElementSize::Implied) #handle_err;
                 }
             }
-        }
+            Type::Vec(ty_vec) => {
+                let read = match ty_vec.quantization() {
+                    Some(quantization) => {
+                        quote!(shrink_wrap::QuantizedVec::des_quantized(rd, #quantization))
+                    }
+                    None => quote!(rd.read(shrink_wrap::ElementSize::Implied)),
+                };
+                if no_alloc {
+                    quote!(let #variable_name = #read #handle_eob;)
+                } else {
+                    let ref_vec = ty_vec.ref_vec(quote!('i));
+                    let des_iter = ty_vec.des_iter();
+                    quote! {
+                        let #variable_name: #ref_vec = #read #handle_eob;
+                        let #variable_name = #variable_name.#des_iter.collect::<Result<Vec<_>, _>>() #handle_err;
+                    }
+                }
+            }
+            Type::Option(some_ty) => {
+                let des_some = some_ty.buf_read(
+                    variable_name.clone(),
+                    handle_err.clone(),
+                    handle_err,
+                    no_alloc,
+                );
+                quote! {
+                    let #variable_name = if rd.read_bool() #handle_eob {
+                        #des_some
+                        Some(#variable_name)
+                    } else {
+                        None
+                    };
+                }
+            }
+            Type::Result(ok_ty, err_ty) => {
+                let des_ok = ok_ty.buf_read(
+                    variable_name.clone(),
+                    handle_err.clone(),
+                    handle_err.clone(),
+                    no_alloc,
+                );
+                let des_err = err_ty.buf_read(
+                    variable_name.clone(),
+                    handle_err.clone(),
+                    handle_err,
+                    no_alloc,
+                );
+                quote! {
+                    let #variable_name = if rd.read_bool() #handle_eob {
+                        #des_ok
+                        Ok(#variable_name)
+                    } else {
+                        #des_err
+                        Err(#variable_name)
+                    };
+                }
+            }
+        };
+        quote!(#read #check)
     }
 }
@@ -0,0 +1,438 @@
+use crate::ast::data::Field;
+use crate::ast::item::{Item, ItemEnum, ItemStruct, Repr};
+use crate::ast::ty::{
+    Type, TypeDiscrete, TypeVariableLength, TypeVec, VariableLengthEncoding, VecEncoding,
+};
+use crate::ast::value::Value;
+use crate::ast::File;
+use crate::dyn_value::hex_dump::{Annotation, AnnotationKind};
+use crate::dyn_value::{find_item, DynEnum, DynError, DynField, DynStruct, DynValue};
+use shrink_wrap::{BufReader, DeltaElement, Error, Quantization};
+use std::ops::Range;
+
+/// Decode `root` item from `rd`, same as its generated `des_shrink_wrap` would.
+pub fn decode(file: &File, root: &str, rd: &mut BufReader) -> Result<DynValue, DynError> {
+    let item = find_item(file, root)?;
+    Decoder::new(file, false).des_item(item, rd)
+}
+
+pub(crate) struct Decoder<'a> {
+    file: &'a File,
+    // Position of the current reader in the outermost buffer, in bits
+    offset: usize,
+    // Item and field names leading to the value being read
+    path: Vec<String>,
+    // Record what was read where and continue after errors instead of failing
+    annotations: Option<Vec<Annotation>>,
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(file: &'a File, annotate: bool) -> Self {
+        Decoder {
+            file,
+            offset: 0,
+            path: vec![],
+            annotations: annotate.then(Vec::new),
+        }
+    }
+
+    pub(crate) fn into_annotations(self) -> Vec<Annotation> {
+        self.annotations.unwrap_or_default()
+    }
+
+    pub(crate) fn des_item(
+        &mut self,
+        item: &Item,
+        rd: &mut BufReader,
+    ) -> Result<DynValue, DynError> {
+        let mut result = None;
+        let large_payload = match item {
+            Item::Enum(item_enum) => item_enum.large_payload,
+            Item::Struct(item_struct) => item_struct.large_payload,
+        };
+        // field names are enough to locate a value, only root item name is included
+        let is_root = self.path.is_empty();
+        if is_root {
+            self.path.push(item.ident().sym.clone());
+        }
+        rd.with_large_payload(large_payload, |rd| {
+            result = Some(match item {
+                Item::Enum(item_enum) => self.des_enum(item_enum, rd),
+                Item::Struct(item_struct) => self.des_struct(item_struct, rd),
+            });
+            Ok(())
+        })?;
+        let result = result.expect("closure is always called");
+        let result = match result {
+            // mark the rest of the item and carry on with the outer one, value is not used when annotating
+            Err(DynError::ShrinkWrap(e)) if self.annotations.is_some() => {
+                let start = bits(rd.pos());
+                let end = bits(rd.rev_pos()).max(start);
+                self.annotate(start..end, AnnotationKind::Corrupt(e));
+                Ok(DynValue::Bytes(vec![]))
+            }
+            result => result,
+        };
+        if is_root {
+            self.path.pop();
+        }
+        result
+    }
+
+    fn des_struct(
+        &mut self,
+        item_struct: &ItemStruct,
+        rd: &mut BufReader,
+    ) -> Result<DynValue, DynError> {
+        Ok(DynValue::Struct(DynStruct {
+            name: item_struct.ident.sym.clone(),
+            fields: self.des_fields(item_struct.fields.iter(), rd)?,
+        }))
+    }
+
+    fn des_enum(&mut self, item_enum: &ItemEnum, rd: &mut BufReader) -> Result<DynValue, DynError> {
+        // discriminant is nibble aligned
+        let start = bits(rd.pos()).next_multiple_of(4);
+        let discriminant = match item_enum.repr {
+            Repr::Vlu16N => rd.read_vlu16n()? as u64,
+            Repr::Vlu32N => rd.read_vlu32n()? as u64,
+            Repr::Vlu64N => rd.read_vlu64n()?,
+        };
+        let variant = item_enum
+            .variants
+            .iter()
+            .find(|v| v.discriminant == discriminant)
+            .ok_or(Error::EnumFutureVersionOrMalformedData)?;
+        self.annotate(
+            start..bits(rd.pos()),
+            AnnotationKind::Discriminant {
+                variant: variant.ident.sym.clone(),
+                value: discriminant,
+            },
+        );
+        self.path.push(variant.ident.sym.clone());
+        let fields = self.des_fields(variant.fields.iter(), rd);
+        self.path.pop();
+        Ok(DynValue::Enum(DynEnum {
+            name: item_enum.ident.sym.clone(),
+            variant: variant.ident.sym.clone(),
+            fields: fields?,
+        }))
+    }
+
+    fn des_fields<'f>(
+        &mut self,
+        fields: impl Iterator<Item = &'f Field>,
+        rd: &mut BufReader,
+    ) -> Result<Vec<DynField>, DynError> {
+        let mut values = vec![];
+        for field in fields {
+            self.path.push(field.ident.sym.clone());
+            let value = self.des_ty(&field.ty, field.default.as_ref(), rd);
+            self.path.pop();
+            values.push(DynField {
+                name: field.ident.sym.clone(),
+                value: value?,
+            });
+        }
+        Ok(values)
+    }
+
+    fn des_ty(
+        &mut self,
+        ty: &Type,
+        default: Option<&Value>,
+        rd: &mut BufReader,
+    ) -> Result<DynValue, DynError> {
+        // same as Field::handle_eob, failed first read results in the default value
+        let eob = |result: Result<DynValue, Error>| match (result, default) {
+            (Ok(value), _) => Ok(value),
+            (Err(_), Some(default)) => Ok(default.into()),
+            (Err(e), None) => Err(DynError::ShrinkWrap(e)),
+        };
+        match ty {
+            Type::Bool => eob(self.leaf(rd, 1, |rd| rd.read_bool().map(DynValue::Bool))),
+            Type::Discrete(ty_discrete) => {
+                let align = match ty_discrete.bits {
+                    4 if !ty_discrete.is_signed => 4,
+                    8 | 16 | 32 | 64 | 128 => 8,
+                    _ => 1,
+                };
+                eob(self.leaf(rd, align, |rd| read_discrete(ty_discrete, rd)))
+            }
+            Type::VariableLength(ty_var) => {
+                let align = match ty_var.encoding {
+                    VariableLengthEncoding::Leb => 8,
+                    VariableLengthEncoding::Nib => 4,
+                };
+                eob(self.leaf(rd, align, |rd| read_variable_length(ty_var, rd)))
+            }
+            Type::Floating(ty_floating) => eob(self.leaf(rd, 8, |rd| {
+                if ty_floating.bits == 32 {
+                    rd.read_f32().map(DynValue::F32)
+                } else {
+                    rd.read_f64().map(DynValue::F64)
+                }
+            })),
+            Type::Fixed(ty_fixed) => eob(self.leaf(rd, 8, |rd| {
+                let raw = match read_discrete(&ty_fixed.discrete, rd)? {
+                    DynValue::Signed(raw) => raw,
+                    DynValue::Unsigned(raw) => raw as i128,
+                    _ => unreachable!("read_discrete returns numbers"),
+                };
+                Ok(DynValue::Fixed {
+                    raw,
+                    frac_bits: ty_fixed.frac_bits,
+                })
+            })),
+            Type::String => eob(self.leaf(rd, 8, |rd| {
+                rd.read_str().map(|s| DynValue::String(s.to_string()))
+            })),
+            Type::Bytes(max_len) => eob(self.leaf(rd, 8, |rd| {
+                let bytes = match max_len {
+                    Some(max_len) => rd.read_bytes_max(*max_len as usize),
+                    None => rd.read_bytes(),
+                };
+                bytes.map(|b| DynValue::Bytes(b.to_vec()))
+            })),
+            Type::Path(path) => {
+                let name = path.segments.last().map(|i| i.sym.as_str());
+                let item = find_item(self.file, name.unwrap_or_default())?;
+                let rev_start = rd.rev_pos();
+                let size = rd.read_size_rev()?;
+                self.annotate(
+                    bits(rd.rev_pos())..bits(rev_start),
+                    AnnotationKind::Size(size),
+                );
+                let mut rd_split = rd.split(size)?;
+                let start = bits(rd.pos()) - size * 8;
+                self.annotate(
+                    start..bits(rd.pos()),
+                    AnnotationKind::Item(item.ident().sym.clone()),
+                );
+                let prev_offset = self.offset;
+                self.offset += start;
+                let value = self.des_item(item, &mut rd_split);
+                self.offset = prev_offset;
+                value
+            }
+            Type::Vec(ty_vec) => {
+                let rev_start = rd.rev_pos();
+                let len = match rd.read_size_rev() {
+                    Ok(len) => len,
+                    Err(e) => return eob(Err(e)),
+                };
+                self.annotate(
+                    bits(rd.rev_pos())..bits(rev_start),
+                    AnnotationKind::Size(len),
+                );
+                rd.count_elements(len as u32)?;
+                let mut values = vec![];
+                let mut prev = None;
+                for idx in 0..len {
+                    self.path.push(idx.to_string());
+                    let value = self.des_element(ty_vec, prev, rd);
+                    self.path.pop();
+                    let value = value?;
+                    prev = Some(value.clone());
+                    values.push(value);
+                }
+                Ok(DynValue::Vec(values))
+            }
+            Type::Option(some_ty) => {
+                let is_some = match self.flag("is_some", rd) {
+                    Ok(is_some) => is_some,
+                    Err(e) => return eob(Err(e)),
+                };
+                let value = if is_some {
+                    Some(Box::new(self.des_ty(some_ty, None, rd)?))
+                } else {
+                    None
+                };
+                Ok(DynValue::Option(value))
+            }
+            Type::Result(ok_ty, err_ty) => {
+                let is_ok = match self.flag("is_ok", rd) {
+                    Ok(is_ok) => is_ok,
+                    Err(e) => return eob(Err(e)),
+                };
+                let value = if is_ok {
+                    Ok(Box::new(self.des_ty(ok_ty, None, rd)?))
+                } else {
+                    Err(Box::new(self.des_ty(err_ty, None, rd)?))
+                };
+                Ok(DynValue::Result(value))
+            }
+        }
+    }
+
+    /// Read one element of a vector, `prev` is the previous one if any.
+    fn des_element(
+        &mut self,
+        ty_vec: &TypeVec,
+        prev: Option<DynValue>,
+        rd: &mut BufReader,
+    ) -> Result<DynValue, DynError> {
+        match (ty_vec.encoding, ty_vec.element.as_ref(), prev) {
+            (VecEncoding::Delta, Type::Discrete(ty_discrete), Some(prev)) => {
+                let prev = delta_element(&prev);
+                Ok(self.leaf(rd, 4, |rd| {
+                    let val = add_delta(ty_discrete, prev, rd.read_vli64n()?)?;
+                    Ok(from_delta_element(ty_discrete, val))
+                })?)
+            }
+            (VecEncoding::Quantized { min, max, bits }, _, _) => {
+                let quantization = Quantization::new(min, max, bits);
+                Ok(self.leaf(rd, 1, |rd| {
+                    let code = rd.read_un(bits)? as u32;
+                    Ok(DynValue::F32(quantization.dequantize(code)))
+                })?)
+            }
+            _ => self.des_ty(&ty_vec.element, None, rd),
+        }
+    }
+
+    /// Read one value and record where it was, including its length from the reversed area if any.
+    /// Value starts at the next multiple of `align` bits, padding before it is not included.
+    fn leaf(
+        &mut self,
+        rd: &mut BufReader,
+        align: usize,
+        f: impl FnOnce(&mut BufReader) -> Result<DynValue, Error>,
+    ) -> Result<DynValue, Error> {
+        if self.annotations.is_none() {
+            return f(rd);
+        }
+        let rev_start = rd.rev_pos();
+        let start = bits(rd.pos()).next_multiple_of(align);
+        let value = f(rd)?;
+        if rd.rev_pos() != rev_start {
+            let len = match &value {
+                DynValue::String(s) => s.len(),
+                DynValue::Bytes(b) => b.len(),
+                _ => 0,
+            };
+            self.annotate(
+                bits(rd.rev_pos())..bits(rev_start),
+                AnnotationKind::Size(len),
+            );
+        }
+        self.annotate(start..bits(rd.pos()), AnnotationKind::Value(value.clone()));
+        Ok(value)
+    }
+
+    fn flag(&mut self, name: &str, rd: &mut BufReader) -> Result<bool, Error> {
+        self.path.push(name.to_string());
+        let value = self.leaf(rd, 1, |rd| rd.read_bool().map(DynValue::Bool));
+        self.path.pop();
+        Ok(value? == DynValue::Bool(true))
+    }
+
+    /// Record `bits` of the current reader as `kind`.
+    fn annotate(&mut self, bits: Range<usize>, kind: AnnotationKind) {
+        let offset = self.offset;
+        let path = self.path.join(".");
+        if let Some(annotations) = &mut self.annotations {
+            annotations.push(Annotation {
+                path,
+                bits: offset + bits.start..offset + bits.end,
+                kind,
+            });
+        }
+    }
+}
+
+/// Bits from the start of the buffer to the (byte, bit) position, bit 7 is MSB.
+fn bits((byte_idx, bit_idx): (usize, u8)) -> usize {
+    byte_idx * 8 + 7 - bit_idx as usize
+}
+
+fn read_discrete(ty: &TypeDiscrete, rd: &mut BufReader) -> Result<DynValue, Error> {
+    let value = match (ty.is_signed, ty.bits) {
+        (false, 4) => DynValue::Unsigned(rd.read_u4()? as u128),
+        (false, 8) => DynValue::Unsigned(rd.read_u8()? as u128),
+        (false, 16) => DynValue::Unsigned(rd.read_u16()? as u128),
+        (false, 32) => DynValue::Unsigned(rd.read_u32()? as u128),
+        (false, 64) => DynValue::Unsigned(rd.read_u64()? as u128),
+        (false, 128) => DynValue::Unsigned(rd.read_u128()?),
+        (false, bits) => DynValue::Unsigned(rd.read_un(bits as u8)? as u128),
+        (true, 8) => DynValue::Signed(rd.read_i8()? as i128),
+        (true, 16) => DynValue::Signed(rd.read_i16()? as i128),
+        (true, 32) => DynValue::Signed(rd.read_i32()? as i128),
+        (true, 64) => DynValue::Signed(rd.read_i64()? as i128),
+        (true, 128) => DynValue::Signed(rd.read_i128()?),
+        (true, bits) => DynValue::Signed(rd.read_in(bits as u8)? as i128),
+    };
+    check_bounds(ty, &value)?;
+    Ok(value)
+}
+
+/// Same check as in the generated code, so that bounded numbers are never out of their bounds.
+pub(crate) fn check_bounds(ty: &TypeDiscrete, value: &DynValue) -> Result<(), Error> {
+    let val = match value {
+        DynValue::Signed(val) => *val,
+        DynValue::Unsigned(val) => i128::try_from(*val).unwrap_or(i128::MAX),
+        _ => return Ok(()),
+    };
+    if ty.is_allowed(val) {
+        Ok(())
+    } else {
+        Err(Error::ValueOutOfBounds(val))
+    }
+}
+
+fn read_variable_length(ty: &TypeVariableLength, rd: &mut BufReader) -> Result<DynValue, Error> {
+    let value = match (ty.encoding, ty.discrete.is_signed, ty.discrete.bits) {
+        (VariableLengthEncoding::Leb, false, 16) => DynValue::Unsigned(rd.read_leb_u16()? as u128),
+        (VariableLengthEncoding::Leb, false, 32) => DynValue::Unsigned(rd.read_leb_u32()? as u128),
+        (VariableLengthEncoding::Leb, false, 64) => DynValue::Unsigned(rd.read_leb_u64()? as u128),
+        (VariableLengthEncoding::Leb, false, _) => DynValue::Unsigned(rd.read_leb_u128()?),
+        (VariableLengthEncoding::Leb, true, 16) => DynValue::Signed(rd.read_leb_i16()? as i128),
+        (VariableLengthEncoding::Leb, true, 32) => DynValue::Signed(rd.read_leb_i32()? as i128),
+        (VariableLengthEncoding::Leb, true, 64) => DynValue::Signed(rd.read_leb_i64()? as i128),
+        (VariableLengthEncoding::Leb, true, _) => DynValue::Signed(rd.read_leb_i128()?),
+        (VariableLengthEncoding::Nib, false, 16) => DynValue::Unsigned(rd.read_vlu16n()? as u128),
+        (VariableLengthEncoding::Nib, false, 32) => DynValue::Unsigned(rd.read_vlu32n()? as u128),
+        (VariableLengthEncoding::Nib, false, _) => DynValue::Unsigned(rd.read_vlu64n()? as u128),
+        (VariableLengthEncoding::Nib, true, 16) => DynValue::Signed(rd.read_vli16n()? as i128),
+        (VariableLengthEncoding::Nib, true, 32) => DynValue::Signed(rd.read_vli32n()? as i128),
+        (VariableLengthEncoding::Nib, true, _) => DynValue::Signed(rd.read_vli64n()? as i128),
+    };
+    Ok(value)
+}
+
+/// Add a difference to the previous element of a delta encoded vector, same as shrink_wrap::DeltaVec does.
+pub(crate) fn add_delta(ty: &TypeDiscrete, prev: i64, delta: i64) -> Result<i64, Error> {
+    fn add<T: DeltaElement>(prev: i64, delta: i64) -> Result<i64, Error> {
+        T::from_i64(prev.wrapping_add(delta))
+            .map(T::to_i64)
+            .ok_or(Error::OutOfRange)
+    }
+    match (ty.is_signed, ty.bits) {
+        (false, 8) => add::<u8>(prev, delta),
+        (false, 16) => add::<u16>(prev, delta),
+        (false, 32) => add::<u32>(prev, delta),
+        (false, _) => add::<u64>(prev, delta),
+        (true, 8) => add::<i8>(prev, delta),
+        (true, 16) => add::<i16>(prev, delta),
+        (true, 32) => add::<i32>(prev, delta),
+        (true, _) => add::<i64>(prev, delta),
+    }
+}
+
+/// Element of a delta encoded vector as DeltaElement::to_i64 would return it, value must already fit.
+pub(crate) fn delta_element(value: &DynValue) -> i64 {
+    match value {
+        DynValue::Unsigned(val) => *val as u64 as i64,
+        DynValue::Signed(val) => *val as i64,
+        _ => 0,
+    }
+}
+
+fn from_delta_element(ty: &TypeDiscrete, val: i64) -> DynValue {
+    if ty.is_signed {
+        DynValue::Signed(val as i128)
+    } else {
+        DynValue::Unsigned(val as u64 as u128)
+    }
+}
@@ -0,0 +1,139 @@
+use crate::ast::File;
+use crate::dyn_value::des::Decoder;
+use crate::dyn_value::{find_item, DynError, DynValue};
+use shrink_wrap::{BufReader, Error};
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Part of an encoded buffer and what it holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    /// Root item name followed by field and variant names, e.g. `X.y.z`
+    pub path: String,
+    /// Bit range in the buffer, 0 is the MSB of the first byte
+    pub bits: Range<usize>,
+    pub kind: AnnotationKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnotationKind {
+    /// Number, bool, string or bytes
+    Value(DynValue),
+    /// Enum discriminant and the variant it selects
+    Discriminant { variant: String, value: u64 },
+    /// Nested item with a size, covers all of its fields
+    Item(String),
+    /// Item size or string and bytes length from the reversed area at the back of the buffer
+    Size(usize),
+    /// Data that failed to decode, from the error position to the end of the item
+    Corrupt(Error),
+    /// Padding or data that was never read
+    Unused,
+}
+
+/// Decode `buf` as `root` item and label every part of it.
+///
+/// Malformed data does not stop decoding, it is reported as [AnnotationKind::Corrupt] instead
+/// and the remaining fields of the outer items are still annotated.
+/// Annotations are sorted by their start position, outer items first.
+pub fn annotate(file: &File, root: &str, buf: &[u8]) -> Result<Vec<Annotation>, DynError> {
+    let item = find_item(file, root)?;
+    let mut decoder = Decoder::new(file, true);
+    decoder.des_item(item, &mut BufReader::new(buf))?;
+    let mut annotations = decoder.into_annotations();
+
+    let mut is_used = vec![false; buf.len() * 8];
+    for a in &annotations {
+        if !matches!(a.kind, AnnotationKind::Item(_)) && a.bits.end <= is_used.len() {
+            is_used[a.bits.clone()].fill(true);
+        }
+    }
+    let mut bit = 0;
+    while bit < is_used.len() {
+        let len = is_used[bit..].iter().take_while(|u| !**u).count();
+        if len > 0 {
+            annotations.push(Annotation {
+                path: root.to_string(),
+                bits: bit..bit + len,
+                kind: AnnotationKind::Unused,
+            });
+        }
+        bit += len + is_used[bit + len..].iter().take_while(|u| **u).count();
+    }
+
+    annotations.sort_by_key(|a| (a.bits.start, usize::MAX - a.bits.end));
+    Ok(annotations)
+}
+
+/// Human-readable dump of `buf` decoded as `root` item, one line per annotation:
+///
+/// ```text
+/// 0000..0001  8a                         bits 0..1  X.a  = true
+/// 0000..0001  8a                         bits 1..4  X  ?? unused
+/// 0000..0001  8a                         bits 4..8  X.b  = 10
+/// 0001..0003  0c 00                                 X.c  = 12
+/// 0003..0006  61 62 63                              X.name  = "abc"
+/// 0006..0007  03                         bits 0..4  X  ?? unused
+/// -- reversed size table --
+/// 0006..0007  03                         bits 4..8  X.name  len 3
+/// ```
+///
+/// Byte ranges are in hex and end exclusive, bit ranges are shown for values not occupying whole bytes
+/// and count from the MSB of the first byte.
+/// Corrupt regions are marked with `!!` and unused ones with `??`.
+pub fn hex_dump(file: &File, root: &str, buf: &[u8]) -> Result<String, DynError> {
+    let annotations = annotate(file, root, buf)?;
+    // nested items have their own size tables, only the one of the root item is shown separately
+    let is_nested = |bits: &Range<usize>| {
+        annotations.iter().any(|a| {
+            matches!(a.kind, AnnotationKind::Item(_))
+                && a.bits.start <= bits.start
+                && bits.end <= a.bits.end
+        })
+    };
+    let rev_start = annotations
+        .iter()
+        .filter(|a| matches!(a.kind, AnnotationKind::Size(_)) && !is_nested(&a.bits))
+        .map(|a| a.bits.start)
+        .min();
+    let mut out = String::new();
+    let mut in_rev_table = false;
+    for a in &annotations {
+        if !in_rev_table && rev_start.is_some_and(|rev_start| a.bits.start >= rev_start) {
+            in_rev_table = true;
+            out.push_str("-- reversed size table --\n");
+        }
+        let bytes = a.bits.start / 8..a.bits.end.div_ceil(8);
+        let mut hex = String::new();
+        for b in buf[bytes.clone()].iter().take(8) {
+            let _ = write!(hex, "{b:02x} ");
+        }
+        if bytes.len() > 8 {
+            hex.push_str("..");
+        }
+        let bits = if a.bits.start % 8 != 0 || a.bits.end % 8 != 0 {
+            let first_bit = bytes.start * 8;
+            format!(
+                "bits {}..{}",
+                a.bits.start - first_bit,
+                a.bits.end - first_bit
+            )
+        } else {
+            String::new()
+        };
+        let label = match &a.kind {
+            AnnotationKind::Value(value) => format!("= {value}"),
+            AnnotationKind::Discriminant { variant, value } => format!("= {variant} ({value})"),
+            AnnotationKind::Item(name) => name.clone(),
+            AnnotationKind::Size(len) => format!("len {len}"),
+            AnnotationKind::Corrupt(e) => format!("!! corrupt: {e:?}"),
+            AnnotationKind::Unused => "?? unused".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "{:04x}..{:04x}  {hex:<26} {bits:<11}{}  {label}",
+            bytes.start, bytes.end, a.path
+        );
+    }
+    Ok(out)
+}
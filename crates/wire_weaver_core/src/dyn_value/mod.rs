@@ -0,0 +1,171 @@
+//! Values of types described by an [ast::File](crate::ast::File) that are only known at runtime.
+//! Decoding and encoding follows the same layout rules as generated code, so that host tools (loggers, UI, CLI)
+//! can work with any schema without compiling it.
+
+mod des;
+mod hex_dump;
+mod ser;
+
+use crate::ast::item::Item;
+use crate::ast::value::Value;
+use crate::ast::File;
+use std::fmt::{Display, Formatter};
+
+pub use des::decode;
+pub use hex_dump::{annotate, hex_dump, Annotation, AnnotationKind};
+pub use ser::encode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DynValue {
+    Bool(bool),
+    /// Any unsigned number, regardless of its width and encoding
+    Unsigned(u128),
+    /// Any signed number, regardless of its width and encoding
+    Signed(i128),
+    F32(f32),
+    F64(f64),
+    /// Fixed-point number equal to `raw / 2^frac_bits`
+    Fixed {
+        raw: i128,
+        frac_bits: u8,
+    },
+    String(String),
+    Bytes(Vec<u8>),
+    Vec(Vec<DynValue>),
+    Option(Option<Box<DynValue>>),
+    Result(Result<Box<DynValue>, Box<DynValue>>),
+    Struct(DynStruct),
+    Enum(DynEnum),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynStruct {
+    pub name: String,
+    pub fields: Vec<DynField>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynEnum {
+    pub name: String,
+    pub variant: String,
+    /// Empty for unit variants
+    pub fields: Vec<DynField>,
+}
+
+/// Named field, or unnamed one with its index as a name, e.g. `_0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynField {
+    pub name: String,
+    pub value: DynValue,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DynError {
+    /// Item is not defined in the file
+    UnknownItem(String),
+    /// Enum variant is not defined in the file
+    UnknownVariant(String),
+    /// Value does not match the type of the field, contains field name
+    TypeMismatch(String),
+    /// Struct or enum variant value does not contain a field
+    MissingField(String),
+    ShrinkWrap(shrink_wrap::Error),
+}
+
+impl From<shrink_wrap::Error> for DynError {
+    fn from(e: shrink_wrap::Error) -> Self {
+        DynError::ShrinkWrap(e)
+    }
+}
+
+impl DynValue {
+    /// Field of a struct or enum variant value by name.
+    pub fn field(&self, name: &str) -> Option<&DynValue> {
+        let fields = match self {
+            DynValue::Struct(dyn_struct) => &dyn_struct.fields,
+            DynValue::Enum(dyn_enum) => &dyn_enum.fields,
+            _ => return None,
+        };
+        fields.iter().find(|f| f.name == name).map(|f| &f.value)
+    }
+}
+
+impl Display for DynValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DynValue::Bool(val) => write!(f, "{val}"),
+            DynValue::Unsigned(val) => write!(f, "{val}"),
+            DynValue::Signed(val) => write!(f, "{val}"),
+            DynValue::F32(val) => write!(f, "{val}"),
+            DynValue::F64(val) => write!(f, "{val}"),
+            DynValue::Fixed { raw, frac_bits } => {
+                write!(f, "{}", *raw as f64 / (1u128 << frac_bits) as f64)
+            }
+            DynValue::String(val) => write!(f, "{val:?}"),
+            DynValue::Bytes(val) => write!(f, "{val:02x?}"),
+            DynValue::Vec(values) => {
+                write!(f, "[")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            DynValue::Option(None) => write!(f, "None"),
+            DynValue::Option(Some(val)) => write!(f, "Some({val})"),
+            DynValue::Result(Ok(val)) => write!(f, "Ok({val})"),
+            DynValue::Result(Err(val)) => write!(f, "Err({val})"),
+            DynValue::Struct(dyn_struct) => {
+                write!(f, "{}", dyn_struct.name)?;
+                fmt_fields(&dyn_struct.fields, f)
+            }
+            DynValue::Enum(dyn_enum) => {
+                write!(f, "{}::{}", dyn_enum.name, dyn_enum.variant)?;
+                fmt_fields(&dyn_enum.fields, f)
+            }
+        }
+    }
+}
+
+fn fmt_fields(fields: &[DynField], f: &mut Formatter<'_>) -> std::fmt::Result {
+    if fields.is_empty() {
+        return Ok(());
+    }
+    write!(f, " {{ ")?;
+    for (idx, field) in fields.iter().enumerate() {
+        if idx > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}: {}", field.name, field.value)?;
+    }
+    write!(f, " }}")
+}
+
+impl From<&Value> for DynValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Bool(val) => DynValue::Bool(*val),
+            Value::F32(val) => DynValue::F32(*val),
+            Value::F64(val) => DynValue::F64(*val),
+            Value::U8(val) => DynValue::Unsigned(*val as u128),
+            Value::U16(val) => DynValue::Unsigned(*val as u128),
+            Value::U32(val) => DynValue::Unsigned(*val as u128),
+            Value::U64(val) => DynValue::Unsigned(*val as u128),
+            Value::U128(val) => DynValue::Unsigned(*val),
+            Value::I8(val) => DynValue::Signed(*val as i128),
+            Value::I16(val) => DynValue::Signed(*val as i128),
+            Value::I32(val) => DynValue::Signed(*val as i128),
+            Value::I64(val) => DynValue::Signed(*val as i128),
+            Value::I128(val) => DynValue::Signed(*val),
+        }
+    }
+}
+
+fn find_item<'a>(file: &'a File, name: &str) -> Result<&'a Item, DynError> {
+    file.items
+        .iter()
+        .find(|item| item.ident().sym == name)
+        .ok_or_else(|| DynError::UnknownItem(name.to_string()))
+}
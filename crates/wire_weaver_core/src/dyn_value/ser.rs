@@ -0,0 +1,304 @@
+use crate::ast::data::Field;
+use crate::ast::item::{Item, ItemEnum, ItemStruct, Repr};
+use crate::ast::ty::{Type, TypeDiscrete, TypeVariableLength, VariableLengthEncoding, VecEncoding};
+use crate::ast::File;
+use crate::dyn_value::des::{add_delta, check_bounds, delta_element};
+use crate::dyn_value::{find_item, DynError, DynField, DynValue};
+use shrink_wrap::{BufWriter, Error, Quantization};
+
+/// Encode `value` of `root` item into `wr`, same as its generated `ser_shrink_wrap` would.
+pub fn encode(
+    file: &File,
+    root: &str,
+    value: &DynValue,
+    wr: &mut BufWriter,
+) -> Result<(), DynError> {
+    let item = find_item(file, root)?;
+    ser_item(file, item, value, wr)
+}
+
+fn ser_item(
+    file: &File,
+    item: &Item,
+    value: &DynValue,
+    wr: &mut BufWriter,
+) -> Result<(), DynError> {
+    let mut result = None;
+    let large_payload = match item {
+        Item::Enum(item_enum) => item_enum.large_payload,
+        Item::Struct(item_struct) => item_struct.large_payload,
+    };
+    wr.with_large_payload(large_payload, |wr| {
+        result = Some(match item {
+            Item::Enum(item_enum) => ser_enum(file, item_enum, value, wr),
+            Item::Struct(item_struct) => ser_struct(file, item_struct, value, wr),
+        });
+        Ok(())
+    })?;
+    result.expect("closure is always called")
+}
+
+fn ser_struct(
+    file: &File,
+    item_struct: &ItemStruct,
+    value: &DynValue,
+    wr: &mut BufWriter,
+) -> Result<(), DynError> {
+    let DynValue::Struct(dyn_struct) = value else {
+        return Err(DynError::TypeMismatch(item_struct.ident.sym.clone()));
+    };
+    let fields = &item_struct.fields;
+    // same as generated code, trailing fields with defaults can be dropped, see BufWriter::set_truncate_evolvable
+    let evolvable_from = fields.len()
+        - fields
+            .iter()
+            .rev()
+            .take_while(|f| f.default.is_some())
+            .count();
+    for (idx, field) in fields.iter().enumerate() {
+        let value = find_field(&dyn_struct.fields, field)?;
+        if idx < evolvable_from {
+            ser_ty(file, &field.ty, value, &field.ident.sym, wr)?;
+            continue;
+        }
+        let mut schema_err = None;
+        let is_written =
+            wr.write_evolvable(
+                |wr| match ser_ty(file, &field.ty, value, &field.ident.sym, wr) {
+                    Ok(()) => Ok(()),
+                    Err(DynError::ShrinkWrap(e)) => Err(e),
+                    Err(e) => {
+                        schema_err = Some(e);
+                        Ok(())
+                    }
+                },
+            )?;
+        if let Some(e) = schema_err {
+            return Err(e);
+        }
+        if !is_written {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn ser_enum(
+    file: &File,
+    item_enum: &ItemEnum,
+    value: &DynValue,
+    wr: &mut BufWriter,
+) -> Result<(), DynError> {
+    let DynValue::Enum(dyn_enum) = value else {
+        return Err(DynError::TypeMismatch(item_enum.ident.sym.clone()));
+    };
+    let variant = item_enum
+        .variants
+        .iter()
+        .find(|v| v.ident.sym == dyn_enum.variant)
+        .ok_or_else(|| DynError::UnknownVariant(dyn_enum.variant.clone()))?;
+    let discriminant = variant.discriminant;
+    match item_enum.repr {
+        Repr::Vlu16N => wr.write_vlu16n(fit(discriminant)?)?,
+        Repr::Vlu32N => wr.write_vlu32n(fit(discriminant)?)?,
+        Repr::Vlu64N => wr.write_vlu64n(discriminant)?,
+    }
+    for field in variant.fields.iter() {
+        let value = find_field(&dyn_enum.fields, field)?;
+        ser_ty(file, &field.ty, value, &field.ident.sym, wr)?;
+    }
+    Ok(())
+}
+
+fn find_field<'a>(fields: &'a [DynField], field: &Field) -> Result<&'a DynValue, DynError> {
+    fields
+        .iter()
+        .find(|f| f.name == field.ident.sym)
+        .map(|f| &f.value)
+        .ok_or_else(|| DynError::MissingField(field.ident.sym.clone()))
+}
+
+fn ser_ty(
+    file: &File,
+    ty: &Type,
+    value: &DynValue,
+    name: &str,
+    wr: &mut BufWriter,
+) -> Result<(), DynError> {
+    match (ty, value) {
+        (Type::Bool, DynValue::Bool(val)) => wr.write_bool(*val)?,
+        (Type::Discrete(ty_discrete), _) => write_discrete(ty_discrete, value, name, wr)?,
+        (Type::VariableLength(ty_var), _) => write_variable_length(ty_var, value, name, wr)?,
+        (Type::Floating(ty_floating), DynValue::F32(val)) if ty_floating.bits == 32 => {
+            wr.write_f32(*val)?
+        }
+        (Type::Floating(ty_floating), DynValue::F64(val)) if ty_floating.bits == 64 => {
+            wr.write_f64(*val)?
+        }
+        (Type::Fixed(ty_fixed), _) => {
+            let scale = (1u128 << ty_fixed.frac_bits) as f64;
+            // floats are accepted as well, so that values can be typed in by hand
+            let raw = match value {
+                DynValue::Fixed { raw, frac_bits } if *frac_bits == ty_fixed.frac_bits => *raw,
+                DynValue::F32(val) => (*val as f64 * scale).round() as i128,
+                DynValue::F64(val) => (val * scale).round() as i128,
+                _ => return Err(DynError::TypeMismatch(name.to_string())),
+            };
+            write_discrete(&ty_fixed.discrete, &DynValue::Signed(raw), name, wr)?
+        }
+        (Type::String, DynValue::String(val)) => wr.write_str(val)?,
+        (Type::Bytes(max_len), DynValue::Bytes(val)) => match max_len {
+            Some(max_len) => wr.write_bytes_max(val, *max_len as usize)?,
+            None => wr.write_bytes(val)?,
+        },
+        (Type::Path(path), _) => {
+            let name = path.segments.last().map(|i| i.sym.as_str());
+            let item = find_item(file, name.unwrap_or_default())?;
+            wr.align_byte();
+            let u16_rev_from = wr.u16_rev_pos();
+            let unsized_start = wr.pos().0;
+            ser_item(file, item, value, wr)?;
+            wr.encode_vlu16n_rev(u16_rev_from, wr.u16_rev_pos())?;
+            wr.align_byte();
+            let size = wr.pos().0 - unsized_start;
+            wr.write_size_rev(size, Error::ItemTooLong)?;
+        }
+        (Type::Vec(ty_vec), DynValue::Vec(values)) => {
+            wr.write_size_rev(values.len(), Error::VecTooLong)?;
+            let mut prev = None;
+            for value in values {
+                match (ty_vec.encoding, ty_vec.element.as_ref(), prev) {
+                    (VecEncoding::Delta, Type::Discrete(ty_discrete), Some(prev)) => {
+                        let val = if ty_discrete.is_signed {
+                            fit::<i64, _>(as_signed(value, name)?)?
+                        } else {
+                            fit::<u64, _>(as_unsigned(value, name)?)? as i64
+                        };
+                        // narrower types are checked by adding a zero difference
+                        add_delta(ty_discrete, val, 0)?;
+                        wr.write_vli64n(val.wrapping_sub(prev))?;
+                    }
+                    (VecEncoding::Quantized { min, max, bits }, _, _) => {
+                        let DynValue::F32(val) = value else {
+                            return Err(DynError::TypeMismatch(name.to_string()));
+                        };
+                        let code = Quantization::new(min, max, bits).quantize(*val);
+                        wr.write_un(code as u64, bits)?;
+                    }
+                    _ => ser_ty(file, &ty_vec.element, value, name, wr)?,
+                }
+                prev = Some(delta_element(value));
+            }
+        }
+        (Type::Option(some_ty), DynValue::Option(val)) => match val {
+            Some(val) => {
+                wr.write_bool(true)?;
+                ser_ty(file, some_ty, val, name, wr)?;
+            }
+            None => wr.write_bool(false)?,
+        },
+        (Type::Result(ok_ty, err_ty), DynValue::Result(val)) => match val {
+            Ok(val) => {
+                wr.write_bool(true)?;
+                ser_ty(file, ok_ty, val, name, wr)?;
+            }
+            Err(val) => {
+                wr.write_bool(false)?;
+                ser_ty(file, err_ty, val, name, wr)?;
+            }
+        },
+        _ => return Err(DynError::TypeMismatch(name.to_string())),
+    }
+    Ok(())
+}
+
+fn write_discrete(
+    ty: &TypeDiscrete,
+    value: &DynValue,
+    name: &str,
+    wr: &mut BufWriter,
+) -> Result<(), DynError> {
+    check_bounds(ty, value)?;
+    if ty.is_signed {
+        let val = as_signed(value, name)?;
+        match ty.bits {
+            8 => wr.write_i8(fit(val)?)?,
+            16 => wr.write_i16(fit(val)?)?,
+            32 => wr.write_i32(fit(val)?)?,
+            64 => wr.write_i64(fit(val)?)?,
+            128 => wr.write_i128(val)?,
+            bits => wr.write_in(fit(val)?, bits as u8)?,
+        }
+    } else {
+        let val = as_unsigned(value, name)?;
+        match ty.bits {
+            4 => {
+                let val: u8 = fit(val)?;
+                if val > 0xF {
+                    return Err(Error::OutOfRange.into());
+                }
+                wr.write_u4(val)?
+            }
+            8 => wr.write_u8(fit(val)?)?,
+            16 => wr.write_u16(fit(val)?)?,
+            32 => wr.write_u32(fit(val)?)?,
+            64 => wr.write_u64(fit(val)?)?,
+            128 => wr.write_u128(val)?,
+            bits => wr.write_un(fit(val)?, bits as u8)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_variable_length(
+    ty: &TypeVariableLength,
+    value: &DynValue,
+    name: &str,
+    wr: &mut BufWriter,
+) -> Result<(), DynError> {
+    if ty.discrete.is_signed {
+        let val = as_signed(value, name)?;
+        match (ty.encoding, ty.discrete.bits) {
+            (VariableLengthEncoding::Leb, 16) => wr.write_leb_i16(fit(val)?)?,
+            (VariableLengthEncoding::Leb, 32) => wr.write_leb_i32(fit(val)?)?,
+            (VariableLengthEncoding::Leb, 64) => wr.write_leb_i64(fit(val)?)?,
+            (VariableLengthEncoding::Leb, _) => wr.write_leb_i128(val)?,
+            (VariableLengthEncoding::Nib, 16) => wr.write_vli16n(fit(val)?)?,
+            (VariableLengthEncoding::Nib, 32) => wr.write_vli32n(fit(val)?)?,
+            (VariableLengthEncoding::Nib, _) => wr.write_vli64n(fit(val)?)?,
+        }
+    } else {
+        let val = as_unsigned(value, name)?;
+        match (ty.encoding, ty.discrete.bits) {
+            (VariableLengthEncoding::Leb, 16) => wr.write_leb_u16(fit(val)?)?,
+            (VariableLengthEncoding::Leb, 32) => wr.write_leb_u32(fit(val)?)?,
+            (VariableLengthEncoding::Leb, 64) => wr.write_leb_u64(fit(val)?)?,
+            (VariableLengthEncoding::Leb, _) => wr.write_leb_u128(val)?,
+            (VariableLengthEncoding::Nib, 16) => wr.write_vlu16n(fit(val)?)?,
+            (VariableLengthEncoding::Nib, 32) => wr.write_vlu32n(fit(val)?)?,
+            (VariableLengthEncoding::Nib, _) => wr.write_vlu64n(fit(val)?)?,
+        }
+    }
+    Ok(())
+}
+
+/// Signed and unsigned numbers are interchangeable as long as the value fits.
+fn as_unsigned(value: &DynValue, name: &str) -> Result<u128, DynError> {
+    match value {
+        DynValue::Unsigned(val) => Ok(*val),
+        DynValue::Signed(val) => fit(*val),
+        _ => Err(DynError::TypeMismatch(name.to_string())),
+    }
+}
+
+fn as_signed(value: &DynValue, name: &str) -> Result<i128, DynError> {
+    match value {
+        DynValue::Signed(val) => Ok(*val),
+        DynValue::Unsigned(val) => fit(*val),
+        _ => Err(DynError::TypeMismatch(name.to_string())),
+    }
+}
+
+fn fit<T: TryFrom<V>, V>(val: V) -> Result<T, DynError> {
+    T::try_from(val).map_err(|_| DynError::ShrinkWrap(Error::OutOfRange))
+}
@@ -1,4 +1,5 @@
 pub mod ast;
 pub mod codegen;
+pub mod dyn_value;
 
 pub use ast::version::Version;
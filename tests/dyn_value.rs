@@ -0,0 +1,452 @@
+use shrink_wrap::{BufReader, BufWriter, DeserializeShrinkWrap, ElementSize, SerializeShrinkWrap};
+use wire_weaver::wire_weaver;
+use wire_weaver_core::ast::file::{File, FileSource};
+use wire_weaver_core::dyn_value::{
+    self, AnnotationKind, DynEnum, DynError, DynField, DynStruct, DynValue,
+};
+
+wire_weaver!(
+    r#"
+    struct X {
+        a: bool,
+        b: u4,
+        c: i7,
+        d: u32,
+        e: leb<i64>,
+        f: nib<u32>,
+        name: String,
+        data: bytes<4>,
+        y: Option<Y>,
+        e2: E,
+        k: K,
+        r: Result<u8, f32>,
+    }
+    struct Y { z: u12 }
+    enum E { A, B(u16), C { y: Y } }
+    enum K { P, Q }
+    "#
+);
+
+const SCHEMA: &str = r#"
+    struct X {
+        a: bool,
+        b: u4,
+        c: i7,
+        d: u32,
+        e: leb<i64>,
+        f: nib<u32>,
+        name: String,
+        data: bytes<4>,
+        y: Option<Y>,
+        e2: E,
+        k: K,
+        r: Result<u8, f32>,
+    }
+    struct Y { z: u12 }
+    enum E { A, B(u16), C { y: Y } }
+    enum K { P, Q }
+"#;
+
+fn schema() -> File {
+    let syn_file = syn::parse_file(SCHEMA).unwrap();
+    File::from_syn(FileSource::Registry, syn_file).unwrap().0
+}
+
+fn x() -> X<'static> {
+    X {
+        a: true,
+        b: 0xA,
+        c: -5,
+        d: 0xAABBCCDD,
+        e: -1000,
+        f: 300,
+        name: "abc",
+        data: &[1, 2],
+        y: Some(Y { z: 0x123 }),
+        e2: E::C { y: Y { z: 7 } },
+        k: K::Q,
+        r: Err(1.5),
+    }
+}
+
+fn field(name: &str, value: DynValue) -> DynField {
+    DynField {
+        name: name.to_string(),
+        value,
+    }
+}
+
+fn y(z: u128) -> DynValue {
+    DynValue::Struct(DynStruct {
+        name: "Y".into(),
+        fields: vec![field("z", DynValue::Unsigned(z))],
+    })
+}
+
+fn x_dyn() -> DynValue {
+    DynValue::Struct(DynStruct {
+        name: "X".into(),
+        fields: vec![
+            field("a", DynValue::Bool(true)),
+            field("b", DynValue::Unsigned(0xA)),
+            field("c", DynValue::Signed(-5)),
+            field("d", DynValue::Unsigned(0xAABBCCDD)),
+            field("e", DynValue::Signed(-1000)),
+            field("f", DynValue::Unsigned(300)),
+            field("name", DynValue::String("abc".into())),
+            field("data", DynValue::Bytes(vec![1, 2])),
+            field("y", DynValue::Option(Some(Box::new(y(0x123))))),
+            field(
+                "e2",
+                DynValue::Enum(DynEnum {
+                    name: "E".into(),
+                    variant: "C".into(),
+                    fields: vec![field("y", y(7))],
+                }),
+            ),
+            field(
+                "k",
+                DynValue::Enum(DynEnum {
+                    name: "K".into(),
+                    variant: "Q".into(),
+                    fields: vec![],
+                }),
+            ),
+            field("r", DynValue::Result(Err(Box::new(DynValue::F32(1.5))))),
+        ],
+    })
+}
+
+#[test]
+fn decode_generated() {
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    x().ser_shrink_wrap(&mut wr).unwrap();
+    let bytes = wr.finish().unwrap();
+
+    let mut rd = BufReader::new(bytes);
+    let value = dyn_value::decode(&schema(), "X", &mut rd).unwrap();
+    assert_eq!(value, x_dyn());
+}
+
+#[test]
+fn encode_same_as_generated() {
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    x().ser_shrink_wrap(&mut wr).unwrap();
+    let expected = wr.finish().unwrap();
+
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    dyn_value::encode(&schema(), "X", &x_dyn(), &mut wr).unwrap();
+    let bytes = wr.finish().unwrap();
+    assert_eq!(bytes, expected);
+
+    let mut rd = BufReader::new(bytes);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.name, "abc");
+    assert!(matches!(x.e2, E::C { y: Y { z: 7 } }));
+    assert!(matches!(x.k, K::Q));
+}
+
+#[test]
+fn encode_errors() {
+    let schema = schema();
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    assert_eq!(
+        dyn_value::encode(&schema, "Z", &y(0), &mut wr),
+        Err(DynError::UnknownItem("Z".into()))
+    );
+    assert_eq!(
+        dyn_value::encode(&schema, "Y", &y(0x1000), &mut wr),
+        Err(DynError::ShrinkWrap(shrink_wrap::Error::OutOfRange))
+    );
+    let value = DynValue::Struct(DynStruct {
+        name: "Y".into(),
+        fields: vec![field("z", DynValue::Bool(true))],
+    });
+    assert_eq!(
+        dyn_value::encode(&schema, "Y", &value, &mut wr),
+        Err(DynError::TypeMismatch("z".into()))
+    );
+    let value = DynValue::Enum(DynEnum {
+        name: "E".into(),
+        variant: "D".into(),
+        fields: vec![],
+    });
+    assert_eq!(
+        dyn_value::encode(&schema, "E", &value, &mut wr),
+        Err(DynError::UnknownVariant("D".into()))
+    );
+}
+
+#[test]
+fn annotate_fields() {
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    x().ser_shrink_wrap(&mut wr).unwrap();
+    let bytes = wr.finish().unwrap();
+
+    let annotations = dyn_value::annotate(&schema(), "X", bytes).unwrap();
+    let find = |path: &str, kind: &AnnotationKind| {
+        annotations
+            .iter()
+            .find(|a| a.path == path && &a.kind == kind)
+            .map(|a| a.bits.clone())
+    };
+    assert_eq!(
+        find("X.a", &AnnotationKind::Value(DynValue::Bool(true))),
+        Some(0..1)
+    );
+    assert_eq!(find("X", &AnnotationKind::Unused), Some(1..4));
+    assert_eq!(
+        find("X.b", &AnnotationKind::Value(DynValue::Unsigned(0xA))),
+        Some(4..8)
+    );
+    assert_eq!(
+        find(
+            "X.d",
+            &AnnotationKind::Value(DynValue::Unsigned(0xAABBCCDD))
+        ),
+        Some(16..48)
+    );
+    assert_eq!(
+        find("X.y", &AnnotationKind::Item("Y".into())),
+        Some(128..144)
+    );
+    assert_eq!(
+        find("X.y.z", &AnnotationKind::Value(DynValue::Unsigned(0x123))),
+        Some(128..140)
+    );
+    let len = bytes.len() * 8;
+    assert_eq!(find("X.name", &AnnotationKind::Size(3)), Some(len - 4..len));
+    assert_eq!(
+        find("X.data", &AnnotationKind::Size(2)),
+        Some(len - 8..len - 4)
+    );
+    assert!(!annotations
+        .iter()
+        .any(|a| matches!(a.kind, AnnotationKind::Corrupt(_))));
+
+    let dump = dyn_value::hex_dump(&schema(), "X", bytes).unwrap();
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(
+        lines[0],
+        "0000..0001  8a                         bits 0..1  X.a  = true"
+    );
+    assert!(lines.contains(&"0002..0006  dd cc bb aa                           X.d  = 2864434397"));
+    assert!(lines.contains(&"0012..0013  30                         bits 0..4  X.e2  = C (3)"));
+    let table = lines
+        .iter()
+        .position(|l| *l == "-- reversed size table --")
+        .unwrap();
+    assert_eq!(
+        &lines[table + 1..],
+        &[
+            "001c..001d  01                         bits 4..8  X.k  len 1",
+            "001d..001e  42                         bits 0..4  X.e2  len 4",
+            "001d..001e  42                         bits 4..8  X.y  len 2",
+            "001e..001f  23                         bits 0..4  X.data  len 2",
+            "001e..001f  23                         bits 4..8  X.name  len 3",
+        ]
+    );
+}
+
+#[test]
+fn annotate_corrupt() {
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    x().ser_shrink_wrap(&mut wr).unwrap();
+    let mut bytes = wr.finish().unwrap().to_vec();
+    // size of y is too big, continued into the nibble of e2's size
+    let len = bytes.len();
+    bytes[len - 2] = 0x4F;
+
+    let annotations = dyn_value::annotate(&schema(), "X", &bytes).unwrap();
+    let corrupt = annotations
+        .iter()
+        .find(|a| matches!(a.kind, AnnotationKind::Corrupt(_)))
+        .unwrap();
+    assert_eq!(
+        corrupt.kind,
+        AnnotationKind::Corrupt(shrink_wrap::Error::OutOfBounds)
+    );
+    assert_eq!(corrupt.bits.start, 128);
+    // fields before the corrupt one are still there
+    assert!(annotations.iter().any(
+        |a| a.path == "X.data" && a.kind == AnnotationKind::Value(DynValue::Bytes(vec![1, 2]))
+    ));
+    let dump = dyn_value::hex_dump(&schema(), "X", &bytes).unwrap();
+    assert!(dump
+        .contains("0010..001d  12 30 30 00 70 02 20 00 ..            X  !! corrupt: OutOfBounds"));
+
+    // truncated buffer
+    let dump = dyn_value::hex_dump(&schema(), "X", &bytes[..10]).unwrap();
+    assert!(dump.contains("!! corrupt: OutOfBoundsRev"));
+}
+
+#[test]
+fn delta_and_quantized_vec() {
+    let schema = r#"
+        struct T {
+            #[delta] samples: Vec<u16>,
+            #[quantize(min = 0.0, max = 10.0, bits = 4)] levels: Vec<f32>,
+        }
+    "#;
+    let schema = File::from_syn(FileSource::Registry, syn::parse_file(schema).unwrap())
+        .unwrap()
+        .0;
+    let mut value = DynValue::Struct(DynStruct {
+        name: "T".into(),
+        fields: vec![
+            field(
+                "samples",
+                DynValue::Vec(vec![
+                    DynValue::Unsigned(0x1234),
+                    DynValue::Unsigned(0x1235),
+                    DynValue::Unsigned(0x1233),
+                ]),
+            ),
+            field(
+                "levels",
+                DynValue::Vec(vec![DynValue::F32(0.0), DynValue::F32(10.0)]),
+            ),
+        ],
+    });
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    dyn_value::encode(&schema, "T", &value, &mut wr).unwrap();
+    let bytes = wr.finish().unwrap();
+    // same as DeltaVec and QuantizedVec
+    assert_eq!(bytes, &[0x34, 0x12, 0x23, 0x0F, 0x23]);
+
+    let mut rd = BufReader::new(bytes);
+    assert_eq!(dyn_value::decode(&schema, "T", &mut rd), Ok(value.clone()));
+
+    let annotations = dyn_value::annotate(&schema, "T", bytes).unwrap();
+    let delta = annotations
+        .iter()
+        .find(|a| a.path == "T.samples.2")
+        .unwrap();
+    assert_eq!(delta.bits, 20..24);
+    assert_eq!(
+        delta.kind,
+        AnnotationKind::Value(DynValue::Unsigned(0x1233))
+    );
+
+    // difference fits into Vlu64N, but the value itself does not fit into u16
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    if let DynValue::Struct(dyn_struct) = &mut value {
+        dyn_struct.fields[0].value =
+            DynValue::Vec(vec![DynValue::Unsigned(1), DynValue::Unsigned(70_000)]);
+    }
+    assert_eq!(
+        dyn_value::encode(&schema, "T", &value, &mut wr),
+        Err(DynError::ShrinkWrap(shrink_wrap::Error::OutOfRange))
+    );
+}
+
+#[test]
+fn fixed_point() {
+    let schema = r#" struct F { a: q15, b: fixed<u16, 4> } "#;
+    let schema = File::from_syn(FileSource::Registry, syn::parse_file(schema).unwrap())
+        .unwrap()
+        .0;
+    let value = DynValue::Struct(DynStruct {
+        name: "F".into(),
+        fields: vec![
+            field("a", DynValue::F32(-0.5)),
+            field("b", DynValue::F64(2.5)),
+        ],
+    });
+    let mut buf = [0u8; 16];
+    let mut wr = BufWriter::new(&mut buf);
+    dyn_value::encode(&schema, "F", &value, &mut wr).unwrap();
+    let bytes = wr.finish().unwrap();
+    assert_eq!(bytes, &[0x00, 0xC0, 0x28, 0x00]);
+
+    let mut rd = BufReader::new(bytes);
+    let decoded = dyn_value::decode(&schema, "F", &mut rd).unwrap();
+    assert_eq!(
+        decoded.field("a"),
+        Some(&DynValue::Fixed {
+            raw: -0x4000,
+            frac_bits: 15
+        })
+    );
+    assert_eq!(decoded.to_string(), "F { a: -0.5, b: 2.5 }");
+}
+
+#[test]
+fn bounded_numbers() {
+    let schema = r#" struct B { a: u8<{1..=3}, 7> } "#;
+    let schema = File::from_syn(FileSource::Registry, syn::parse_file(schema).unwrap())
+        .unwrap()
+        .0;
+    let b = |a| {
+        DynValue::Struct(DynStruct {
+            name: "B".into(),
+            fields: vec![field("a", DynValue::Unsigned(a))],
+        })
+    };
+    let mut buf = [0u8; 8];
+    let mut wr = BufWriter::new(&mut buf);
+    assert_eq!(
+        dyn_value::encode(&schema, "B", &b(4), &mut wr),
+        Err(DynError::ShrinkWrap(shrink_wrap::Error::ValueOutOfBounds(
+            4
+        )))
+    );
+    let mut rd = BufReader::new(&[7]);
+    assert_eq!(dyn_value::decode(&schema, "B", &mut rd), Ok(b(7)));
+    let mut rd = BufReader::new(&[0]);
+    assert_eq!(
+        dyn_value::decode(&schema, "B", &mut rd),
+        Err(DynError::ShrinkWrap(shrink_wrap::Error::ValueOutOfBounds(
+            0
+        )))
+    );
+}
+
+#[test]
+fn unaligned_nested_item() {
+    wire_weaver!(r#" struct U { v: V, a: u8 } struct V { z: u4 } "#);
+    let schema = r#" struct U { v: V, a: u8 } struct V { z: u4 } "#;
+    let schema = File::from_syn(FileSource::Registry, syn::parse_file(schema).unwrap())
+        .unwrap()
+        .0;
+    let value = DynValue::Struct(DynStruct {
+        name: "U".into(),
+        fields: vec![
+            field(
+                "v",
+                DynValue::Struct(DynStruct {
+                    name: "V".into(),
+                    fields: vec![field("z", DynValue::Unsigned(0x5))],
+                }),
+            ),
+            field("a", DynValue::Unsigned(0xAA)),
+        ],
+    });
+    let mut buf = [0u8; 16];
+    let mut wr = BufWriter::new(&mut buf);
+    dyn_value::encode(&schema, "U", &value, &mut wr).unwrap();
+    let bytes = wr.finish().unwrap();
+    // V ends mid-byte, its size is 1, same as generated code writes
+    assert_eq!(bytes, &[0x50, 0xAA, 0x01]);
+
+    let mut buf = [0u8; 16];
+    let mut wr = BufWriter::new(&mut buf);
+    U {
+        v: V { z: 0x5 },
+        a: 0xAA,
+    }
+    .ser_shrink_wrap(&mut wr)
+    .unwrap();
+    assert_eq!(wr.finish().unwrap(), bytes);
+
+    let mut rd = BufReader::new(bytes);
+    assert_eq!(dyn_value::decode(&schema, "U", &mut rd), Ok(value));
+}
@@ -0,0 +1,287 @@
+use shrink_wrap::serde_bridge::{self, Deserializer, Serializer};
+use shrink_wrap::{
+    BufReader, BufWriter, DeserializeShrinkWrap, ElementSize, ReadLimits, RefVec,
+    SerializeShrinkWrap,
+};
+use std::collections::BTreeMap;
+use wire_weaver::wire_weaver;
+
+wire_weaver!(
+    r#"
+    struct Msg {
+        flag: bool,
+        id: u16,
+        name: String,
+        payload: Option<Inner>,
+        cmd: Cmd,
+        mode: Mode,
+        result: Result<u8, f32>,
+        temp: f32,
+    }
+    struct Inner { a: u8, b: i32 }
+    enum Cmd { Stop, Move(u16), Set { inner: Inner } }
+    enum Mode { Idle, Busy }
+    "#
+);
+
+/// Hand-written types with the same shape as the generated ones.
+mod host {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub struct Msg {
+        pub flag: bool,
+        pub id: u16,
+        pub name: String,
+        pub payload: Option<Inner>,
+        pub cmd: Cmd,
+        pub mode: Mode,
+        pub result: Result<u8, f32>,
+        pub temp: f32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub struct Inner {
+        pub a: u8,
+        pub b: i32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub enum Cmd {
+        Stop,
+        Move(u16),
+        Set { inner: Inner },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub enum Mode {
+        Idle,
+        Busy,
+    }
+}
+
+fn host_msg(cmd: host::Cmd) -> host::Msg {
+    host::Msg {
+        flag: true,
+        id: 0x1234,
+        name: "abc".into(),
+        payload: Some(host::Inner { a: 7, b: -2 }),
+        cmd,
+        mode: host::Mode::Busy,
+        result: Err(1.5),
+        temp: -0.25,
+    }
+}
+
+fn to_bytes(msg: &host::Msg) -> Vec<u8> {
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    let mut ser = Serializer::new(&mut wr);
+    ser.set_result_enums(&["Result"]);
+    serde::Serialize::serialize(msg, &mut ser).unwrap();
+    wr.finish().unwrap().to_vec()
+}
+
+fn from_bytes(bytes: &[u8]) -> host::Msg {
+    let mut rd = BufReader::new(bytes);
+    let mut de = Deserializer::new(&mut rd);
+    de.set_result_enums(&["Result"]);
+    serde::Deserialize::deserialize(&mut de).unwrap()
+}
+
+#[test]
+fn byte_compatible_with_generated() {
+    let cases = [
+        (Cmd::Stop, host::Cmd::Stop),
+        (Cmd::Move(300), host::Cmd::Move(300)),
+        (
+            Cmd::Set {
+                inner: Inner { a: 1, b: 2 },
+            },
+            host::Cmd::Set {
+                inner: host::Inner { a: 1, b: 2 },
+            },
+        ),
+    ];
+    for (cmd, host_cmd) in cases {
+        let msg = Msg {
+            flag: true,
+            id: 0x1234,
+            name: "abc",
+            payload: Some(Inner { a: 7, b: -2 }),
+            cmd,
+            mode: Mode::Busy,
+            result: Err(1.5),
+            temp: -0.25,
+        };
+        let mut buf = [0u8; 256];
+        let mut wr = BufWriter::new(&mut buf);
+        msg.ser_shrink_wrap(&mut wr).unwrap();
+        let generated = wr.finish().unwrap();
+
+        let host_msg = host_msg(host_cmd);
+        let bytes = to_bytes(&host_msg);
+        assert_eq!(bytes, generated);
+        assert_eq!(from_bytes(generated), host_msg);
+
+        let mut rd = BufReader::new(&bytes);
+        let msg = Msg::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+        assert_eq!(msg.name, "abc");
+        assert!(matches!(msg.mode, Mode::Busy));
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+struct Collections<'a> {
+    inner: Vec<host::Inner>,
+    bytes: Vec<u8>,
+    pair: (u8, String),
+    map: BTreeMap<u16, bool>,
+    c: char,
+    unit: Unit,
+    none: Option<u128>,
+    #[serde(borrow)]
+    borrowed: &'a str,
+    wrapped: Wrapper,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+struct Unit;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+struct Wrapper(i64);
+
+#[test]
+fn collections_round_trip() {
+    let value = Collections {
+        inner: vec![host::Inner { a: 1, b: 2 }, host::Inner { a: 3, b: 4 }],
+        bytes: vec![1, 2, 3],
+        pair: (5, "pair".into()),
+        map: BTreeMap::from([(1, true), (2, false)]),
+        c: 'ж',
+        unit: Unit,
+        none: None,
+        borrowed: "borrowed",
+        wrapped: Wrapper(-1),
+    };
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    serde_bridge::to_writer(&value, &mut wr).unwrap();
+    let bytes = wr.finish().unwrap();
+    let mut rd = BufReader::new(bytes);
+    let decoded: Collections = serde_bridge::from_reader(&mut rd).unwrap();
+    assert_eq!(decoded, value);
+}
+
+#[test]
+fn sequence_same_as_ref_vec() {
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    RefVec::from(&[1u16, 2, 3][..])
+        .ser_shrink_wrap(&mut wr)
+        .unwrap();
+    let expected = wr.finish().unwrap().to_vec();
+
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    serde_bridge::to_writer(&vec![1u16, 2, 3], &mut wr).unwrap();
+    assert_eq!(wr.finish().unwrap(), expected);
+}
+
+#[test]
+fn self_describing_enums_in_sequence() {
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    RefVec::from(&[Mode::Busy, Mode::Idle][..])
+        .ser_shrink_wrap(&mut wr)
+        .unwrap();
+    let expected = wr.finish().unwrap().to_vec();
+
+    let modes = vec![host::Mode::Busy, host::Mode::Idle];
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    let mut ser = Serializer::new(&mut wr);
+    ser.set_self_describing(&["Mode"]);
+    serde::Serialize::serialize(&modes, &mut ser).unwrap();
+    assert_eq!(wr.finish().unwrap(), expected);
+
+    let mut rd = BufReader::new(&expected);
+    let mut de = Deserializer::new(&mut rd);
+    de.set_self_describing(&["Mode"]);
+    let decoded: Vec<host::Mode> = serde::Deserialize::deserialize(&mut de).unwrap();
+    assert_eq!(decoded, modes);
+}
+
+#[test]
+fn result_enums_opt_in() {
+    let value: (Result<u8, f32>,) = (Ok(5),);
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    wr.write_bool(true).unwrap();
+    wr.write_u8(5).unwrap();
+    let expected = wr.finish().unwrap().to_vec();
+
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    let mut ser = Serializer::new(&mut wr);
+    ser.set_result_enums(&["Result"]);
+    serde::Serialize::serialize(&value, &mut ser).unwrap();
+    assert_eq!(wr.finish().unwrap(), expected);
+
+    let mut rd = BufReader::new(&expected);
+    let mut de = Deserializer::new(&mut rd);
+    de.set_result_enums(&["Result"]);
+    let decoded: (Result<u8, f32>,) = serde::Deserialize::deserialize(&mut de).unwrap();
+    assert_eq!(decoded, value);
+
+    // Not listed, written as any other enum
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    serde_bridge::to_writer(&value, &mut wr).unwrap();
+    assert_ne!(wr.finish().unwrap(), expected);
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+struct Node {
+    next: Option<Box<Node>>,
+}
+
+fn chain(len: usize) -> Node {
+    (0..len).fold(Node { next: None }, |node, _| Node {
+        next: Some(Box::new(node)),
+    })
+}
+
+#[test]
+fn limits_depth_of_recursive_types() {
+    let mut buf = [0u8; 256];
+    let mut wr = BufWriter::new(&mut buf);
+    serde_bridge::to_writer(&chain(8), &mut wr).unwrap();
+    let bytes = wr.finish().unwrap();
+
+    let limits = ReadLimits::new(32, 1024, 1024);
+    let mut rd = BufReader::new_with_limits(bytes, &limits);
+    let decoded: Node = serde_bridge::from_reader(&mut rd).unwrap();
+    assert_eq!(decoded, chain(8));
+
+    let limits = ReadLimits::new(8, 1024, 1024);
+    let mut rd = BufReader::new_with_limits(bytes, &limits);
+    let r: Result<Node, _> = serde_bridge::from_reader(&mut rd);
+    assert_eq!(r.err(), Some(shrink_wrap::Error::LimitExceeded));
+}
+
+#[test]
+fn deserialize_any_unsupported() {
+    let mut rd = BufReader::new(&[0x00]);
+    let r: Result<serde::de::IgnoredAny, _> = serde_bridge::from_reader(&mut rd);
+    assert_eq!(r.err(), Some(shrink_wrap::Error::SerdeUnsupported));
+}
+
+#[test]
+fn collect_str() {
+    let mut buf = [0u8; 64];
+    let mut wr = BufWriter::new(&mut buf);
+    serde_bridge::to_writer(&format_args!("{}-{}", 42, "x"), &mut wr).unwrap();
+    let mut rd = BufReader::new(wr.finish().unwrap());
+    assert_eq!(rd.read_str(), Ok("42-x"));
+}
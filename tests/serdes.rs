@@ -1,4 +1,4 @@
-use shrink_wrap::SerializeShrinkWrap;
+use shrink_wrap::{DeserializeShrinkWrap, ElementSize, SerializeShrinkWrap};
 use wire_weaver::wire_weaver;
 
 macro_rules! ser_and_cmp {
@@ -52,7 +52,13 @@ fn enum_vlu16n_standalone() {
 fn enum_vlu16n_in_struct() {
     wire_weaver!(r#" #[repr(vlu16n)] enum E { A, B, C = 9 } struct X { e: E } "#);
     let x = X { e: E::A };
-    ser_and_cmp!(x, &[0x10]);
+    // nested enum is prefixed with its size, same as enums with data
+    ser_and_cmp!(x, &[0x10, 0x01]);
+
+    let buf = [0x91, 0x01];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert!(matches!(x.e, E::C));
 }
 
 /// Unsized enum, but since it's the root object, size is not written
@@ -84,6 +90,60 @@ fn enum_vlu16n_data_in_struct() {
     ser_and_cmp!(x, &[0x91, 0xAA, 0xCC, 0xBB, 0x04]);
 }
 
+#[test]
+fn option_packs_with_bools_and_nibbles() {
+    wire_weaver!(r#" struct X { a: bool, b: Option<u4>, c: Option<u8>, d: bool } "#);
+    let x = X {
+        a: true,
+        b: Some(5),
+        c: None,
+        d: true,
+    };
+    ser_and_cmp!(x, &[0b1100_0101, 0b0100_0000]);
+
+    let buf = [0b1100_0101, 0b1000_0000, 0xAA, 0b0000_0000];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert!(x.a);
+    assert_eq!(x.b, Some(5));
+    assert_eq!(x.c, Some(0xAA));
+    assert!(!x.d);
+}
+
+#[test]
+fn result_in_struct() {
+    wire_weaver!(r#" struct X { r1: Result<u16, u8>, r2: Result<u16, u8> } "#);
+    let x = X {
+        r1: Ok(0xAABB),
+        r2: Err(0xCC),
+    };
+    ser_and_cmp!(x, &[0b1000_0000, 0xBB, 0xAA, 0b0000_0000, 0xCC]);
+
+    let buf = [0b1000_0000, 0xBB, 0xAA, 0b0000_0000, 0xCC];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.r1, Ok(0xAABB));
+    assert_eq!(x.r2, Err(0xCC));
+}
+
+#[test]
+fn option_struct_in_struct() {
+    wire_weaver!(r#" struct X { a: bool, y: Option<Y>, z: Option<Y> } struct Y { b: u8 } "#);
+    let x = X {
+        a: true,
+        y: Some(Y { b: 0xAA }),
+        z: None,
+    };
+    // Y is aligned to byte boundary, its size is written at the back
+    ser_and_cmp!(x, &[0b1100_0000, 0xAA, 0b0000_0001]);
+
+    let buf = [0b1100_0000, 0xAA, 0b0000_0001];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.y.map(|y| y.b), Some(0xAA));
+    assert!(x.z.is_none());
+}
+
 // #[test]
 // fn enum_vlu16n_final() {
 //     wire_weaver!(r#"
@@ -153,3 +213,461 @@ fn enum_vlu16n_data_in_struct() {
 //     ser_and_cmp!(x, &[]);
 // }
 //
+
+#[test]
+fn leb_in_struct() {
+    wire_weaver!(r#" struct X { a: bool, counter: leb<u32>, offset: leb<i64> } "#);
+    let x = X {
+        a: true,
+        counter: 300,
+        offset: -2,
+    };
+    ser_and_cmp!(x, &[0b1000_0000, 0xAC, 0x02, 0x03]);
+
+    let buf = [0b1000_0000, 0xAC, 0x02, 0x03];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert!(x.a);
+    assert_eq!(x.counter, 300);
+    assert_eq!(x.offset, -2);
+}
+
+#[test]
+fn nib_in_struct() {
+    wire_weaver!(r#" struct X { a: nib<u32>, b: nib<i16>, c: nib<u64> } "#);
+    let x = X { a: 63, b: -1, c: 5 };
+    ser_and_cmp!(x, &[0b1111_0111, 0b0001_0101]);
+
+    let buf = [0b1111_0111, 0b0001_0101];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.a, 63);
+    assert_eq!(x.b, -1);
+    assert_eq!(x.c, 5);
+}
+
+#[test]
+fn enum_vlu32n_standalone() {
+    wire_weaver!(r#" #[repr(vlu32n)] enum E { A, B = 70000 } "#);
+    let e = E::A;
+    ser_and_cmp!(e, &[0x10]);
+    let e = E::B;
+    ser_and_cmp!(e, &[0xA9, 0x8D, 0xE0]);
+
+    let buf = [0xA9, 0x8D, 0xE0];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let e = E::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(e.discriminant(), 70000u32);
+}
+
+#[test]
+fn large_payload_struct_in_struct() {
+    wire_weaver!(r#" #[large_payload] struct X { a: u8, y: Y } struct Y { b: u8 } "#);
+    let x = X {
+        a: 0xAA,
+        y: Y { b: 0xBB },
+    };
+    // sizes below 65536 are encoded the same way in large payload mode
+    ser_and_cmp!(x, &[0xAA, 0xBB, 0x01]);
+
+    let buf = [0xAA, 0xBB, 0x01];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.a, 0xAA);
+    assert_eq!(x.y.b, 0xBB);
+}
+
+#[test]
+fn large_payload_file() {
+    wire_weaver!(r#" #![large_payload] struct X { y: Y } struct Y { b: u8 } "#);
+    let x = X { y: Y { b: 0xBB } };
+    ser_and_cmp!(x, &[0xBB, 0x01]);
+
+    let buf = [0xBB, 0x01];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.y.b, 0xBB);
+}
+
+#[test]
+fn error_context_path() {
+    wire_weaver!(r#" struct X { a: u8, y: Y } struct Y { b: u8, c: u16 } "#);
+    // Y is only 2 bytes long, so reading c fails
+    let buf = [0xAA, 0xBB, 0xCC, 0x02];
+    let ctx = shrink_wrap::ErrorContext::new();
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    rd.set_error_context(&ctx);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied);
+    assert!(matches!(x, Err(shrink_wrap::Error::OutOfBounds)));
+    assert_eq!(ctx.position(), Some((2, 7)));
+    assert_eq!(ctx.path().collect::<Vec<_>>(), ["X", "y", "Y", "c"]);
+    assert_eq!(ctx.to_string(), "X.y.Y.c at byte 2 bit 7");
+}
+
+#[test]
+fn error_context_enum_variant() {
+    wire_weaver!(r#" enum E { A { a: u8 }, B(u16) } "#);
+    let buf = [0x20, 0xCC];
+    let ctx = shrink_wrap::ErrorContext::new();
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    rd.set_error_context(&ctx);
+    let e = E::des_shrink_wrap(&mut rd, ElementSize::Implied);
+    assert!(e.is_err());
+    assert_eq!(ctx.to_string(), "E.B._0 at byte 1 bit 7");
+}
+
+#[test]
+fn bytes_in_struct() {
+    wire_weaver!(r#" struct X { a: u8, data: bytes, tail: bytes<2> } "#);
+    let x = X {
+        a: 0xAA,
+        data: &[1, 2, 3],
+        tail: &[4],
+    };
+    // lengths 3 and 1 are written to the back
+    ser_and_cmp!(x, &[0xAA, 1, 2, 3, 4, 0x13]);
+
+    let buf = [0xAA, 1, 2, 3, 4, 0x13];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.a, 0xAA);
+    assert_eq!(x.data, &[1, 2, 3]);
+    assert_eq!(x.tail, &[4]);
+
+    let x = X {
+        a: 0xAA,
+        data: &[],
+        tail: &[4, 5, 6],
+    };
+    let mut buf = [0u8; 256];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    assert_eq!(
+        x.ser_shrink_wrap(&mut wr),
+        Err(shrink_wrap::Error::VecTooLong)
+    );
+}
+
+#[test]
+fn ref_types_in_nested_items() {
+    wire_weaver!(r#" struct X { y: Y, e: E } struct Y { name: String } enum E { A, B(bytes) } "#);
+    let x = X {
+        y: Y { name: "ab" },
+        e: E::B(&[0xCC]),
+    };
+    let mut buf = [0u8; 256];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    x.ser_shrink_wrap(&mut wr).unwrap();
+    let buf = wr.finish().unwrap();
+
+    let mut rd = shrink_wrap::BufReader::new(buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.y.name, "ab");
+    assert!(matches!(x.e, E::B(&[0xCC])));
+}
+
+#[test]
+fn arbitrary_width_in_struct() {
+    wire_weaver!(r#" struct X { mode: u3, adc: u12, offset: i5, flag: bool } "#);
+    let x = X {
+        mode: 5,
+        adc: 0xABC,
+        offset: -2,
+        flag: true,
+    };
+    ser_and_cmp!(x, &[0b1011_0101, 0b0111_1001, 0b1110_1000]);
+
+    let buf = [0b1011_0101, 0b0111_1001, 0b1110_1000];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.mode, 5u8);
+    assert_eq!(x.adc, 0xABCu16);
+    assert_eq!(x.offset, -2i8);
+    assert!(x.flag);
+
+    let x = X {
+        mode: 8,
+        adc: 0,
+        offset: 0,
+        flag: false,
+    };
+    let mut buf = [0u8; 256];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    assert_eq!(
+        x.ser_shrink_wrap(&mut wr),
+        Err(shrink_wrap::Error::OutOfRange)
+    );
+}
+
+#[test]
+fn unaligned_struct_in_struct() {
+    wire_weaver!(r#" struct X { y: Y, a: u8 } struct Y { z: u12 } "#);
+    let x = X {
+        y: Y { z: 0x123 },
+        a: 0xAA,
+    };
+    // partially used last byte of Y is counted in its size
+    ser_and_cmp!(x, &[0x12, 0x30, 0xAA, 0x02]);
+
+    let buf = [0x12, 0x30, 0xAA, 0x02];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.y.z, 0x123);
+    assert_eq!(x.a, 0xAA);
+}
+
+#[test]
+fn encoded_size_matches() {
+    wire_weaver!(
+        r#" struct X { a: bool, y: Y, e: E, z: Option<Y> } struct Y { name: String, adc: u12 } enum E { A, B(bytes) } "#
+    );
+    let x = X {
+        a: true,
+        y: Y {
+            name: "abc",
+            adc: 0x123,
+        },
+        e: E::B(&[1, 2, 3, 4, 5]),
+        z: None,
+    };
+    let mut buf = [0u8; 256];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    x.ser_shrink_wrap(&mut wr).unwrap();
+    let len = wr.finish().unwrap().len();
+    assert_eq!(x.encoded_size(), Ok(len));
+    // 3 bytes of str, 12 bits of adc and str length in the last nibble
+    assert_eq!(x.y.encoded_size(), Ok(5));
+}
+
+#[test]
+fn max_encoded_size_bound() {
+    wire_weaver!(
+        r#" #[max_encoded_size] struct X { a: bool, y: Y, e: E, z: Option<Y> } struct Y { data: bytes<4>, adc: u12 } enum E { A, B(u8, u16), C(Y) } "#
+    );
+    let y = Y {
+        data: &[1, 2, 3, 4],
+        adc: 0xFFF,
+    };
+    assert!(y.encoded_size().unwrap() <= Y::MAX_ENCODED_SIZE);
+    let x = X {
+        a: true,
+        y: Y {
+            data: &[1, 2, 3, 4],
+            adc: 0x123,
+        },
+        e: E::C(Y { data: &[], adc: 0 }),
+        z: Some(y),
+    };
+    let mut buf = [0u8; X::MAX_ENCODED_SIZE];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    x.ser_shrink_wrap(&mut wr).unwrap();
+    let len = wr.finish().unwrap().len();
+    assert!(len <= X::MAX_ENCODED_SIZE);
+    assert!(E::B(0xFF, 0xFFFF).encoded_size().unwrap() <= E::MAX_ENCODED_SIZE);
+}
+
+#[test]
+fn truncate_evolvable_fields() {
+    wire_weaver!(r#" struct X { a: u16, #[default = 1.5] b: f32, #[default = 2.5] c: f32 } "#);
+    let x = X {
+        a: 0xAABB,
+        b: 0.5,
+        c: 0.25,
+    };
+    let mut buf = [0u8; 7];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    assert!(x.ser_shrink_wrap(&mut wr).is_err());
+
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    wr.set_truncate_evolvable(true);
+    x.ser_shrink_wrap(&mut wr).unwrap();
+    assert!(wr.is_truncated());
+    let bytes = wr.finish().unwrap();
+    assert_eq!(bytes.len(), 6);
+
+    let mut rd = shrink_wrap::BufReader::new(bytes);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.a, 0xAABB);
+    assert_eq!(x.b, 0.5);
+    assert_eq!(x.c, 2.5);
+}
+
+#[test]
+fn delta_and_quantized_vec() {
+    wire_weaver!(
+        r#" struct X { raw: Vec<u16>, #[delta] samples: Vec<i32>, #[quantize(min = -40.0, max = 125.0, bits = 12)] temps: Vec<f32> } "#
+    );
+    let x = X {
+        raw: (&[1, 2][..]).into(),
+        samples: (&[1000, 1001, 999][..]).into(),
+        temps: (&[-40.0, 125.0, 20.0][..]).into(),
+    };
+    // first sample, +1 and -2 zigzag encoded in one nibble each, three 12-bit temperatures,
+    // then vector lengths in the reversed area starting from the last nibble
+    ser_and_cmp!(
+        x,
+        &[
+            0x01, 0x00, 0x02, 0x00, 0xE8, 0x03, 0x00, 0x00, 0x23, 0x00, 0x0F, 0xFF, 0x5D, 0x13,
+            0x32
+        ]
+    );
+
+    let buf = [
+        0x01, 0x00, 0x02, 0x00, 0xE8, 0x03, 0x00, 0x00, 0x23, 0x00, 0x0F, 0xFF, 0x5D, 0x13, 0x32,
+    ];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.raw, (&[1, 2][..]).into());
+    assert_eq!(x.samples, (&[1000, 1001, 999][..]).into());
+    let temps: Vec<f32> = x.temps.iter().map(|t| t.unwrap()).collect();
+    assert_eq!(temps[..2], [-40.0, 125.0]);
+    assert!((temps[2] - 20.0).abs() < 165.0 / 4095.0 / 2.0);
+}
+
+#[test]
+fn fixed_point() {
+    wire_weaver!(r#" struct X { a: q15, b: Option<fixed<u32, 16>>, c: Vec<q7> } "#);
+    let c = [shrink_wrap::Fixed::<i8, 7>::from_f32(-0.5)];
+    let x = X {
+        a: shrink_wrap::Fixed::<i16, 15>::from_f32(0.5),
+        b: Some(shrink_wrap::Fixed::<u32, 16>::from_f32(1.25)),
+        c: (&c[..]).into(),
+    };
+    // same as the underlying integers, Option flag is in the first bit followed by padding
+    ser_and_cmp!(x, &[0x00, 0x40, 0x80, 0x00, 0x40, 0x01, 0x00, 0xC0, 0x01]);
+
+    let buf = [0x00, 0x40, 0x80, 0x00, 0x40, 0x01, 0x00, 0xC0, 0x01];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!(x.a.to_f32(), 0.5);
+    assert_eq!(x.b.map(|b| b.to_f64()), Some(1.25));
+    assert_eq!(x.c, (&c[..]).into());
+}
+
+#[test]
+fn bounded_numbers() {
+    wire_weaver!(
+        r#" struct X { a: u16<{1..=512}>, b: u8<{0..=8}, 12, 16>, c: Option<i7<{-8..8}>> } "#
+    );
+    let x = X {
+        a: 512,
+        b: 12,
+        c: Some(-8),
+    };
+    ser_and_cmp!(x, &[0x00, 0x02, 0x0C, 0b1111_1000]);
+
+    let mut buf = [0u8; 8];
+    for (x, value) in [
+        (
+            X {
+                a: 0,
+                b: 0,
+                c: None,
+            },
+            0,
+        ),
+        (
+            X {
+                a: 1,
+                b: 9,
+                c: None,
+            },
+            9,
+        ),
+        (
+            X {
+                a: 1,
+                b: 16,
+                c: Some(8),
+            },
+            8,
+        ),
+    ] {
+        let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+        assert_eq!(
+            x.ser_shrink_wrap(&mut wr),
+            Err(shrink_wrap::Error::ValueOutOfBounds(value))
+        );
+    }
+
+    let buf = [0x00, 0x02, 0x0D, 0b1111_1000];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    assert_eq!(
+        X::des_shrink_wrap(&mut rd, ElementSize::Implied).err(),
+        Some(shrink_wrap::Error::ValueOutOfBounds(13))
+    );
+    let buf = [0x00, 0x02, 0x10, 0b1111_1000];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert_eq!((x.a, x.b, x.c), (512, 16, Some(-8)));
+}
+
+#[test]
+fn bounded_u128_saturates() {
+    wire_weaver!(r#" struct Z { a: u128<{0..=10}> } "#);
+    let z = Z { a: u128::MAX };
+    let mut buf = [0u8; 32];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    assert_eq!(
+        z.ser_shrink_wrap(&mut wr),
+        Err(shrink_wrap::Error::ValueOutOfBounds(i128::MAX))
+    );
+    let buf = [0xFF; 16];
+    let mut rd = shrink_wrap::BufReader::new(&buf);
+    assert_eq!(
+        Z::des_shrink_wrap(&mut rd, ElementSize::Implied).err(),
+        Some(shrink_wrap::Error::ValueOutOfBounds(i128::MAX))
+    );
+}
+
+#[test]
+fn vec_of_generated_struct() {
+    wire_weaver!(r#" struct Y { a: u8, s: String } "#);
+    let items = [Y { a: 1, s: "ab" }, Y { a: 2, s: "" }];
+    let mut buf = [0u8; 64];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    wr.write(&shrink_wrap::RefVec::from(&items[..])).unwrap();
+    let buf = wr.finish().unwrap();
+
+    let mut rd = shrink_wrap::BufReader::new(buf);
+    let vec: shrink_wrap::RefVec<'_, Y> = rd.read(ElementSize::Implied).unwrap();
+    let mut iter = vec.des_iter().unwrap();
+    let y = iter.next().unwrap().unwrap();
+    assert_eq!((y.a, y.s), (1, "ab"));
+    let y = iter.next_back().unwrap().unwrap();
+    assert_eq!((y.a, y.s), (2, ""));
+    assert!(iter.next().is_none());
+}
+
+/// Enums nested in another item are prefixed with a size, whether they have data variants or not,
+/// and nested items that end mid-byte have their last byte counted in the size.
+/// Bytes are pinned, so that any further change to this layout is deliberate.
+#[test]
+fn wireformat_compatibility_nested_items() {
+    wire_weaver!(
+        r#"
+        #[repr(vlu16n)] enum E { A, B, C = 9 }
+        #[repr(vlu16n)] enum D { A { a: u8 } }
+        struct Y { z: u12 }
+        struct X { e: E, y: Y, d: D, a: u4 }
+    "#
+    );
+    let x = X {
+        e: E::C,
+        y: Y { z: 0x123 },
+        d: D::A { a: 0xAA },
+        a: 0x5,
+    };
+    let mut buf = [0u8; 64];
+    let mut wr = shrink_wrap::BufWriter::new(&mut buf);
+    x.ser_shrink_wrap(&mut wr).unwrap();
+    let buf = wr.finish().unwrap();
+    // E::C, Y in 2 bytes with the last one half used, D with its data, u4, sizes of D, Y and E
+    assert_eq!(buf, &[0x91, 0x12, 0x30, 0x10, 0xAA, 0x52, 0x21]);
+
+    let mut rd = shrink_wrap::BufReader::new(buf);
+    let x = X::des_shrink_wrap(&mut rd, ElementSize::Implied).unwrap();
+    assert!(matches!(x.e, E::C));
+    assert_eq!(x.y.z, 0x123);
+    assert!(matches!(x.d, D::A { a: 0xAA }));
+    assert_eq!(x.a, 0x5);
+}